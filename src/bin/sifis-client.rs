@@ -1,8 +1,32 @@
+use std::ffi::OsStr;
 use std::fmt::Write as _;
-
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::stream::{self, StreamExt};
+use reedline_repl_rs::clap::builder::{
+    PossibleValue, RangedI64ValueParser, StringValueParser, TypedValueParser,
+};
 use reedline_repl_rs::clap::{value_parser, Arg, ArgMatches, Command};
 use reedline_repl_rs::Repl;
-use sifis_api::Sifis;
+use sifis_api::service::ErrorCode;
+use sifis_api::{Clamped, Curtain, Door, Fridge, HomeEvent, KnownKind, Lamp, Sifis, Sink};
+
+/// How often a watched device is polled, if its changes are not all on the
+/// event stream
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the device count shown in the prompt is trusted
+const PROMPT_REFRESH: Duration = Duration::from_secs(10);
+
+/// Commands kept in the history file
+const HISTORY_CAPACITY: usize = 1000;
 
 #[derive(Debug, thiserror::Error)]
 enum CliError {
@@ -10,36 +34,141 @@ enum CliError {
     Sifis(#[from] sifis_api::Error),
     #[error(transparent)]
     Repl(#[from] reedline_repl_rs::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    InvalidArgument(String),
     #[error("Quit requested")]
     Quit,
 }
 
 type Result<T> = std::result::Result<T, CliError>;
 
+/// Required argument accepting only the values in `range`
+///
+/// The ranges are the ones the device wrappers define, e.g. [Lamp::BRIGHTNESS].
+fn ranged_arg<T>(name: &'static str, range: RangeInclusive<T>) -> Arg
+where
+    T: Copy + Into<i64> + TryFrom<i64> + Send + Sync + 'static,
+    <T as TryFrom<i64>>::Error: std::error::Error + Send + Sync + 'static,
+{
+    let (start, end) = range.into_inner();
+    let parser = RangedI64ValueParser::<T>::new().range(start.into()..=end.into());
+
+    Arg::new(name).value_parser(parser).required(true)
+}
+
+/// Devices offered as completions of the `id` arguments, by id and kind
+///
+/// Refreshed along with the device count of the prompt, empty while the
+/// runtime is unreachable.
+static KNOWN_DEVICES: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Parser of a device id, completing the known devices of kind `.0`, or of
+/// any kind
+///
+/// Any id is accepted, the runtime tells whether it exists.
+#[derive(Clone)]
+struct DeviceIdParser(Option<&'static str>);
+
+impl TypedValueParser for DeviceIdParser {
+    type Value = String;
+
+    fn parse_ref(
+        &self,
+        cmd: &Command,
+        arg: Option<&Arg>,
+        value: &OsStr,
+    ) -> std::result::Result<String, reedline_repl_rs::clap::Error> {
+        StringValueParser::new().parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        let ids: Vec<_> = KNOWN_DEVICES
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, kind)| self.0.is_none() || self.0 == Some(kind.as_str()))
+            .map(|(id, _)| PossibleValue::new(id.clone()))
+            .collect();
+
+        (!ids.is_empty()).then(|| Box::new(ids.into_iter()) as Box<dyn Iterator<Item = _>>)
+    }
+}
+
+/// Required device id argument, completing the ids of the devices of kind
+/// `kind`, or of any kind
+fn id_arg(kind: Option<&'static str>) -> Arg {
+    Arg::new("id")
+        .value_parser(DeviceIdParser(kind))
+        .hide_possible_values(true)
+        .required(true)
+}
+
 struct Ctx {
     sifis: Sifis,
+    /// Number of devices shown in the prompt, along with when it was counted
+    devices: Option<(usize, Instant)>,
 }
 
+impl Ctx {
+    fn new(sifis: Sifis) -> Ctx {
+        Ctx {
+            sifis,
+            devices: None,
+        }
+    }
+}
+
+/// Command implementation, shared by the REPL and the one-shot mode
+type Handler = for<'a> fn(
+    ArgMatches,
+    &'a mut Ctx,
+) -> Pin<Box<dyn Future<Output = Result<Option<String>>> + 'a>>;
+
 async fn list_lamps(_args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
-    let mut out = String::new();
+    Ok(Some(context.sifis.lamps_summary().await?))
+}
 
-    writeln!(out, "{:<15} {:<7} {:<5}", "Lamp id", "Status", "Brightness").unwrap();
-    for lamp in context.sifis.lamps().await? {
-        let on_off = if lamp.get_on_off().await? {
-            "On"
-        } else {
-            "Off"
-        };
-        let brightness = lamp.get_brightness().await?;
-        writeln!(out, "{:<15} {:<7} {:<5} ", lamp.id, on_off, brightness).unwrap();
+/// Prompt showing how many devices the runtime has, or that it is gone
+///
+/// The count, along with the [KNOWN_DEVICES], is refreshed every
+/// [PROMPT_REFRESH] at most.
+async fn prompt(context: &mut Ctx) -> String {
+    const DISCONNECTED: &str = "sifis[disconnected]";
+
+    if !context.sifis.is_connected() {
+        context.devices = None;
+        KNOWN_DEVICES.lock().unwrap().clear();
+        return DISCONNECTED.to_owned();
     }
 
-    Ok(Some(out))
+    let count = match context.devices {
+        Some((count, at)) if at.elapsed() < PROMPT_REFRESH => count,
+        _ => match context.sifis.devices().await {
+            Ok(devices) => {
+                context.devices = Some((devices.len(), Instant::now()));
+                *KNOWN_DEVICES.lock().unwrap() = devices
+                    .iter()
+                    .map(|device| (device.id.clone(), device.kind.clone()))
+                    .collect();
+                devices.len()
+            }
+            Err(_) => {
+                context.devices = None;
+                KNOWN_DEVICES.lock().unwrap().clear();
+                return DISCONNECTED.to_owned();
+            }
+        },
+    };
+
+    format!("sifis[{count} devices]")
 }
 
-async fn update_prompt(_context: &mut Ctx) -> Result<Option<String>> {
-    let msg = "Ok";
-    Ok(Some(msg.to_owned()))
+async fn update_prompt(context: &mut Ctx) -> Result<Option<String>> {
+    Ok(Some(prompt(context).await))
 }
 
 async fn light_on(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
@@ -62,47 +191,37 @@ async fn brightness(args: ArgMatches, context: &mut Ctx) -> Result<Option<String
     let id = args.get_one::<String>("id").unwrap();
     let brightness = args.get_one::<u8>("brightness").unwrap();
 
-    context
+    let r = context
         .sifis
         .lamp(id)
         .await?
         .set_brightness(*brightness)
         .await?;
 
-    Ok(None)
+    Ok(clamped_warning(id, "brightness", r))
 }
 
 async fn list_sinks(_args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
-    let mut out = String::new();
-
-    writeln!(
-        out,
-        "{:<15} {:<4} {:<11} {:<11}",
-        "Sink id", "Flow", "Water level", "Temperature"
-    )
-    .unwrap();
-    for sink in context.sifis.sinks().await? {
-        let flow = sink.get_flow().await?;
-        let water_level = sink.get_water_level().await?;
-        let temperature = sink.get_temperature().await?;
-        writeln!(
-            out,
-            "{:<15} {flow:<4} {water_level:<11} {temperature:<11}",
-            sink.id
-        )
-        .unwrap();
-    }
-
-    Ok(Some(out))
+    Ok(Some(context.sifis.sinks_summary().await?))
 }
 
 async fn set_sink_flow(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
     let id = args.get_one::<String>("id").unwrap();
     let flow = args.get_one::<u8>("flow").unwrap();
 
-    context.sifis.sink(id).await?.set_flow(*flow).await?;
+    let r = context.sifis.sink(id).await?.set_flow(*flow).await?;
 
-    Ok(None)
+    Ok(clamped_warning(id, "flow", r))
+}
+
+/// Warning about the `value` of `id` set lower than requested, if it was
+fn clamped_warning(id: &str, value: &str, r: Clamped<u8>) -> Option<String> {
+    r.was_clamped().then(|| {
+        format!(
+            "Warning: {id} {value} set to {} instead of {}",
+            r.applied, r.requested
+        )
+    })
 }
 
 async fn open_sink_drain(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
@@ -131,56 +250,67 @@ async fn set_sink_temperature(args: ArgMatches, context: &mut Ctx) -> Result<Opt
 }
 
 async fn list_doors(_args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
-    let mut out = String::new();
-
-    writeln!(
-        out,
-        "{:<15} {:<5} {:<11}",
-        "Door id", "Open?", "Lock status"
-    )
-    .unwrap();
-    for door in context.sifis.doors().await? {
-        let is_open = door.is_open().await?;
-        let lock_status = door.lock_status().await?;
-        writeln!(out, "{:<15} {is_open:<5} {lock_status:<11}", door.id).unwrap();
-    }
-
-    Ok(Some(out))
+    Ok(Some(context.sifis.doors_summary().await?))
 }
 
 async fn lock_door(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
     let id = args.get_one::<String>("id").unwrap();
 
-    context.sifis.door(id).await?.lock().await?;
+    let r = context.sifis.door(id).await?.lock().await?;
 
-    Ok(None)
+    Ok(Some(format!("{id}: {r}")))
 }
 
 async fn unlock_door(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
     let id = args.get_one::<String>("id").unwrap();
 
-    context.sifis.door(id).await?.unlock().await?;
+    let r = context.sifis.door(id).await?.unlock().await?;
+
+    Ok(Some(format!("{id}: {r}")))
+}
+
+async fn lock_all(_args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    let mut out = String::new();
+
+    writeln!(out, "{:<15} {:<11}", "Door id", "Lock status").unwrap();
+    for (id, locked) in context.sifis.lock_all_doors().await? {
+        let status = if locked { "locked" } else { "not locked" };
+        writeln!(out, "{id:<15} {status:<11}").unwrap();
+    }
+
+    Ok(Some(out))
+}
+
+async fn lockout(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    let id = args.get_one::<String>("id").unwrap();
+    let state = args.get_one::<String>("state").unwrap();
+
+    context.sifis.set_lockout(id, state == "on").await?;
 
     Ok(None)
 }
 
-async fn list_fridges(_args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+async fn history(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    let limit = args.get_one::<usize>("limit").unwrap();
     let mut out = String::new();
 
     writeln!(
         out,
-        "{:<15} {:<5} {:<11} {:<12}",
-        "Fridge id", "Open?", "Temperature", "Target Temp."
+        "{:<12} {:<15} {:<30} {:<8}",
+        "Age", "Device id", "Operation", "Pid"
     )
     .unwrap();
-    for fridge in context.sifis.fridges().await? {
-        let is_open = fridge.is_open().await?;
-        let temperature = fridge.temperature().await?;
-        let target_temperature = fridge.target_temperature().await?;
+    for event in context.sifis.recent_events(*limit).await? {
+        let age = event.timestamp.elapsed().unwrap_or_default();
+        let pid = event
+            .peer_pid
+            .map_or_else(|| "-".to_owned(), |pid| pid.to_string());
         writeln!(
             out,
-            "{:<15} {is_open:<5} {temperature:<11} {target_temperature:<12}",
-            fridge.id
+            "{:<12} {:<15} {:<30} {pid:<8}",
+            format!("{:.1}s ago", age.as_secs_f32()),
+            event.device_id,
+            event.operation
         )
         .unwrap();
     }
@@ -188,140 +318,599 @@ async fn list_fridges(_args: ArgMatches, context: &mut Ctx) -> Result<Option<Str
     Ok(Some(out))
 }
 
+async fn list_fridges(_args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    Ok(Some(context.sifis.fridges_summary().await?))
+}
+
+/// Convert to Celsius degrees, rounding to the nearest one
+fn fahrenheit_to_celsius(fahrenheit: i16) -> i16 {
+    ((f32::from(fahrenheit) - 32.0) * 5.0 / 9.0).round() as i16
+}
+
 async fn set_fridge_target_temperature(
     args: ArgMatches,
     context: &mut Ctx,
 ) -> Result<Option<String>> {
     let id = args.get_one::<String>("id").unwrap();
-    let temperature = args.get_one::<i8>("temperature").unwrap();
+    let value = *args.get_one::<i16>("temperature").unwrap();
+    let celsius = match args.get_one::<String>("unit").map(String::as_str) {
+        Some("f") => fahrenheit_to_celsius(value),
+        _ => value,
+    };
+
+    // Checked once converted, the parser does not know the unit
+    let range = context
+        .sifis
+        .property_range(id, "target_temperature")
+        .await?;
+    let temperature = i8::try_from(celsius)
+        .ok()
+        .filter(|&t| range.contains(t.into()))
+        .ok_or_else(|| {
+            CliError::InvalidArgument(format!(
+                "{celsius}°C is outside the {}..={}°C range",
+                range.min, range.max
+            ))
+        })?;
 
     context
         .sifis
         .fridge(id)
         .await?
-        .set_target_temperature(*temperature)
+        .set_target_temperature(temperature)
         .await?;
 
     Ok(None)
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let mut repl = Repl::new(Ctx {
-        sifis: Sifis::new().await?,
-    })
-    .with_name("Sifis developer API REPL")
-    .with_version("v0.1.0")
-    .with_command_async(
-        Command::new("list_lamps").about("List the available lamps"),
-        |args, context| Box::pin(list_lamps(args, context)),
-    )
-    .with_command_async(
-        Command::new("turn_light_on")
-            .arg(Arg::new("id").required(true))
-            .about("Turn the lamp on."),
-        |args, context| Box::pin(light_on(args, context)),
-    )
-    .with_command_async(
-        Command::new("turn_light_off")
-            .arg(Arg::new("id").required(true))
-            .about("Turn the lamp off."),
-        |args, context| Box::pin(light_off(args, context)),
-    )
-    .with_command_async(
-        Command::new("set_lamp_brightness")
-            .arg(Arg::new("id").required(true))
-            .arg(
-                Arg::new("brightness")
-                    .value_parser(value_parser!(u8).range(0..=100))
-                    .required(true),
-            )
-            .about("Set the lamp brightness"),
-        |args, context| Box::pin(brightness(args, context)),
-    )
-    .with_command_async(
-        Command::new("list_sinks").about("List the available sinks"),
-        |args, context| Box::pin(list_sinks(args, context)),
-    )
-    .with_command_async(
-        Command::new("set_sink_flow")
-            .arg(Arg::new("id").required(true))
-            .arg(
-                Arg::new("flow")
-                    .value_parser(value_parser!(u8).range(0..=100))
-                    .required(true),
-            )
-            .about("Set the flow of the sink."),
-        |args, context| Box::pin(set_sink_flow(args, context)),
-    )
-    .with_command_async(
-        Command::new("close_sink_drain")
-            .arg(Arg::new("id").required(true))
-            .about("Close the drain of the sink."),
-        |args, context| Box::pin(close_sink_drain(args, context)),
-    )
-    .with_command_async(
-        Command::new("open_sink_drain")
-            .arg(Arg::new("id").required(true))
-            .about("Open the drain of the sink."),
-        |args, context| Box::pin(open_sink_drain(args, context)),
-    )
-    .with_command_async(
-        Command::new("set_sink_temperature")
-            .arg(Arg::new("id").required(true))
-            .arg(
-                Arg::new("temperature")
-                    .value_parser(value_parser!(u8).range(10..=80))
-                    .required(true),
-            )
-            .about("Set the sink temperature"),
-        |args, context| Box::pin(set_sink_temperature(args, context)),
-    )
-    .with_command_async(
-        Command::new("list_doors").about("List the available doors"),
-        |args, context| Box::pin(list_doors(args, context)),
-    )
-    .with_command_async(
-        Command::new("lock_door")
-            .arg(Arg::new("id").required(true))
-            .about("Lock the door"),
-        |args, context| Box::pin(lock_door(args, context)),
-    )
-    .with_command_async(
-        Command::new("unlock_door")
-            .arg(Arg::new("id").required(true))
-            .about("Unlock the door"),
-        |args, context| Box::pin(unlock_door(args, context)),
-    )
-    .with_command_async(
-        Command::new("list_fridges").about("List the available fridges"),
-        |args, context| Box::pin(list_fridges(args, context)),
-    )
-    .with_command_async(
-        Command::new("set_fridge_target_temperature")
-            .arg(Arg::new("id").required(true))
-            .arg(
-                Arg::new("temperature")
-                    .value_parser(value_parser!(i8).range(-20..=20))
-                    .required(true),
-            )
-            .about("Set the fridge target temperature"),
-        |args, context| Box::pin(set_fridge_target_temperature(args, context)),
+async fn list_curtains(_args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    Ok(Some(context.sifis.curtains_summary().await?))
+}
+
+async fn open_curtain(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    let id = args.get_one::<String>("id").unwrap();
+
+    context.sifis.curtain(id).await?.open().await?;
+
+    Ok(None)
+}
+
+async fn close_curtain(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    let id = args.get_one::<String>("id").unwrap();
+
+    context.sifis.curtain(id).await?.close().await?;
+
+    Ok(None)
+}
+
+/// A device being watched
+enum Watched {
+    Lamp(Lamp),
+    Sink(Sink),
+    Door(Door),
+    Fridge(Fridge),
+    Curtain(Curtain),
+}
+
+impl Watched {
+    async fn find(sifis: &Sifis, id: &str) -> Result<Watched> {
+        let kind = sifis.device_kind(id).await?;
+        let known = KnownKind::ALL.into_iter().find(|k| k.display() == kind);
+
+        let watched = match known {
+            Some(KnownKind::Lamp) => Watched::Lamp(sifis.lamp_unchecked(id)),
+            Some(KnownKind::Sink) => Watched::Sink(sifis.sink_unchecked(id)),
+            Some(KnownKind::Door) => Watched::Door(sifis.door_unchecked(id)),
+            Some(KnownKind::Fridge) => Watched::Fridge(sifis.fridge_unchecked(id)),
+            Some(KnownKind::Curtain) => Watched::Curtain(sifis.curtain_unchecked(id)),
+            None => {
+                return Err(CliError::InvalidArgument(format!(
+                    "{id} is a {kind}, unknown to this client"
+                )))
+            }
+        };
+
+        Ok(watched)
+    }
+
+    /// Whether its state changes on its own, e.g. a fridge cooling down,
+    /// without any change on the event stream
+    fn drifts(&self) -> bool {
+        matches!(self, Watched::Fridge(_))
+    }
+
+    async fn state(&self) -> std::result::Result<String, sifis_api::Error> {
+        let state = match self {
+            Watched::Lamp(lamp) => format!(
+                "on: {} brightness: {}",
+                lamp.get_on_off().await?,
+                lamp.get_brightness().await?
+            ),
+            Watched::Sink(sink) => format!(
+                "flow: {} water level: {} temperature: {}",
+                sink.get_flow().await?,
+                sink.get_water_level().await?,
+                sink.get_temperature().await?
+            ),
+            Watched::Door(door) => format!(
+                "open: {} lock status: {}",
+                door.is_open().await?,
+                door.lock_status().await?
+            ),
+            Watched::Fridge(fridge) => format!(
+                "open: {} temperature: {} target temperature: {}",
+                fridge.is_open().await?,
+                fridge.temperature().await?,
+                fridge.target_temperature().await?
+            ),
+            Watched::Curtain(curtain) => format!("open: {}", curtain.is_open().await?),
+        };
+
+        Ok(state)
+    }
+}
+
+async fn watch(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    let id = args.get_one::<String>("id").unwrap();
+
+    let device = Watched::find(&context.sifis, id).await?;
+    // An older runtime has no event stream, the device is polled instead
+    let (mut events, poll) = match context.sifis.events().await {
+        Ok(events) => (events.boxed(), device.drifts()),
+        Err(e) if e.code() == Some(ErrorCode::Unsupported) => (stream::pending().boxed(), true),
+        Err(e) => return Err(e.into()),
+    };
+    let mut last = None;
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        match device.state().await {
+            Ok(state) => {
+                if last.as_ref() != Some(&state) {
+                    println!("{id} {state}");
+                    last = Some(state);
+                }
+            }
+            Err(e) if e.code() == Some(ErrorCode::NotFound) => {
+                return Ok(Some(format!("{id} is no longer available")));
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        // Read the state again once the device changes
+        loop {
+            tokio::select! {
+                _ = &mut ctrl_c => return Ok(None),
+                _ = interval.tick(), if poll => break,
+                event = events.next() => match event {
+                    Some(HomeEvent::Changed(event)) if event.device_id == *id => break,
+                    Some(HomeEvent::Removed { id: removed }) if removed == *id => break,
+                    // The change of the device may be among the missed ones
+                    Some(HomeEvent::Lagged(_)) => break,
+                    Some(_) => {}
+                    None => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::NotConnected,
+                            "The runtime closed the event stream",
+                        )
+                        .into())
+                    }
+                },
+            }
+        }
+    }
+}
+
+async fn list_room(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    let room = args.get_one::<String>("name").unwrap();
+    let mut out = String::new();
+
+    writeln!(out, "{:<15} {:<8} Name", "Device id", "Kind").unwrap();
+    for device in context.sifis.devices_in_room(room).await? {
+        writeln!(out, "{:<15} {:<8} {}", device.id, device.kind, device.name).unwrap();
+    }
+
+    Ok(Some(out))
+}
+
+async fn list_rooms(_args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    Ok(Some(context.sifis.rooms().await?.join("\n")))
+}
+
+async fn summary(_args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    Ok(Some(context.sifis.summary().await?))
+}
+
+async fn all_off(_args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    let mut out = String::new();
+
+    writeln!(out, "{:<15} {:<7}", "Device id", "Result").unwrap();
+    for (id, res) in context.sifis.all_off().await? {
+        match res {
+            Ok(()) => writeln!(out, "{id:<15} Ok"),
+            Err(e) => writeln!(out, "{id:<15} {e}"),
+        }
+        .unwrap();
+    }
+
+    Ok(Some(out))
+}
+
+async fn export(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    let path = args.get_one::<PathBuf>("file").unwrap();
+    let snapshot = context.sifis.snapshot().await?;
+    let count = snapshot.lamps.len()
+        + snapshot.sinks.len()
+        + snapshot.doors.len()
+        + snapshot.fridges.len()
+        + snapshot.curtains.len();
+
+    std::fs::write(path, serde_json::to_string_pretty(&snapshot)?)?;
+
+    Ok(Some(format!(
+        "Saved the state of {count} devices to {}",
+        path.display()
+    )))
+}
+
+async fn import(args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    let path = args.get_one::<PathBuf>("file").unwrap();
+    let snapshot = serde_json::from_slice(&std::fs::read(path)?)?;
+    let mut out = String::new();
+
+    writeln!(out, "{:<15} {:<7}", "Device id", "Result").unwrap();
+    for (id, res) in context.sifis.restore_snapshot(snapshot).await? {
+        match res {
+            Ok(()) => writeln!(out, "{id:<15} Ok"),
+            Err(e) => writeln!(out, "{id:<15} {e}"),
+        }
+        .unwrap();
+    }
+
+    Ok(Some(out))
+}
+
+async fn health(_args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "{:<15} {:<8} {:<9} Detail",
+        "Device id", "Kind", "Status"
     )
-    .with_command(
-        Command::new("quit").about("Quit the repl"),
-        |_, _context| Err(CliError::Quit),
+    .unwrap();
+    for device in context.sifis.scan_health().await? {
+        writeln!(
+            out,
+            "{:<15} {:<8} {:<9} {}",
+            device.id,
+            device.kind,
+            format!("{:?}", device.status),
+            device.detail.unwrap_or_default()
+        )
+        .unwrap();
+    }
+
+    Ok(Some(out))
+}
+
+async fn maintenance(_args: ArgMatches, context: &mut Ctx) -> Result<Option<String>> {
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "{:<15} {:<8} {:<8} Replacement",
+        "Device id", "Kind", "Battery"
     )
-    .with_stop_on_ctrl_c(true)
-    .with_on_after_command_async(|context| Box::pin(update_prompt(context)))
-    .with_error_handler(|e, _context| {
-        if matches!(e, CliError::Quit) {
-            Err(reedline_repl_rs::Error::UnknownCommand("quit".to_string()))
-        } else {
-            eprintln!("{}", e);
-            Ok(())
+    .unwrap();
+    for device in context.sifis.devices().await? {
+        let status = context.sifis.maintenance_status(&device.id).await?;
+        if !status.needs_attention() {
+            continue;
         }
-    });
+        writeln!(
+            out,
+            "{:<15} {:<8} {:<8} {}",
+            device.id,
+            device.kind,
+            status
+                .battery
+                .map_or_else(|| "-".to_owned(), |battery| format!("{battery}%")),
+            if status.replacement_due { "due" } else { "-" }
+        )
+        .unwrap();
+    }
+
+    Ok(Some(out))
+}
+
+/// Pair a command with its implementation
+fn command(command: Command, handler: Handler) -> (Command, Handler) {
+    (command, handler)
+}
+
+/// Every command along with its implementation
+fn commands() -> Vec<(Command, Handler)> {
+    vec![
+        command(
+            Command::new("list_lamps").about("List the available lamps"),
+            |args, context| Box::pin(list_lamps(args, context)),
+        ),
+        command(
+            Command::new("turn_light_on")
+                .arg(id_arg(Some("Lamp")))
+                .about("Turn the lamp on."),
+            |args, context| Box::pin(light_on(args, context)),
+        ),
+        command(
+            Command::new("turn_light_off")
+                .arg(id_arg(Some("Lamp")))
+                .about("Turn the lamp off."),
+            |args, context| Box::pin(light_off(args, context)),
+        ),
+        command(
+            Command::new("set_lamp_brightness")
+                .arg(id_arg(Some("Lamp")))
+                .arg(ranged_arg("brightness", Lamp::BRIGHTNESS))
+                .about("Set the lamp brightness"),
+            |args, context| Box::pin(brightness(args, context)),
+        ),
+        command(
+            Command::new("list_sinks").about("List the available sinks"),
+            |args, context| Box::pin(list_sinks(args, context)),
+        ),
+        command(
+            Command::new("set_sink_flow")
+                .arg(id_arg(Some("Sink")))
+                .arg(ranged_arg("flow", Sink::FLOW))
+                .about("Set the flow of the sink."),
+            |args, context| Box::pin(set_sink_flow(args, context)),
+        ),
+        command(
+            Command::new("close_sink_drain")
+                .arg(id_arg(Some("Sink")))
+                .about("Close the drain of the sink."),
+            |args, context| Box::pin(close_sink_drain(args, context)),
+        ),
+        command(
+            Command::new("open_sink_drain")
+                .arg(id_arg(Some("Sink")))
+                .about("Open the drain of the sink."),
+            |args, context| Box::pin(open_sink_drain(args, context)),
+        ),
+        command(
+            Command::new("set_sink_temperature")
+                .arg(id_arg(Some("Sink")))
+                .arg(ranged_arg("temperature", Sink::TEMPERATURE))
+                .about("Set the sink temperature"),
+            |args, context| Box::pin(set_sink_temperature(args, context)),
+        ),
+        command(
+            Command::new("list_doors").about("List the available doors"),
+            |args, context| Box::pin(list_doors(args, context)),
+        ),
+        command(
+            Command::new("lock_door")
+                .arg(id_arg(Some("Door")))
+                .about("Lock the door"),
+            |args, context| Box::pin(lock_door(args, context)),
+        ),
+        command(
+            Command::new("unlock_door")
+                .arg(id_arg(Some("Door")))
+                .about("Unlock the door"),
+            |args, context| Box::pin(unlock_door(args, context)),
+        ),
+        command(
+            Command::new("lock_all").about("Lock every door"),
+            |args, context| Box::pin(lock_all(args, context)),
+        ),
+        command(
+            Command::new("list_fridges").about("List the available fridges"),
+            |args, context| Box::pin(list_fridges(args, context)),
+        ),
+        command(
+            Command::new("set_fridge_target_temperature")
+                .arg(id_arg(Some("Fridge")))
+                .arg(
+                    Arg::new("temperature")
+                        .value_parser(value_parser!(i16))
+                        .allow_negative_numbers(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("unit")
+                        .long("unit")
+                        .value_parser(["c", "f"])
+                        .default_value("c")
+                        .help("Celsius or Fahrenheit degrees"),
+                )
+                .about("Set the fridge target temperature"),
+            |args, context| Box::pin(set_fridge_target_temperature(args, context)),
+        ),
+        command(
+            Command::new("list_curtains").about("List the available curtains"),
+            |args, context| Box::pin(list_curtains(args, context)),
+        ),
+        command(
+            Command::new("open_curtain")
+                .arg(id_arg(Some("Curtain")))
+                .about("Open the curtain"),
+            |args, context| Box::pin(open_curtain(args, context)),
+        ),
+        command(
+            Command::new("close_curtain")
+                .arg(id_arg(Some("Curtain")))
+                .about("Close the curtain"),
+            |args, context| Box::pin(close_curtain(args, context)),
+        ),
+        command(
+            Command::new("watch")
+                .arg(id_arg(None))
+                .about("Print the device state as it changes, until Ctrl-C"),
+            |args, context| Box::pin(watch(args, context)),
+        ),
+        command(
+            Command::new("history")
+                .arg(
+                    Arg::new("limit")
+                        .value_parser(value_parser!(usize))
+                        .default_value("20"),
+                )
+                .about("Show the most recent device changes"),
+            |args, context| Box::pin(history(args, context)),
+        ),
+        command(
+            Command::new("list_rooms").about("List the rooms holding a device"),
+            |args, context| Box::pin(list_rooms(args, context)),
+        ),
+        command(
+            Command::new("list_room")
+                .arg(Arg::new("name").required(true))
+                .about("List the devices in the room"),
+            |args, context| Box::pin(list_room(args, context)),
+        ),
+        command(
+            Command::new("summary").about("Show the state of every device"),
+            |args, context| Box::pin(summary(args, context)),
+        ),
+        command(
+            Command::new("all_off").about("Turn off every lamp and stop every sink"),
+            |args, context| Box::pin(all_off(args, context)),
+        ),
+        command(
+            Command::new("export")
+                .arg(
+                    Arg::new("file")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                )
+                .about("Save the state of every device to a JSON file"),
+            |args, context| Box::pin(export(args, context)),
+        ),
+        command(
+            Command::new("import")
+                .arg(
+                    Arg::new("file")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                )
+                .about("Restore the state of the devices saved by export"),
+            |args, context| Box::pin(import(args, context)),
+        ),
+        command(
+            Command::new("health").about("Probe every device and report its health"),
+            |args, context| Box::pin(health(args, context)),
+        ),
+        command(
+            Command::new("maintenance")
+                .about("List the devices with a low battery or a part due for replacement"),
+            |args, context| Box::pin(maintenance(args, context)),
+        ),
+        command(
+            Command::new("lockout")
+                .arg(id_arg(None))
+                .arg(Arg::new("state").value_parser(["on", "off"]).required(true))
+                .about(
+                    "Refuse the commands to the device during maintenance, or accept them again",
+                ),
+            |args, context| Box::pin(lockout(args, context)),
+        ),
+    ]
+}
+
+/// Run the command given on the command line and exit, for scripts
+///
+/// Same as typing it in the REPL, the exit status tells whether it failed.
+async fn run_once() -> Result<ExitCode> {
+    let commands = commands();
+    let matches = Command::new("sifis-client")
+        .about("Run a single command, start the REPL if none is given")
+        .subcommand_required(true)
+        .subcommands(commands.iter().map(|(command, _)| command.clone()))
+        .get_matches();
+    let (name, args) = matches.subcommand().expect("a command is required");
+    let (_, handler) = commands
+        .iter()
+        .find(|(command, _)| command.get_name() == name)
+        .expect("the command is known to the parser");
+
+    let mut context = Ctx::new(Sifis::new().await?);
+    match handler(args.clone(), &mut context).await {
+        Ok(out) => {
+            if let Some(out) = out {
+                println!("{out}");
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// File keeping the REPL history across sessions, `SIFIS_HISTORY` or one in
+/// the data directory of the user
+///
+/// None if it cannot be written, the history is then kept in memory.
+fn history_path() -> Option<PathBuf> {
+    let path = match std::env::var_os("SIFIS_HISTORY") {
+        Some(path) => PathBuf::from(path),
+        None => directories::ProjectDirs::from("", "", "sifis-client")?
+            .data_dir()
+            .join("history"),
+    };
+
+    let writable = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| OpenOptions::new().create(true).append(true).open(&path));
+    match writable {
+        Ok(_) => Some(path),
+        Err(e) => {
+            eprintln!("Not saving the history to {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    if std::env::args_os().len() > 1 {
+        return run_once().await;
+    }
+
+    let mut context = Ctx::new(Sifis::new().await?);
+    let initial_prompt = prompt(&mut context).await;
+
+    let mut repl = Repl::new(context)
+        .with_name("Sifis developer API REPL")
+        .with_prompt(&initial_prompt)
+        .with_version("v0.1.0");
+    if let Some(path) = history_path() {
+        repl = repl.with_history(path, HISTORY_CAPACITY);
+    }
+    for (command, handler) in commands() {
+        repl = repl.with_command_async(command, handler);
+    }
+    let mut repl = repl
+        .with_command(
+            Command::new("quit").about("Quit the repl"),
+            |_, _context| Err(CliError::Quit),
+        )
+        .with_stop_on_ctrl_c(true)
+        .with_on_after_command_async(|context| Box::pin(update_prompt(context)))
+        .with_error_handler(|e, _context| {
+            if matches!(e, CliError::Quit) {
+                Err(reedline_repl_rs::Error::UnknownCommand("quit".to_string()))
+            } else {
+                eprintln!("{}", e);
+                Ok(())
+            }
+        });
 
     use reedline_repl_rs::Error;
     match repl.run_async().await {
@@ -336,5 +925,5 @@ async fn main() -> Result<()> {
         Err(e) => Err(e),
     }?;
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
@@ -2,426 +2,359 @@
 //!
 //! It simulates a number of devices
 
+use clap::{value_parser, Arg, Command};
 use futures::{future, prelude::*};
 use libproc::libproc::proc_pid::pidpath;
 use serde::{Deserialize, Serialize};
-use sifis_api::runtime::peer_pid;
+use serde_json::Value;
+use sifis_api::runtime::{peer_pid, InMemoryBackend};
 use std::collections::HashMap;
 use std::os::fd::AsFd;
-use std::path::Path;
-use std::sync::Arc;
-use tarpc::context::Context;
-use tarpc::server::{self, Channel};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tarpc::server::{self, incoming::Incoming, Channel, Serve};
 use tarpc::tokio_serde::formats::Bincode;
 use tokio::fs::read_to_string;
-use tokio::sync::Mutex;
-use tracing::info;
+use tracing::{info, warn};
 
-use sifis_api::{service::*, DoorLockStatus};
+use sifis_api::{service::*, CancellationToken, DeviceSpec, DEFAULT_MAX_FRAME_LENGTH};
 
-#[derive(Default, Clone, Debug, Serialize, Deserialize)]
-struct LampState {
-    brightness: u8,
-    on: bool,
+/// Clients served at once, unless `SIFIS_MAX_CONCURRENT` is set
+const DEFAULT_MAX_CONCURRENT: usize = 10;
+
+/// Connections a single process may keep open, unless
+/// `SIFIS_MAX_CHANNELS_PER_CLIENT` is set
+const DEFAULT_MAX_CHANNELS_PER_CLIENT: u32 = 8;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SifisConf {
+    /// Most power the circuit can draw, in watts, unlimited if unset
+    breaker_watts: Option<f32>,
+    /// Shortest interval between two commands to the same device, in
+    /// milliseconds, unlimited if unset
+    min_command_interval_ms: Option<u64>,
+    devices: HashMap<String, DeviceSpec>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct SinkState {
-    flow: u8,
-    temp: u8,
-    level: u8,
-    drain: bool,
+/// Parse an environment variable, ignoring it if unset or malformed
+fn env_var<T: FromStr>(key: &str) -> Option<T> {
+    let value = std::env::var(key).ok()?;
+    value
+        .parse()
+        .map_err(|_| warn!("Ignoring malformed {key}={value}"))
+        .ok()
 }
 
-impl Default for SinkState {
-    #[inline]
-    fn default() -> Self {
-        Self {
-            flow: 0,
-            temp: 20,
-            level: 0,
-            drain: true,
-        }
-    }
+/// Permissions of the socket, from the octal `SIFIS_SOCKET_MODE`, e.g. `0660`
+fn socket_mode_from_env() -> Option<u32> {
+    let value = std::env::var("SIFIS_SOCKET_MODE").ok()?;
+    u32::from_str_radix(&value, 8)
+        .ok()
+        .filter(|&mode| mode <= 0o777)
+        .or_else(|| {
+            warn!("Ignoring malformed SIFIS_SOCKET_MODE={value}");
+            None
+        })
 }
 
-#[derive(Default, Clone, Debug, Serialize, Deserialize)]
-struct DoorState {
-    is_open: bool,
-    lock: DoorLockStatus,
+/// Command line, the options override the environment variables
+fn cli() -> Command {
+    Command::new("sifis-runtime-mock")
+        .about("Mock sifis runtime")
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .help("Devices configuration [default: sifis-runtime.toml if present]"),
+        )
+        .arg(
+            Arg::new("socket")
+                .long("socket")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .help("Unix socket to listen on [default: SIFIS_SERVER or /var/run/sifis.sock]"),
+        )
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct FridgeState {
-    open: bool,
-    temperature: i8,
-    target_temperature: i8,
+/// Set a field of the state of a device, `entry` is `id.field=value`
+///
+/// The field may be a dotted path, the value is JSON or else a string.
+fn apply_override(devices: &mut HashMap<String, DeviceSpec>, entry: &str) -> Result<(), String> {
+    let (path, value) = entry.split_once('=').ok_or("missing `=`")?;
+    let (id, path) = path.trim().split_once('.').ok_or("missing field")?;
+    let value = value.trim();
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()));
+
+    let spec = devices
+        .get_mut(id)
+        .ok_or_else(|| format!("no device {id}"))?;
+    let mut kind = serde_json::to_value(&spec.kind).map_err(|e| e.to_string())?;
+
+    // The kind is externally tagged, its only value is the state
+    let mut field = kind
+        .as_object_mut()
+        .and_then(|kind| kind.values_mut().next())
+        .ok_or("no state")?;
+    for key in path.split('.') {
+        field = field
+            .get_mut(key)
+            .ok_or_else(|| format!("no field {key}"))?;
+    }
+    *field = value;
+
+    spec.kind = serde_json::from_value(kind).map_err(|e| e.to_string())?;
+
+    Ok(())
 }
 
-impl Default for FridgeState {
-    fn default() -> Self {
-        Self {
-            open: false,
-            temperature: 5,
-            target_temperature: 4,
+/// Apply the comma separated overrides of `SIFIS_INIT`, ignoring the
+/// malformed ones
+fn overrides_from_env(devices: &mut HashMap<String, DeviceSpec>) {
+    let Ok(overrides) = std::env::var("SIFIS_INIT") else {
+        return;
+    };
+
+    for entry in overrides
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+    {
+        match apply_override(devices, entry) {
+            Ok(()) => info!("Initial state override {entry}"),
+            Err(e) => warn!("Ignoring the initial state override {entry}: {e}"),
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-enum DeviceKind {
-    Lamp(LampState),
-    Sink(SinkState),
-    Door(DoorState),
-    Fridge(FridgeState),
-}
+/// Keep the devices from starting in a hazardous state if `SIFIS_SAFE_START`
+/// is set: `1` starts them in their safe state, `refuse` refuses to start
+fn safe_start_from_env(devices: &mut HashMap<String, DeviceSpec>) -> Result<(), String> {
+    let Ok(mode) = std::env::var("SIFIS_SAFE_START") else {
+        return Ok(());
+    };
+    let refuse = match mode.as_str() {
+        "1" => false,
+        "refuse" => true,
+        _ => {
+            warn!("Ignoring malformed SIFIS_SAFE_START={mode}");
+            return Ok(());
+        }
+    };
 
-impl DeviceKind {
-    pub fn display(&self) -> &str {
-        match self {
-            DeviceKind::Lamp(_) => "Lamp",
-            DeviceKind::Sink(_) => "Sink",
-            DeviceKind::Door(_) => "Door",
-            DeviceKind::Fridge(_) => "Fridge",
+    for (id, spec) in devices {
+        let hazards = InMemoryBackend::safe_start(&mut spec.kind);
+        if hazards.is_empty() {
+            continue;
         }
+        if refuse {
+            return Err(format!("{id} would start with the hazards {hazards:?}"));
+        }
+        warn!("{id} would start with the hazards {hazards:?}, starting it in its safe state");
     }
-}
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct Device {
-    name: String,
-    kind: DeviceKind,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct SifisConf {
-    devices: HashMap<String, Device>,
+    Ok(())
 }
 
-#[derive(Clone, Debug)]
-struct SifisMock {
-    devices: Arc<Mutex<HashMap<String, Device>>>,
+/// Requests of a connection, to close it once idle
+struct Activity {
+    /// Requests being served
+    in_flight: AtomicUsize,
+    /// When the last request ended, or the connection opened
+    last: Mutex<Instant>,
 }
 
-impl SifisMock {
-    async fn apply<F, R>(&self, id: &str, f: F) -> Result<R, Error>
-    where
-        F: FnOnce(&mut Device) -> Result<R, Error>,
-    {
-        let mut devs = self.devices.lock().await;
-
-        let d = devs
-            .get_mut(id)
-            .ok_or_else(|| Error::NotFound(id.to_owned()))?;
-
-        f(d)
-    }
-    async fn apply_lamp<F, R>(&self, id: &str, f: F) -> Result<R, Error>
-    where
-        F: FnOnce(&mut LampState) -> Result<R, Error>,
-    {
-        self.apply(id, |d| match d.kind {
-            DeviceKind::Lamp(ref mut lamp) => f(lamp),
-            _ => Err(Error::Mismatch {
-                found: d.kind.display().to_string(),
-                req: "Lamp".to_string(),
-            }),
+impl Activity {
+    fn new() -> Arc<Activity> {
+        Arc::new(Activity {
+            in_flight: AtomicUsize::new(0),
+            last: Mutex::new(Instant::now()),
         })
-        .await
     }
-    async fn apply_sink<F, R>(&self, id: &str, f: F) -> Result<R, Error>
-    where
-        F: FnOnce(&mut SinkState) -> Result<R, Error>,
-    {
-        self.apply(id, |d| match d.kind {
-            DeviceKind::Sink(ref mut sink) => f(sink),
-            _ => Err(Error::Mismatch {
-                found: d.kind.display().to_string(),
-                req: "Sink".to_string(),
-            }),
-        })
-        .await
-    }
-    async fn apply_door<F, R>(&self, id: &str, f: F) -> Result<R, Error>
-    where
-        F: FnOnce(&mut DoorState) -> Result<R, Error>,
-    {
-        self.apply(id, |d| match d.kind {
-            DeviceKind::Door(ref mut door) => f(door),
-            _ => Err(Error::Mismatch {
-                found: d.kind.display().to_string(),
-                req: "Door".to_string(),
-            }),
-        })
-        .await
+
+    /// How long the connection has been without a request to serve
+    fn idle(&self) -> Duration {
+        if self.in_flight.load(Ordering::SeqCst) > 0 {
+            return Duration::ZERO;
+        }
+        self.last.lock().unwrap().elapsed()
     }
-    async fn apply_fridge<F, R>(&self, id: &str, f: F) -> Result<R, Error>
-    where
-        F: FnOnce(&mut FridgeState) -> Result<R, Error>,
-    {
-        self.apply(id, |d| match d.kind {
-            DeviceKind::Fridge(ref mut fridge) => f(fridge),
-            _ => Err(Error::Mismatch {
-                found: d.kind.display().to_string(),
-                req: "Fridge".to_string(),
-            }),
-        })
-        .await
+
+    /// Wait for the connection to be idle for `timeout`
+    async fn idle_for(&self, timeout: Duration) {
+        loop {
+            let idle = self.idle();
+            if idle >= timeout {
+                return;
+            }
+            tokio::time::sleep(timeout - idle).await;
+        }
     }
 }
 
-#[tarpc::server]
-impl SifisApi for SifisMock {
-    async fn find_lamps(self, _: Context) -> Result<Vec<String>, Error> {
-        let res = self
-            .devices
-            .lock()
-            .await
-            .iter()
-            .filter_map(|(id, dev)| match dev.kind {
-                DeviceKind::Lamp { .. } => Some(id.clone()),
-                _ => None,
-            })
-            .collect();
-
-        Ok(res)
-    }
+/// Request counted in its [Activity] until dropped
+struct Busy(Arc<Activity>);
 
-    async fn find_sinks(self, _: Context) -> Result<Vec<String>, Error> {
-        let res = self
-            .devices
-            .lock()
-            .await
-            .iter()
-            .filter_map(|(id, dev)| match dev.kind {
-                DeviceKind::Sink { .. } => Some(id.clone()),
-                _ => None,
-            })
-            .collect();
-
-        Ok(res)
+impl Busy {
+    fn new(activity: Arc<Activity>) -> Busy {
+        activity.in_flight.fetch_add(1, Ordering::SeqCst);
+        Busy(activity)
     }
+}
 
-    // Lamp-specific API
-    async fn turn_lamp_on(self, _: Context, id: String) -> Result<bool, Error> {
-        self.apply_lamp(&id, |l| {
-            tracing::info!("Setting lamp {id} on property to true from {}", l.on);
-            l.on = true;
-            Ok(true)
-        })
-        .await
-    }
-    async fn turn_lamp_off(self, _: Context, id: String) -> Result<bool, Error> {
-        self.apply_lamp(&id, |l| {
-            tracing::info!("Setting lamp {id} on property to false from {}", l.on);
-            l.on = false;
-            Ok(false)
-        })
-        .await
-    }
-    async fn get_lamp_on_off(self, _: Context, id: String) -> Result<bool, Error> {
-        self.apply_lamp(&id, |l| Ok(l.on)).await
-    }
-    async fn set_lamp_brightness(
-        self,
-        _: Context,
-        id: String,
-        brightness: u8,
-    ) -> Result<u8, Error> {
-        self.apply_lamp(&id, |l: &mut LampState| {
-            tracing::info!(
-                "Setting lamp {id} brightness to {brightness} from {}",
-                l.brightness,
-            );
-            l.brightness = brightness;
-            Ok(brightness)
-        })
-        .await
-    }
-    async fn get_lamp_brightness(self, _: Context, id: String) -> Result<u8, Error> {
-        self.apply_lamp(&id, |l: &mut LampState| Ok(l.brightness))
-            .await
+impl Drop for Busy {
+    fn drop(&mut self) {
+        *self.0.last.lock().unwrap() = Instant::now();
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
     }
+}
 
-    // Sink-specific API
-    async fn set_sink_flow(self, _: Context, id: String, flow: u8) -> Result<u8, Error> {
-        self.apply_sink(&id, |s: &mut SinkState| {
-            s.flow = flow;
-            Ok(flow)
-        })
-        .await
-    }
-    async fn get_sink_flow(self, _: Context, id: String) -> Result<u8, Error> {
-        self.apply_sink(&id, |s: &mut SinkState| Ok(s.flow)).await
-    }
-    async fn set_sink_temp(self, _: Context, id: String, temp: u8) -> Result<u8, Error> {
-        self.apply_sink(&id, |s: &mut SinkState| {
-            s.temp = temp;
-            Ok(temp)
-        })
-        .await
-    }
-    async fn get_sink_temp(self, _: Context, id: String) -> Result<u8, Error> {
-        self.apply_sink(&id, |s: &mut SinkState| Ok(s.temp)).await
-    }
-    async fn close_sink_drain(self, _: Context, id: String) -> Result<bool, Error> {
-        self.apply_sink(&id, |s: &mut SinkState| {
-            s.drain = false;
-            Ok(false)
-        })
-        .await
-    }
-    async fn open_sink_drain(self, _: Context, id: String) -> Result<bool, Error> {
-        self.apply_sink(&id, |s: &mut SinkState| {
-            s.drain = true;
-            Ok(true)
-        })
-        .await
-    }
-    async fn get_sink_level(self, _: Context, id: String) -> Result<u8, Error> {
-        self.apply_sink(&id, |s: &mut SinkState| Ok(s.level)).await
-    }
+/// Serve the requests with `serve`, keeping track of them in `activity`
+fn tracked<S>(
+    serve: S,
+    activity: Arc<Activity>,
+) -> impl Serve<
+    SifisApiRequest,
+    Resp = SifisApiResponse,
+    Fut = impl Future<Output = SifisApiResponse> + Send,
+> + Clone
+       + Send
+       + 'static
+where
+    S: Serve<SifisApiRequest, Resp = SifisApiResponse> + Clone + Send + 'static,
+    S::Fut: Send,
+{
+    server::serve(move |ctx, req: SifisApiRequest| {
+        let serve = serve.clone();
+        let busy = Busy::new(activity.clone());
+        async move {
+            let response = serve.serve(ctx, req).await;
+            drop(busy);
+            response
+        }
+    })
+}
 
-    async fn find_doors(self, _: Context) -> Result<Vec<String>, Error> {
-        let res = self
-            .devices
-            .lock()
-            .await
-            .iter()
-            .filter_map(|(id, dev)| match dev.kind {
-                DeviceKind::Door { .. } => Some(id.clone()),
-                _ => None,
-            })
-            .collect();
-
-        Ok(res)
-    }
+/// Load the devices from `path`, or from `sifis-runtime.toml` if present
+async fn load_conf(path: Option<&PathBuf>) -> std::io::Result<InMemoryBackend> {
+    let conf_s = match path {
+        Some(path) => Some(read_to_string(path).await?),
+        None => read_to_string("sifis-runtime.toml").await.ok(),
+    };
+
+    let conf = match conf_s {
+        Some(conf_s) => toml::from_str(&conf_s).expect("Failed to load configuration"),
+        None => {
+            tracing::info!("Using the default configuration");
+            SifisConf {
+                breaker_watts: None,
+                min_command_interval_ms: None,
+                devices: InMemoryBackend::default_devices().into_iter().collect(),
+            }
+        }
+    };
 
-    async fn get_door_lock_status(self, _: Context, id: String) -> Result<DoorLockStatus, Error> {
-        self.apply_door(&id, |s: &mut DoorState| Ok(s.lock)).await
-    }
+    let mut devices = conf.devices;
+    overrides_from_env(&mut devices);
+    safe_start_from_env(&mut devices)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut backend = InMemoryBackend::new(devices);
+    tracing::debug!("{backend:#?}");
 
-    async fn get_door_open(self, _: Context, id: String) -> Result<bool, Error> {
-        self.apply_door(&id, |s: &mut DoorState| Ok(s.is_open))
-            .await
+    if let Some(watts) = conf.breaker_watts {
+        backend = backend.with_breaker(watts);
     }
-
-    async fn lock_door(self, _: Context, id: String) -> Result<bool, Error> {
-        self.apply_door(&id, |s: &mut DoorState| {
-            Ok(match s.lock {
-                DoorLockStatus::Locked => true,
-                DoorLockStatus::Unlocked => {
-                    s.lock = DoorLockStatus::Locked;
-                    true
-                }
-                DoorLockStatus::Jammed => false,
-            })
-        })
-        .await
+    if let Some(ms) = conf.min_command_interval_ms {
+        backend = backend.with_rate_limit(Duration::from_millis(ms));
     }
 
-    async fn unlock_door(self, _: Context, id: String) -> Result<bool, Error> {
-        self.apply_door(&id, |s: &mut DoorState| {
-            Ok(match s.lock {
-                DoorLockStatus::Unlocked => true,
-                DoorLockStatus::Locked => {
-                    s.lock = DoorLockStatus::Unlocked;
-                    true
-                }
-                DoorLockStatus::Jammed => false,
-            })
-        })
-        .await
-    }
+    Ok(backend)
+}
 
-    async fn find_fridges(self, _: Context) -> Result<Vec<String>, Error> {
-        let res = self
-            .devices
-            .lock()
-            .await
-            .iter()
-            .filter_map(|(id, dev)| match dev.kind {
-                DeviceKind::Fridge { .. } => Some(id.clone()),
-                _ => None,
-            })
-            .collect();
-
-        Ok(res)
-    }
+/// Configure the fault injection from `SIFIS_FAULT_RATE`,
+/// `SIFIS_FAULT_DELAY_MS`, `SIFIS_FAULT_DROP_RATE` and `SIFIS_SEED`
+fn faults_from_env(backend: InMemoryBackend) -> InMemoryBackend {
+    let rate = env_var::<f64>("SIFIS_FAULT_RATE").unwrap_or(0.0);
+    let delay = Duration::from_millis(env_var("SIFIS_FAULT_DELAY_MS").unwrap_or(0));
+    let drop_rate = env_var::<f64>("SIFIS_FAULT_DROP_RATE").unwrap_or(0.0);
 
-    async fn get_fridge_temperature(self, _: Context, id: String) -> Result<i8, Error> {
-        self.apply_fridge(&id, |s: &mut FridgeState| Ok(s.temperature))
-            .await
+    if rate == 0.0 && delay.is_zero() && drop_rate == 0.0 {
+        return backend;
     }
 
-    async fn get_fridge_target_temperature(self, _: Context, id: String) -> Result<i8, Error> {
-        self.apply_fridge(&id, |s: &mut FridgeState| Ok(s.target_temperature))
-            .await
-    }
+    info!("Injecting faults: rate {rate} delay {delay:?} drop rate {drop_rate}");
+    backend
+        .with_faults(rate, delay, env_var("SIFIS_SEED"))
+        .with_drop_rate(drop_rate)
+}
 
-    async fn set_fridge_target_temperature(
-        self,
-        _: Context,
-        id: String,
-        target_temperature: i8,
-    ) -> Result<i8, Error> {
-        self.apply_fridge(&id, |s: &mut FridgeState| {
-            s.target_temperature = target_temperature;
-            Ok(target_temperature)
-        })
-        .await
+/// Serve `channel` with `serve`, closing the connection once `hangup` is
+/// cancelled by a call dropping it on purpose
+async fn execute<C, S>(channel: C, serve: S, hangup: CancellationToken)
+where
+    C: Channel<Req = SifisApiRequest, Resp = SifisApiResponse> + Send + 'static,
+    S: Serve<SifisApiRequest, Resp = SifisApiResponse> + Clone + Send + 'static,
+    S::Fut: Send,
+{
+    // Dropping the channel closes the connection, before the answer to the
+    // call that dropped it is sent
+    tokio::select! {
+        biased;
+        _ = hangup.cancelled() => info!("Connection dropped on purpose"),
+        _ = channel.execute(serve) => {}
     }
+}
 
-    async fn get_fridge_open(self, _: Context, id: String) -> Result<bool, Error> {
-        self.apply_fridge(&id, |s: &mut FridgeState| Ok(s.open))
-            .await
+/// Start the simulation if `SIFIS_TICK_MS`, in simulated time, is set
+fn simulate_from_env(backend: &InMemoryBackend) {
+    if let Some(tick) = env_var::<u64>("SIFIS_TICK_MS").filter(|&tick| tick > 0) {
+        info!("Simulating every {tick}ms");
+        backend.simulate(Duration::from_millis(tick));
     }
 }
 
-async fn load_conf() -> SifisConf {
-    if let Ok(conf_s) = read_to_string("sifis-runtime.toml").await {
-        toml::from_str(&conf_s).expect("Failed to load configuration")
-    } else {
-        tracing::info!("Using the default configuration");
-        let mut devices = HashMap::new();
-        devices.insert(
-            "lamp1".to_owned(),
-            Device {
-                name: "Safe lamp".to_owned(),
-                kind: DeviceKind::Lamp(LampState::default()),
-            },
-        );
-        devices.insert(
-            "lamp2".to_owned(),
-            Device {
-                name: "Unsafe lamp".to_owned(),
-                kind: DeviceKind::Lamp(LampState::default()),
-            },
-        );
-        devices.insert(
-            "sink1".to_owned(),
-            Device {
-                name: "Kitchen Sink".to_owned(),
-                kind: DeviceKind::Sink(SinkState::default()),
-            },
-        );
-        devices.insert(
-            "door1".to_owned(),
-            Device {
-                name: "Bedroom Door".to_owned(),
-                kind: DeviceKind::Door(DoorState::default()),
-            },
-        );
-        devices.insert(
-            "fridge1".to_owned(),
-            Device {
-                name: "Kitchen Fridge".to_owned(),
-                kind: DeviceKind::Fridge(FridgeState::default()),
-            },
-        );
-
-        tracing::debug!("{devices:#?}");
-        SifisConf { devices }
+/// Serve over TLS on `addr` using the `SIFIS_TLS_CERT` certificate and the
+/// `SIFIS_TLS_KEY` key
+#[cfg(feature = "tls")]
+async fn serve_tls(addr: String, backend: InMemoryBackend) -> std::io::Result<()> {
+    use sifis_api::tls;
+    use std::io::{Error, ErrorKind};
+    use tokio_rustls::TlsAcceptor;
+
+    let (Ok(cert), Ok(key)) = (
+        std::env::var("SIFIS_TLS_CERT"),
+        std::env::var("SIFIS_TLS_KEY"),
+    ) else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "SIFIS_TLS_CERT and SIFIS_TLS_KEY are required",
+        ));
+    };
+    let acceptor = TlsAcceptor::from(std::sync::Arc::new(tls::load_server_config(cert, key)?));
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    info!("Listening over TLS on {addr}");
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let backend = backend.clone();
+
+        tokio::spawn(async move {
+            match acceptor.accept(stream).await {
+                Ok(stream) => {
+                    info!("New TLS client {peer}");
+                    let transport =
+                        tarpc::serde_transport::new(tls::framed(stream), Bincode::default());
+                    let hangup = CancellationToken::new();
+                    let serve = backend.with_hangup(hangup.clone()).serve_metered();
+                    execute(server::BaseChannel::with_defaults(transport), serve, hangup).await;
+                }
+                Err(e) => warn!("TLS handshake with {peer} failed: {e}"),
+            }
+        });
     }
 }
 
@@ -439,20 +372,67 @@ async fn exit_request() {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt::init();
-    let path = std::env::var("SIFIS_SERVER").unwrap_or("/var/run/sifis.sock".to_string());
-    if Path::new(&path).exists() {
+    let args = cli().get_matches();
+    let path = args
+        .get_one::<PathBuf>("socket")
+        .cloned()
+        .or_else(|| std::env::var_os("SIFIS_SERVER").map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("/var/run/sifis.sock"));
+    if path.exists() {
         std::fs::remove_file(&path)
             .unwrap_or_else(|err| panic!("unable to remove old unix socket file: {err}"));
     }
-    let listener = tarpc::serde_transport::unix::listen(path, Bincode::default).await?;
-
-    let conf = load_conf().await;
-    let devices = Arc::new(Mutex::new(conf.devices));
+    let mut listener = tarpc::serde_transport::unix::listen(&path, Bincode::default).await?;
+    if let Some(mode) = socket_mode_from_env() {
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+        info!("Socket {} restricted to mode {mode:04o}", path.display());
+    }
+    listener
+        .config_mut()
+        .max_frame_length(env_var("SIFIS_MAX_FRAME_LENGTH").unwrap_or(DEFAULT_MAX_FRAME_LENGTH));
+
+    let mut backend = faults_from_env(load_conf(args.get_one("config")).await?);
+    if let Some(seed) = env_var("SIFIS_SEED") {
+        backend = backend.with_seed(seed);
+    }
+    if let Some(scale) = env_var::<f64>("SIFIS_TIME_SCALE") {
+        info!("Running the simulated time {scale}x faster");
+        backend = backend.with_time_scale(scale);
+    }
+    if env_var::<bool>("SIFIS_METRICS") == Some(false) {
+        info!("Not counting the calls in the metrics");
+        backend = backend.with_metrics(false);
+    }
+    simulate_from_env(&backend);
+
+    #[cfg(feature = "tls")]
+    if let Ok(addr) = std::env::var("SIFIS_TLS_ADDR") {
+        let backend = backend.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_tls(addr, backend).await {
+                warn!("TLS listener failed: {e}");
+            }
+        });
+    }
+
+    let max_concurrent = env_var("SIFIS_MAX_CONCURRENT")
+        .filter(|&max| max > 0)
+        .unwrap_or(DEFAULT_MAX_CONCURRENT);
+    let max_channels_per_client = env_var("SIFIS_MAX_CHANNELS_PER_CLIENT")
+        .filter(|&max| max > 0)
+        .unwrap_or(DEFAULT_MAX_CHANNELS_PER_CLIENT);
+    info!("Serving {max_concurrent} clients at once, {max_channels_per_client} connections each");
+    let idle_timeout = env_var("SIFIS_IDLE_TIMEOUT")
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs);
 
     let listen = listener
         .filter_map(|r| future::ready(r.ok()))
         .map(server::BaseChannel::with_defaults)
-        //        .max_channels_per_key(1, |t| t.transport().unwrap().peer_addr().as_pathname().unwrap())
+        // The connections over the limit of their process are closed right away
+        .max_channels_per_key(max_channels_per_client, |channel| {
+            peer_pid(channel.transport().get_ref().as_fd())
+        })
         .map(|channel| {
             let peer = channel.transport().get_ref();
 
@@ -463,13 +443,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let path = pidpath(pid).unwrap_or_else(|e| format!("Cannot find the executable: {e}"));
 
             info!("New client, pid {pid} {path}");
-            let server = SifisMock {
-                devices: devices.clone(),
-            };
-            channel.execute(server.serve())
+            let activity = Activity::new();
+            let hangup = CancellationToken::new();
+            let serve = tracked(
+                backend
+                    .clone()
+                    .with_peer_pid(pid)
+                    .with_hangup(hangup.clone())
+                    .serve_metered(),
+                activity.clone(),
+            );
+            async move {
+                let Some(timeout) = idle_timeout else {
+                    return execute(channel, serve, hangup).await;
+                };
+                tokio::select! {
+                    _ = execute(channel, serve, hangup) => {}
+                    _ = activity.idle_for(timeout) => {
+                        info!("Closing the connection of pid {pid}, idle for {timeout:?}");
+                    }
+                }
+            }
         })
-        // Max concurrent calls
-        .buffer_unordered(10)
+        // The clients over the limit wait for a free slot
+        .buffer_unordered(max_concurrent)
         .for_each(|_| async {});
 
     tokio::select! {
@@ -0,0 +1,51 @@
+//! JSON-RPC over WebSocket bridge
+//!
+//! It lets the browser clients reach the runtime listening on the unix
+//! socket, see [sifis_api::ws].
+
+use clap::{Arg, ArgAction, Command};
+use sifis_api::Sifis;
+use tokio::net::TcpListener;
+use tracing::info;
+
+fn cli() -> Command {
+    Command::new("sifis-ws-bridge")
+        .about("JSON-RPC over WebSocket bridge to the sifis runtime")
+        .arg(
+            Arg::new("listen")
+                .long("listen")
+                .value_name("ADDR")
+                .default_value("127.0.0.1:9001")
+                .help("Address to accept the WebSocket connections on"),
+        )
+        .arg(
+            Arg::new("allow-origin")
+                .long("allow-origin")
+                .value_name("ORIGIN")
+                .action(ArgAction::Append)
+                .help("Origin of the browser pages allowed to connect, e.g. http://localhost:8080"),
+        )
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt::init();
+    let args = cli().get_matches();
+    let addr = args.get_one::<String>("listen").unwrap();
+    let allowed_origins = args
+        .get_many::<String>("allow-origin")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+
+    let sifis = Sifis::new().await?;
+    let listener = TcpListener::bind(addr).await?;
+    info!("Bridging the WebSocket connections on {addr}");
+
+    tokio::select! {
+        r = sifis_api::ws::serve(listener, sifis, allowed_origins) => r?,
+        _ = tokio::signal::ctrl_c() => info!("Exiting"),
+    }
+
+    Ok(())
+}
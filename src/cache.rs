@@ -0,0 +1,81 @@
+//! Client-side cache of the device reads
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::Result;
+
+type Entries = HashMap<(String, &'static str), (Instant, Box<dyn Any + Send>)>;
+
+#[derive(Debug)]
+struct Inner {
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+/// Values read from the devices, indexed by device id and property
+///
+/// The default one is disabled and caches nothing.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Cache(Option<Arc<Inner>>);
+
+impl Cache {
+    /// Keep the values read for `ttl`
+    pub(crate) fn new(ttl: Duration) -> Cache {
+        Cache(Some(Arc::new(Inner {
+            ttl,
+            entries: Mutex::default(),
+        })))
+    }
+
+    /// Return the cached `property` of the device `id` if it is fresh,
+    /// otherwise `read` it and cache it
+    pub(crate) async fn get_or<T, F>(&self, id: &str, property: &'static str, read: F) -> Result<T>
+    where
+        T: Clone + Send + 'static,
+        F: Future<Output = Result<T>>,
+    {
+        let Some(inner) = &self.0 else {
+            return read.await;
+        };
+
+        let key = (id.to_owned(), property);
+        if let Some((at, value)) = inner.entries.lock().unwrap().get(&key) {
+            if at.elapsed() < inner.ttl {
+                if let Some(value) = value.downcast_ref::<T>() {
+                    return Ok(value.clone());
+                }
+            }
+        }
+
+        let value = read.await?;
+        inner
+            .entries
+            .lock()
+            .unwrap()
+            .insert(key, (Instant::now(), Box::new(value.clone())));
+
+        Ok(value)
+    }
+
+    /// Forget the values of the device `id`
+    pub(crate) fn invalidate(&self, id: &str) {
+        if let Some(inner) = &self.0 {
+            inner
+                .entries
+                .lock()
+                .unwrap()
+                .retain(|(dev, _), _| dev != id);
+        }
+    }
+
+    /// Forget every value
+    pub(crate) fn clear(&self) {
+        if let Some(inner) = &self.0 {
+            inner.entries.lock().unwrap().clear();
+        }
+    }
+}
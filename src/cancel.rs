@@ -0,0 +1,27 @@
+//! Cancellation of the requests in flight, e.g. on shutdown
+//!
+//! A client made by [Sifis::with_cancellation] sends its requests through a
+//! proxy, as one made by [Sifis::with_priority] does. The proxy answers the
+//! requests still waiting with [service::Error::Cancelled] as soon as the
+//! token is cancelled, instead of waiting for the runtime or their deadline.
+
+use crate::{service, CancellationToken, Sifis};
+
+impl Sifis {
+    /// Abort the requests of this client, and of the device handles it looks
+    /// up, once `token` is cancelled.
+    ///
+    /// The requests in flight fail at once with [service::Error::Cancelled],
+    /// as do the ones made afterwards. The runtime is told to drop them, a
+    /// command it already carried out is not undone. The priority set by
+    /// [Sifis::with_priority] is kept.
+    ///
+    /// It must be called from within a tokio runtime.
+    pub fn with_cancellation(self, token: CancellationToken) -> Sifis {
+        Sifis {
+            cancel: Some(token),
+            ..self
+        }
+        .proxied()
+    }
+}
@@ -0,0 +1,252 @@
+//! Description of what each kind of device supports
+//!
+//! The names are the operations accepted by [Sifis::invoke](crate::Sifis::invoke),
+//! so a client can render the controls of a device it knows nothing about and
+//! drive them without the typed wrappers. The properties can also be read and
+//! set by name, through [Sifis::get_property](crate::Sifis::get_property) and
+//! [Sifis::set_property](crate::Sifis::set_property), and the range of the
+//! numeric ones read with [Sifis::property_range](crate::Sifis::property_range)
+//! to check a value before sending it.
+
+use std::ops::RangeInclusive;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DeviceKind, Fridge, Lamp, Sink};
+
+/// Type of a value read or written on a device
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValueType {
+    /// On/off, open/closed
+    Bool,
+    /// Whole number within `min..=max`
+    Integer { min: i64, max: i64 },
+    /// Real number, e.g. a power in watts
+    Number,
+    /// One of the listed values
+    Enum(Vec<String>),
+}
+
+impl ValueType {
+    fn integer<T: Copy + Into<i64>>(range: RangeInclusive<T>) -> Self {
+        ValueType::Integer {
+            min: (*range.start()).into(),
+            max: (*range.end()).into(),
+        }
+    }
+
+    /// The range of a whole number, `None` for the other types.
+    pub fn range(&self) -> Option<Range> {
+        match *self {
+            ValueType::Integer { min, max } => Some(Range { min, max }),
+            _ => None,
+        }
+    }
+}
+
+/// Values a numeric property accepts, `min` and `max` included
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Range {
+    pub min: i64,
+    pub max: i64,
+}
+
+impl Range {
+    /// Whether `value` is within the range.
+    pub fn contains(&self, value: i64) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+}
+
+/// Value reported by a device
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Property {
+    /// Name of the value, e.g. `brightness`
+    pub name: String,
+    /// Type and range of the value
+    pub value: ValueType,
+    /// Operation reading it
+    pub get: String,
+    /// Operation setting it, taking the new value, if it can be set directly
+    pub set: Option<String>,
+}
+
+/// Action a device can perform
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Operation {
+    /// Name of the operation, e.g. `turn_on`
+    pub name: String,
+    /// Name and type of each argument
+    pub args: Vec<(String, ValueType)>,
+}
+
+/// Properties and operations supported by a device
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Kind, as in [DeviceKind::display]
+    pub kind: String,
+    /// Values the device reports
+    pub properties: Vec<Property>,
+    /// Actions besides setting a property
+    pub operations: Vec<Operation>,
+}
+
+fn property(name: &str, value: ValueType, get: &str, set: Option<&str>) -> Property {
+    Property {
+        name: name.to_owned(),
+        value,
+        get: get.to_owned(),
+        set: set.map(str::to_owned),
+    }
+}
+
+fn operation(name: &str, args: &[(&str, ValueType)]) -> Operation {
+    Operation {
+        name: name.to_owned(),
+        args: args
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect(),
+    }
+}
+
+impl Capabilities {
+    /// Capabilities of the device of kind `kind`
+    ///
+    /// They are shared by every device of the same kind, except for the
+    /// color temperature only the tunable lamps have.
+    pub fn of(kind: &DeviceKind) -> Capabilities {
+        use ValueType::*;
+
+        let (properties, operations) = match kind {
+            DeviceKind::Lamp(lamp) => (
+                [
+                    property("on", Bool, "get_on_off", None),
+                    property(
+                        "brightness",
+                        ValueType::integer(Lamp::BRIGHTNESS),
+                        "get_brightness",
+                        Some("set_brightness"),
+                    ),
+                    property(
+                        "brightness_hi",
+                        ValueType::integer(Lamp::BRIGHTNESS_HI),
+                        "get_brightness_hi",
+                        Some("set_brightness_hi"),
+                    ),
+                    property("power", Number, "power", None),
+                ]
+                .into_iter()
+                .chain(lamp.color_temp_kelvin.map(|_| {
+                    property(
+                        "color_temperature",
+                        ValueType::integer(Lamp::COLOR_TEMPERATURE),
+                        "color_temperature",
+                        Some("set_color_temperature"),
+                    )
+                }))
+                .collect(),
+                vec![
+                    operation("turn_on", &[]),
+                    operation("turn_off", &[]),
+                    operation(
+                        "adjust_brightness",
+                        &[("delta", ValueType::integer(i16::MIN..=i16::MAX))],
+                    ),
+                ],
+            ),
+            DeviceKind::Sink(_) => (
+                vec![
+                    property(
+                        "water_level",
+                        ValueType::integer(0..=100),
+                        "get_water_level",
+                        None,
+                    ),
+                    property("drain", Bool, "get_drain", None),
+                    property(
+                        "flow",
+                        ValueType::integer(Sink::FLOW),
+                        "get_flow",
+                        Some("set_flow"),
+                    ),
+                    property(
+                        "temperature",
+                        ValueType::integer(Sink::TEMPERATURE),
+                        "get_temperature",
+                        Some("set_temperature"),
+                    ),
+                    property(
+                        "max_temperature",
+                        ValueType::integer(u8::MIN..=u8::MAX),
+                        "max_temperature",
+                        Some("set_max_temperature"),
+                    ),
+                ],
+                vec![
+                    operation("open_drain", &[]),
+                    operation("close_drain", &[]),
+                    operation("stop", &[]),
+                    operation(
+                        "set_temperature_forced",
+                        &[("temperature", ValueType::integer(Sink::TEMPERATURE))],
+                    ),
+                    operation(
+                        "adjust_flow",
+                        &[("delta", ValueType::integer(i16::MIN..=i16::MAX))],
+                    ),
+                ],
+            ),
+            DeviceKind::Door(_) => (
+                vec![
+                    property("open", Bool, "is_open", None),
+                    property(
+                        "lock_status",
+                        Enum(["unlocked", "locked", "jammed"].map(str::to_owned).to_vec()),
+                        "lock_status",
+                        None,
+                    ),
+                ],
+                vec![
+                    operation("lock", &[]),
+                    operation("unlock", &[]),
+                    operation(
+                        "lock_with_retries",
+                        &[("attempts", ValueType::integer(u8::MIN..=u8::MAX))],
+                    ),
+                ],
+            ),
+            DeviceKind::Fridge(_) => (
+                vec![
+                    property("open", Bool, "is_open", None),
+                    property(
+                        "temperature",
+                        ValueType::integer(i8::MIN..=i8::MAX),
+                        "temperature",
+                        None,
+                    ),
+                    property(
+                        "target_temperature",
+                        ValueType::integer(Fridge::TARGET_TEMPERATURE),
+                        "target_temperature",
+                        Some("set_target_temperature"),
+                    ),
+                ],
+                vec![operation(
+                    "adjust_target_temperature",
+                    &[("delta", ValueType::integer(i16::MIN..=i16::MAX))],
+                )],
+            ),
+            DeviceKind::Curtain(_) => (
+                vec![property("open", Bool, "is_open", None)],
+                vec![operation("open", &[]), operation("close", &[])],
+            ),
+        };
+
+        Capabilities {
+            kind: kind.display().to_owned(),
+            properties,
+            operations,
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! Documentation of the device operations, for the clients to show at run
+//! time
+//!
+//! It mirrors the rustdoc of [SifisApi](crate::service::SifisApi), the
+//! operations are named after its methods.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Hazard;
+use crate::Hazard::*;
+
+/// Human-readable description of an operation
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationDoc {
+    /// Name of the operation, e.g. `turn_lamp_on`
+    pub name: String,
+    /// What the operation does, in a sentence
+    pub summary: String,
+    /// Hazards the operation may bring about
+    pub hazards: Vec<Hazard>,
+    /// Arguments besides the device id, in order
+    pub params: Vec<ParamDoc>,
+}
+
+/// Human-readable description of an argument of an operation
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParamDoc {
+    /// Name of the argument, e.g. `brightness`
+    pub name: String,
+    /// Rust type of the argument, e.g. `u8`
+    pub type_name: String,
+    /// Meaning of the argument
+    pub summary: String,
+}
+
+/// Entry of the table of the operations
+struct Doc {
+    name: &'static str,
+    summary: &'static str,
+    hazards: &'static [Hazard],
+    /// Name, type and meaning of each argument
+    params: &'static [(&'static str, &'static str, &'static str)],
+}
+
+/// Hazards of lighting a lamp or making it brighter
+const LIGHTING: &[Hazard] = &[Fire, LogEnergyConsumption, EnergyConsumption, PowerOutage];
+
+const DELTA: (&str, &str, &str) = ("delta", "i16", "Change to apply, saturating");
+
+const OPERATIONS: &[Doc] = &[
+    // Lamp
+    Doc {
+        name: "turn_lamp_on",
+        summary: "Turn the lamp on.",
+        hazards: LIGHTING,
+        params: &[],
+    },
+    Doc {
+        name: "turn_lamp_off",
+        summary: "Turn the lamp off.",
+        hazards: &[LogEnergyConsumption],
+        params: &[],
+    },
+    Doc {
+        name: "get_lamp_on_off",
+        summary: "Get whether the lamp is on.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "set_lamp_brightness",
+        summary: "Change the brightness, clamped to 100 percent and the lamp maximum.",
+        hazards: LIGHTING,
+        params: &[("brightness", "u8", "Brightness in percent")],
+    },
+    Doc {
+        name: "adjust_lamp_brightness",
+        summary: "Change the brightness by a delta.",
+        hazards: LIGHTING,
+        params: &[DELTA],
+    },
+    Doc {
+        name: "ramp_lamp_brightness",
+        summary: "Change the brightness gradually.",
+        hazards: LIGHTING,
+        params: &[
+            ("brightness", "u8", "Brightness to reach in percent"),
+            ("duration_ms", "u32", "Duration of the ramp in milliseconds"),
+        ],
+    },
+    Doc {
+        name: "wait_for_lamp_brightness",
+        summary: "Wait for the brightness to reach a level.",
+        hazards: &[],
+        params: &[
+            ("target", "u8", "Brightness to wait for in percent"),
+            ("timeout_ms", "u32", "Longest wait in milliseconds"),
+        ],
+    },
+    Doc {
+        name: "get_lamp_brightness",
+        summary: "Get the brightness in percent.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "set_lamp_brightness_hi",
+        summary: "Change the brightness with the resolution of the professional dimmers.",
+        hazards: LIGHTING,
+        params: &[("brightness", "u16", "Brightness from 0 to 65535")],
+    },
+    Doc {
+        name: "get_lamp_brightness_hi",
+        summary: "Get the brightness from 0 to 65535.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "get_lamp_max_brightness",
+        summary: "Get the highest brightness allowed, if the lamp has a cap.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "get_lamp_color_temp",
+        summary: "Get the white color temperature, if the lamp is tunable.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "set_lamp_color_temp",
+        summary: "Change the white color temperature of a tunable lamp.",
+        hazards: &[],
+        params: &[("kelvin", "u16", "Color temperature in kelvin")],
+    },
+    Doc {
+        name: "set_lamp_state",
+        summary: "Set the on status, brightness and color temperature at once.",
+        hazards: LIGHTING,
+        params: &[("state", "LampState", "State to set")],
+    },
+    Doc {
+        name: "get_lamp_power",
+        summary: "Get the estimated power drawn in watts.",
+        hazards: &[LogEnergyConsumption],
+        params: &[],
+    },
+    // Sink
+    Doc {
+        name: "set_sink_flow",
+        summary: "Change the water flow, clamped to 100 percent.",
+        hazards: &[Flood],
+        params: &[("flow", "u8", "Water flow in percent")],
+    },
+    Doc {
+        name: "adjust_sink_flow",
+        summary: "Change the water flow by a delta.",
+        hazards: &[Flood],
+        params: &[DELTA],
+    },
+    Doc {
+        name: "get_sink_flow",
+        summary: "Get the water flow in percent.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "set_sink_temp",
+        summary: "Change the water temperature, refused above the sink limit.",
+        hazards: &[Scald],
+        params: &[("temp", "u8", "Water temperature in Celsius degrees")],
+    },
+    Doc {
+        name: "set_sink_temp_forced",
+        summary: "Change the water temperature even above the sink limit.",
+        hazards: &[Scald],
+        params: &[("temp", "u8", "Water temperature in Celsius degrees")],
+    },
+    Doc {
+        name: "get_sink_temp",
+        summary: "Get the water temperature in Celsius degrees.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "get_sink_max_temp",
+        summary: "Get the highest water temperature the sink accepts.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "set_sink_max_temp",
+        summary:
+            "Change the highest water temperature the sink accepts, raising it may be refused.",
+        hazards: &[Scald],
+        params: &[("max_temp", "u8", "Highest temperature in Celsius degrees")],
+    },
+    Doc {
+        name: "close_sink_drain",
+        summary: "Close the drain.",
+        hazards: &[Flood],
+        params: &[],
+    },
+    Doc {
+        name: "open_sink_drain",
+        summary: "Open the drain, emptying the sink.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "stop_sink",
+        summary: "Stop the water and open the drain.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "get_sink_drain",
+        summary: "Get whether the drain is open.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "get_sink_level",
+        summary: "Get the water level in percent.",
+        hazards: &[],
+        params: &[],
+    },
+    // Door
+    Doc {
+        name: "get_door_lock_status",
+        summary: "Get whether the door is locked, unlocked or jammed.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "get_door_open",
+        summary: "Get whether the door is open.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "lock_door",
+        summary: "Lock the door, moving the lock may jam it.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "lock_door_retry",
+        summary: "Lock the door, trying again while the lock is jammed.",
+        hazards: &[],
+        params: &[("attempts", "u8", "Attempts after the first one")],
+    },
+    Doc {
+        name: "unlock_door",
+        summary: "Unlock the door, moving the lock may jam it.",
+        hazards: &[],
+        params: &[],
+    },
+    // Fridge
+    Doc {
+        name: "get_fridge_temperature",
+        summary: "Get the temperature inside the fridge in Celsius degrees.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "get_fridge_target_temperature",
+        summary: "Get the target temperature in Celsius degrees.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "set_fridge_target_temperature",
+        summary: "Change the target temperature.",
+        hazards: &[],
+        params: &[(
+            "target_temperature",
+            "i8",
+            "Target temperature in Celsius degrees",
+        )],
+    },
+    Doc {
+        name: "adjust_fridge_target_temperature",
+        summary: "Change the target temperature by a delta.",
+        hazards: &[],
+        params: &[DELTA],
+    },
+    Doc {
+        name: "get_fridge_open",
+        summary: "Get whether the fridge door is open.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "get_fridge_open_duration",
+        summary: "Get for how long the fridge door has been open in seconds.",
+        hazards: &[],
+        params: &[],
+    },
+    // Curtain
+    Doc {
+        name: "open_curtain",
+        summary: "Open the curtain.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "close_curtain",
+        summary: "Close the curtain.",
+        hazards: &[],
+        params: &[],
+    },
+    Doc {
+        name: "get_curtain_open",
+        summary: "Get whether the curtain is open.",
+        hazards: &[],
+        params: &[],
+    },
+];
+
+impl OperationDoc {
+    /// Description of the operation `name`, if it is a device operation
+    pub fn of(name: &str) -> Option<OperationDoc> {
+        let doc = OPERATIONS.iter().find(|doc| doc.name == name)?;
+
+        Some(OperationDoc {
+            name: doc.name.to_owned(),
+            summary: doc.summary.to_owned(),
+            hazards: doc.hazards.to_vec(),
+            params: doc
+                .params
+                .iter()
+                .map(|&(name, type_name, summary)| ParamDoc {
+                    name: name.to_owned(),
+                    type_name: type_name.to_owned(),
+                    summary: summary.to_owned(),
+                })
+                .collect(),
+        })
+    }
+
+    /// Names of the documented operations
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        OPERATIONS.iter().map(|doc| doc.name)
+    }
+}
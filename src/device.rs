@@ -0,0 +1,492 @@
+//! Device state model
+//!
+//! The states are plain data, the runtime owns the actual devices.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DoorLockStatus, Lamp};
+
+/// Identifier of a device
+///
+/// A valid id is not empty and contains no control characters, [DeviceId::new]
+/// and [FromStr] check it while the `From` conversions defer the check to the
+/// lookup using the id.
+///
+/// The lookups returning a device handle take any `impl Into<DeviceId>`, e.g.
+/// a `&str`, a `String`, a `&String` or a `DeviceId`, and keep the id. The
+/// other calls by id borrow a `&str`, that a `&String` or a `&DeviceId` derefs
+/// to.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DeviceId(String);
+
+/// A device id that is empty or contains control characters
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid device id {0:?}")]
+pub struct InvalidDeviceId(pub String);
+
+impl DeviceId {
+    /// Validate `id` as a device id.
+    pub fn new(id: impl Into<String>) -> Result<DeviceId, InvalidDeviceId> {
+        let id = DeviceId(id.into());
+        id.validate()?;
+        Ok(id)
+    }
+
+    /// The id as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), InvalidDeviceId> {
+        if self.0.is_empty() || self.0.chars().any(char::is_control) {
+            Err(InvalidDeviceId(self.0.clone()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl FromStr for DeviceId {
+    type Err = InvalidDeviceId;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DeviceId::new(s)
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.0)
+    }
+}
+
+impl Deref for DeviceId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for DeviceId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The ids compare and hash as their string, a map keyed by [DeviceId] can
+/// be searched with a `&str`
+impl Borrow<str> for DeviceId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for DeviceId {
+    fn from(id: String) -> Self {
+        DeviceId(id)
+    }
+}
+
+impl From<&String> for DeviceId {
+    fn from(id: &String) -> Self {
+        DeviceId(id.clone())
+    }
+}
+
+impl From<&str> for DeviceId {
+    fn from(id: &str) -> Self {
+        DeviceId(id.to_owned())
+    }
+}
+
+impl From<&DeviceId> for DeviceId {
+    fn from(id: &DeviceId) -> Self {
+        id.clone()
+    }
+}
+
+impl From<DeviceId> for String {
+    fn from(id: DeviceId) -> Self {
+        id.0
+    }
+}
+
+impl PartialEq<str> for DeviceId {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for DeviceId {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<DeviceId> for &str {
+    fn eq(&self, other: &DeviceId) -> bool {
+        *self == other.0
+    }
+}
+
+/// State of a lamp
+///
+/// It is serialized with the brightness in percent, as the configurations
+/// give it, along with the high resolution `brightness_hi` if the percentage
+/// is not exact. `brightness_hi` takes precedence when given.
+#[derive(Default, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "LampStateRepr", into = "LampStateRepr")]
+pub struct LampState {
+    /// Brightness level, within [Lamp::BRIGHTNESS_HI]
+    pub brightness: u16,
+    /// Whether the lamp is on
+    pub on: bool,
+    /// Highest brightness level allowed in percent, the requests above it
+    /// are clamped
+    pub max_brightness: Option<u8>,
+    /// White color temperature in kelvin, `None` if the lamp is not
+    /// tunable
+    pub color_temp_kelvin: Option<u16>,
+}
+
+/// Serialized form of [LampState]
+#[derive(Serialize, Deserialize)]
+struct LampStateRepr {
+    /// Brightness level, from 0 to 100
+    #[serde(default)]
+    brightness: u8,
+    on: bool,
+    #[serde(default)]
+    max_brightness: Option<u8>,
+    #[serde(default)]
+    color_temp_kelvin: Option<u16>,
+    #[serde(default)]
+    brightness_hi: Option<u16>,
+}
+
+impl From<LampStateRepr> for LampState {
+    fn from(repr: LampStateRepr) -> Self {
+        let brightness = repr
+            .brightness_hi
+            .unwrap_or_else(|| Lamp::brightness_to_hi(repr.brightness));
+
+        LampState {
+            brightness,
+            on: repr.on,
+            max_brightness: repr.max_brightness,
+            color_temp_kelvin: repr.color_temp_kelvin,
+        }
+    }
+}
+
+impl From<LampState> for LampStateRepr {
+    fn from(state: LampState) -> Self {
+        let brightness = Lamp::brightness_from_hi(state.brightness);
+        // Left out when exact, an override of the percentage is not shadowed
+        let brightness_hi =
+            (Lamp::brightness_to_hi(brightness) != state.brightness).then_some(state.brightness);
+
+        LampStateRepr {
+            brightness,
+            on: state.on,
+            max_brightness: state.max_brightness,
+            color_temp_kelvin: state.color_temp_kelvin,
+            brightness_hi,
+        }
+    }
+}
+
+/// State of a water basin/sink
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SinkState {
+    /// Water flow, from 0 to 100
+    pub flow: u8,
+    /// Water temperature
+    pub temp: u8,
+    /// Water level, from 0 to 100
+    pub level: u8,
+    /// Whether the drain is open
+    pub drain: bool,
+    /// Highest water temperature allowed, the requests above it are refused
+    /// and the running water above it is a scald hazard
+    #[serde(default = "SinkState::unlimited_temp")]
+    pub max_safe_temp: u8,
+}
+
+impl SinkState {
+    fn unlimited_temp() -> u8 {
+        u8::MAX
+    }
+}
+
+impl Default for SinkState {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            flow: 0,
+            temp: 20,
+            level: 0,
+            drain: true,
+            max_safe_temp: Self::unlimited_temp(),
+        }
+    }
+}
+
+/// State of a door
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DoorState {
+    /// Whether the door is open
+    pub is_open: bool,
+    /// Status of the lock
+    pub lock: DoorLockStatus,
+    /// Chance, from `0.0` to `1.0`, the lock jams when it is moved
+    #[serde(default)]
+    pub jam_probability: f32,
+}
+
+/// State of a fridge
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FridgeState {
+    /// Whether the door is open
+    pub open: bool,
+    /// Current temperature
+    pub temperature: i8,
+    /// Temperature the fridge is trying to reach
+    pub target_temperature: i8,
+}
+
+impl Default for FridgeState {
+    fn default() -> Self {
+        Self {
+            open: false,
+            temperature: 5,
+            target_temperature: 4,
+        }
+    }
+}
+
+/// State of a curtain, it can only be fully open or fully closed
+#[derive(Default, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CurtainState {
+    /// Whether the curtain is open
+    pub open: bool,
+    /// Whether the curtain is moving
+    pub moving: bool,
+}
+
+/// Kind of a device along with its state
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DeviceKind {
+    Lamp(LampState),
+    Sink(SinkState),
+    Door(DoorState),
+    Fridge(FridgeState),
+    Curtain(CurtainState),
+}
+
+impl DeviceKind {
+    pub fn display(&self) -> &'static str {
+        match self {
+            DeviceKind::Lamp(_) => "Lamp",
+            DeviceKind::Sink(_) => "Sink",
+            DeviceKind::Door(_) => "Door",
+            DeviceKind::Fridge(_) => "Fridge",
+            DeviceKind::Curtain(_) => "Curtain",
+        }
+    }
+}
+
+/// Description of a device, used to add it to a runtime
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeviceSpec {
+    /// Human readable name
+    pub name: String,
+    /// Room the device is in, if any
+    #[serde(default)]
+    pub room: Option<String>,
+    /// Initial maintenance needs, a battery powered device has a charge
+    #[serde(default)]
+    pub maintenance: MaintenanceStatus,
+    /// Kind and initial state
+    pub kind: DeviceKind,
+}
+
+/// Identity of a device
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    /// Unique identifier
+    pub id: String,
+    /// Human readable name
+    pub name: String,
+    /// Room the device is in, if any
+    pub room: Option<String>,
+    /// Kind, as in [DeviceKind::display]
+    ///
+    /// A plain string, so a client can list the kinds of a newer runtime it
+    /// does not know, see [DeviceInfo::known_kind].
+    pub kind: String,
+}
+
+impl DeviceInfo {
+    /// The kind of the device, if this client knows it.
+    ///
+    /// The kinds added by a newer runtime are `None`, the device can still
+    /// be shown by its name and driven through its
+    /// [Capabilities](crate::Capabilities).
+    pub fn known_kind(&self) -> Option<KnownKind> {
+        KnownKind::ALL
+            .into_iter()
+            .find(|kind| kind.display() == self.kind)
+    }
+}
+
+/// Kind of device this client has a typed handle for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KnownKind {
+    /// A [crate::Lamp]
+    Lamp,
+    /// A [crate::Sink]
+    Sink,
+    /// A [crate::Door]
+    Door,
+    /// A [crate::Fridge]
+    Fridge,
+    /// A [crate::Curtain]
+    Curtain,
+}
+
+impl KnownKind {
+    /// Every kind, in the order of [DeviceKind]
+    pub const ALL: [KnownKind; 5] = [
+        KnownKind::Lamp,
+        KnownKind::Sink,
+        KnownKind::Door,
+        KnownKind::Fridge,
+        KnownKind::Curtain,
+    ];
+
+    /// Name of the kind, as in [DeviceKind::display]
+    pub fn display(&self) -> &'static str {
+        match self {
+            KnownKind::Lamp => "Lamp",
+            KnownKind::Sink => "Sink",
+            KnownKind::Door => "Door",
+            KnownKind::Fridge => "Fridge",
+            KnownKind::Curtain => "Curtain",
+        }
+    }
+}
+
+/// Health of a device
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HealthStatus {
+    /// Working as expected
+    Ok,
+    /// Reachable but not working as expected, e.g. with a jammed lock
+    Degraded,
+    /// Not answering
+    Offline,
+}
+
+/// Outcome of probing a device
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceHealth {
+    /// Unique identifier
+    pub id: String,
+    /// Kind, as in [DeviceKind::display]
+    pub kind: String,
+    /// Health found by the probe
+    pub status: HealthStatus,
+    /// What is wrong, unless the device is healthy
+    pub detail: Option<String>,
+}
+
+/// Maintenance needs of a device
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaintenanceStatus {
+    /// Battery charge in percent, unless the device is mains powered
+    #[serde(default)]
+    pub battery: Option<u8>,
+    /// Whether a part wearing out, e.g. a filter, is due for replacement
+    #[serde(default)]
+    pub replacement_due: bool,
+}
+
+impl MaintenanceStatus {
+    /// Battery charge below which the battery is low, in percent
+    pub const LOW_BATTERY: u8 = 20;
+
+    /// Whether the battery is low and should be recharged or replaced.
+    pub fn low_battery(&self) -> bool {
+        self.battery
+            .is_some_and(|battery| battery < Self::LOW_BATTERY)
+    }
+
+    /// Whether the device needs attention, for its battery or a part.
+    pub fn needs_attention(&self) -> bool {
+        self.low_battery() || self.replacement_due
+    }
+}
+
+/// State of every device in the home, grouped by kind and sorted by id
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HomeSnapshot {
+    /// Lamps along with their id
+    pub lamps: Vec<(String, LampState)>,
+    /// Sinks along with their id
+    pub sinks: Vec<(String, SinkState)>,
+    /// Doors along with their id
+    pub doors: Vec<(String, DoorState)>,
+    /// Fridges along with their id
+    pub fridges: Vec<(String, FridgeState)>,
+    /// Curtains along with their id
+    pub curtains: Vec<(String, CurtainState)>,
+}
+
+/// A change made to a device
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceEvent {
+    /// When the change happened
+    pub timestamp: SystemTime,
+    /// Device changed
+    pub device_id: String,
+    /// Name of the operation, e.g. `turn_lamp_on`
+    pub operation: String,
+    /// Process requesting the change, if known
+    pub peer_pid: Option<i32>,
+    /// Whether the device changed state, was added or removed or needs
+    /// maintenance
+    pub change: Change,
+}
+
+/// What a [DeviceEvent] did to the device
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Change {
+    /// Its state changed
+    #[default]
+    State,
+    /// It was added, `kind` as in [DeviceKind::display]
+    Added { kind: String },
+    /// It was removed
+    Removed,
+    /// Its maintenance needs crossed a threshold, e.g. its battery ran low
+    Maintenance(MaintenanceStatus),
+}
+
+/// Device changes following a position in the runtime event log
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventBatch {
+    /// The changes, oldest first
+    pub events: Vec<DeviceEvent>,
+    /// Position to wait for the next changes from
+    pub next: u64,
+    /// Changes forgotten by the runtime before being requested
+    pub lagged: u64,
+}
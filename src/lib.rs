@@ -1,15 +1,58 @@
+use std::collections::{HashSet, VecDeque};
 use std::fmt::{self, Display};
+use std::future::Future;
+use std::ops::RangeInclusive;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
+use futures::future;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use tarpc::client::RpcError;
+use serde_json::Value;
+use tarpc::client::{NewClient, RpcError};
 use tarpc::tokio_serde::formats::Bincode;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 
+mod cache;
+mod cancel;
+mod capabilities;
+mod describe;
+mod device;
+mod multi;
+mod observer;
+mod priority;
+mod recording;
 pub mod runtime;
+mod schedule;
+mod server_info;
+mod shared;
+mod summary;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "ws")]
+pub mod ws;
+
+pub use capabilities::{Capabilities, Operation, Property, Range, ValueType};
+pub use describe::{OperationDoc, ParamDoc};
+pub use device::{
+    Change, CurtainState, DeviceEvent, DeviceHealth, DeviceId, DeviceInfo, DeviceKind, DeviceSpec,
+    DoorState, EventBatch, FridgeState, HealthStatus, HomeSnapshot, InvalidDeviceId, KnownKind,
+    LampState, MaintenanceStatus, SinkState,
+};
+pub use multi::SifisMulti;
+pub use observer::{
+    Observer, ObserverCurtain, ObserverDoor, ObserverFridge, ObserverLamp, ObserverSink,
+};
+pub use priority::Priority;
+pub use schedule::{Schedule, ScheduleId};
+pub use server_info::ServerInfo;
+pub use tokio_util::sync::CancellationToken;
 
 // TODO: Use sifis-hazards
 /// Hazard descriptions
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum Hazard {
     /// The execution may cause fire.
     Fire,
@@ -23,6 +66,8 @@ pub enum Hazard {
     Flood,
     /// Might boil water or heat up a surface
     Scald,
+    /// The home may be entered without permission
+    Intrusion,
 }
 
 impl Display for Hazard {
@@ -32,65 +77,288 @@ impl Display for Hazard {
 }
 
 /// Lower level rpc
+///
+/// A runtime implements [service::SifisApi] to expose its devices,
+/// [runtime::InMemoryBackend] is a complete example that can be embedded.
 pub mod service {
-    use crate::DoorLockStatus;
+    use crate::{
+        Capabilities, Clamped, DeviceEvent, DeviceHealth, DeviceInfo, DeviceSpec, DoorLockStatus,
+        EventBatch, HomeSnapshot, LampState, LockResult, MaintenanceStatus, OperationDoc, Priority,
+        Range, Schedule, ScheduleId, ServerInfo, ToggleResult,
+    };
+    use std::time::SystemTime;
 
     use super::Hazard;
 
+    /// Version of the [SifisApi] protocol, bumped on every incompatible
+    /// change
+    pub const PROTOCOL_VERSION: u32 = 4;
+
     #[derive(Debug, thiserror::Error, serde::Serialize, serde::Deserialize)]
     pub enum Error {
         #[error("Device of kind {found} found {req} requested")]
         Mismatch { found: String, req: String },
         #[error("Device {0} not found")]
         NotFound(String),
+        #[error("Device {0} already exists")]
+        AlreadyExists(String),
+        /// `retriable_with_ack` tells whether a `*_forced` variant of the
+        /// operation exists, to retry once the user acknowledged the risk
         #[error("Operation forbidden {risk}: {comment}")]
-        Forbidden { risk: Hazard, comment: String },
+        Forbidden {
+            risk: Hazard,
+            comment: String,
+            retriable_with_ack: bool,
+        },
+        #[error("Invalid argument: {0}")]
+        InvalidArgument(String),
+        #[error("Device busy, retry in {retry_after_ms}ms")]
+        RateLimited { retry_after_ms: u64 },
+        #[error("Value {value} out of the {min}..={max} range")]
+        OutOfRange { value: i64, min: i64, max: i64 },
+        #[error("Device {0} locked out for maintenance")]
+        LockedOut(String),
+        /// Given by the client itself, see [crate::Sifis::with_cancellation]
+        #[error("Request cancelled")]
+        Cancelled,
+        #[error("Device {0} is offline")]
+        Offline(String),
+        /// The runtime does not serve the method, see
+        /// [crate::Sifis::supports]
+        #[error("Method {method} not supported by the runtime")]
+        Unsupported { method: String },
+    }
+
+    /// Stable identifier of an [Error] variant
+    ///
+    /// Meant for programmatic matching, it does not depend on the error
+    /// message wording.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    #[non_exhaustive]
+    pub enum ErrorCode {
+        /// The device does not exist.
+        NotFound,
+        /// A device with the same id already exists.
+        AlreadyExists,
+        /// The device is not of the requested kind.
+        KindMismatch,
+        /// The operation has been refused because of a hazard.
+        Forbidden,
+        /// The arguments of the operation are malformed.
+        InvalidArgument,
+        /// The device has been commanded too recently.
+        RateLimited,
+        /// An argument is outside of the range the device accepts.
+        OutOfRange,
+        /// The device refuses the commands while in maintenance.
+        LockedOut,
+        /// The client gave up on the request.
+        Cancelled,
+        /// The device is not reachable.
+        Offline,
+        /// The runtime does not implement the method.
+        Unsupported,
+    }
+
+    impl Error {
+        /// The stable code of this error.
+        pub fn code(&self) -> ErrorCode {
+            match self {
+                Error::Mismatch { .. } => ErrorCode::KindMismatch,
+                Error::NotFound(_) => ErrorCode::NotFound,
+                Error::AlreadyExists(_) => ErrorCode::AlreadyExists,
+                Error::Forbidden { .. } => ErrorCode::Forbidden,
+                Error::InvalidArgument(_) => ErrorCode::InvalidArgument,
+                Error::RateLimited { .. } => ErrorCode::RateLimited,
+                Error::OutOfRange { .. } => ErrorCode::OutOfRange,
+                Error::LockedOut(_) => ErrorCode::LockedOut,
+                Error::Cancelled => ErrorCode::Cancelled,
+                Error::Offline(_) => ErrorCode::Offline,
+                Error::Unsupported { .. } => ErrorCode::Unsupported,
+            }
+        }
     }
 
     #[tarpc::service]
     pub trait SifisApi {
         // Lamp-specific API
+        /// Find every lamp, sorted by id.
         async fn find_lamps() -> Result<Vec<String>, Error>;
+        /// Find the lamps whose id matches a glob pattern, sorted by id.
+        ///
+        /// `*` matches any sequence of characters and `?` exactly one.
+        /// The match is against the device id, not its name.
+        async fn find_lamps_matching(pattern: String) -> Result<Vec<String>, Error>;
         /// Turns a light on.
         ///
+        /// The state is whether the light is on.
+        ///
         /// # Hazards
         /// * [Hazard::Fire]
         /// * [Hazard::LogEnergyConsumption]
         /// * [Hazard::EnergyConsumption]
-        async fn turn_lamp_on(id: String) -> Result<bool, Error>;
+        /// * [Hazard::PowerOutage]
+        async fn turn_lamp_on(id: String) -> Result<ToggleResult, Error>;
         /// Turns a light off.
         ///
+        /// The state is whether the light is on.
+        ///
         /// # Hazards
         /// * [Hazard::LogEnergyConsumption]
-        async fn turn_lamp_off(id: String) -> Result<bool, Error>;
+        async fn turn_lamp_off(id: String) -> Result<ToggleResult, Error>;
         /// Get the current on/off status for a light
         async fn get_lamp_on_off(id: String) -> Result<bool, Error>;
         /// Change the brightness.
         ///
+        /// A brightness above 100 or the lamp maximum is clamped to it, the
+        /// answer tells the brightness actually set.
+        ///
+        /// # Hazards
+        /// * [Hazard::Fire]
+        /// * [Hazard::LogEnergyConsumption]
+        /// * [Hazard::EnergyConsumption]
+        /// * [Hazard::PowerOutage]
+        async fn set_lamp_brightness(id: String, brightness: u8) -> Result<Clamped<u8>, Error>;
+        /// Change the brightness by `delta`, saturating at 0 and at the lamp
+        /// maximum, and return the new brightness.
+        ///
+        /// Unlike [SifisApi::set_lamp_brightness] the answer is not a
+        /// [Clamped]: saturating is the expected outcome of a delta, and the
+        /// requested value may not even fit a `u8`.
+        ///
+        /// # Hazards
+        /// * [Hazard::Fire]
+        /// * [Hazard::LogEnergyConsumption]
+        /// * [Hazard::EnergyConsumption]
+        /// * [Hazard::PowerOutage]
+        async fn adjust_lamp_brightness(id: String, delta: i16) -> Result<u8, Error>;
+        /// Change the brightness gradually over `duration_ms` milliseconds.
+        ///
+        /// The ramp stops where it is if the request is cancelled or its
+        /// deadline expires, the returned value is the brightness reached.
+        ///
         /// # Hazards
         /// * [Hazard::Fire]
         /// * [Hazard::LogEnergyConsumption]
         /// * [Hazard::EnergyConsumption]
-        async fn set_lamp_brightness(id: String, brightness: u8) -> Result<u8, Error>;
+        /// * [Hazard::PowerOutage]
+        async fn ramp_lamp_brightness(
+            id: String,
+            brightness: u8,
+            duration_ms: u32,
+        ) -> Result<u8, Error>;
+        /// Wait up to `timeout_ms` for the brightness to be `target`, e.g. at
+        /// the end of a ramp.
+        ///
+        /// Returns false if the time runs out first.
+        async fn wait_for_lamp_brightness(
+            id: String,
+            target: u8,
+            timeout_ms: u32,
+        ) -> Result<bool, Error>;
         /// Get the current brightness level.
         async fn get_lamp_brightness(id: String) -> Result<u8, Error>;
+        /// Change the brightness with the resolution of the professional
+        /// dimmers, within [crate::Lamp::BRIGHTNESS_HI].
+        ///
+        /// It is the brightness of [SifisApi::set_lamp_brightness] in finer
+        /// steps, see [crate::Lamp::brightness_to_hi]. A brightness above the
+        /// lamp maximum is clamped to it, the answer tells the brightness
+        /// actually set.
+        ///
+        /// # Hazards
+        /// * [Hazard::Fire]
+        /// * [Hazard::LogEnergyConsumption]
+        /// * [Hazard::EnergyConsumption]
+        /// * [Hazard::PowerOutage]
+        async fn set_lamp_brightness_hi(id: String, brightness: u16)
+            -> Result<Clamped<u16>, Error>;
+        /// Get the current brightness level, within
+        /// [crate::Lamp::BRIGHTNESS_HI].
+        async fn get_lamp_brightness_hi(id: String) -> Result<u16, Error>;
+        /// Get the highest brightness level allowed, if the lamp has a cap.
+        async fn get_lamp_max_brightness(id: String) -> Result<Option<u8>, Error>;
+        /// Get the white color temperature in kelvin, if the lamp is
+        /// tunable.
+        async fn get_lamp_color_temp(id: String) -> Result<Option<u16>, Error>;
+        /// Set the white color temperature in kelvin, within
+        /// [crate::Lamp::COLOR_TEMPERATURE].
+        ///
+        /// Fails with [Error::Mismatch] if the lamp is not tunable and with
+        /// [Error::OutOfRange] outside of the range.
+        async fn set_lamp_color_temp(id: String, kelvin: u16) -> Result<u16, Error>;
+        /// Set the on status, brightness and color temperature at once,
+        /// returning the new state.
+        ///
+        /// Every field is checked before any is applied, the lamp is left
+        /// untouched if one is refused. The brightness is capped as in
+        /// [SifisApi::set_lamp_brightness], the cap itself is kept and a
+        /// missing color temperature leaves the current one.
+        ///
+        /// The answer is the whole state applied rather than a [Clamped] per
+        /// field, a capped brightness differs from the one requested.
+        ///
+        /// # Hazards
+        /// * [Hazard::Fire]
+        /// * [Hazard::LogEnergyConsumption]
+        /// * [Hazard::EnergyConsumption]
+        /// * [Hazard::PowerOutage]
+        async fn set_lamp_state(id: String, state: LampState) -> Result<LampState, Error>;
+        /// Get the estimated power drawn by the lamp, in watts.
+        ///
+        /// # Hazards
+        /// * [Hazard::LogEnergyConsumption]
+        async fn get_lamp_power(id: String) -> Result<f32, Error>;
 
         // Sink-specific API
+        /// Find every sink, sorted by id.
         async fn find_sinks() -> Result<Vec<String>, Error>;
         /// Change the water flow.
         ///
+        /// A flow above [crate::Sink::FLOW] is clamped to it, the answer
+        /// tells the flow actually set.
+        ///
+        /// # Hazards
+        /// * [Hazard::Flood]
+        async fn set_sink_flow(id: String, flow: u8) -> Result<Clamped<u8>, Error>;
+        /// Change the water flow by `delta`, saturating within
+        /// [crate::Sink::FLOW], and return the new flow.
+        ///
+        /// As for [SifisApi::adjust_lamp_brightness] the answer is not a
+        /// [Clamped], saturating is the expected outcome of a delta.
+        ///
         /// # Hazards
         /// * [Hazard::Flood]
-        async fn set_sink_flow(id: String, flow: u8) -> Result<u8, Error>;
+        async fn adjust_sink_flow(id: String, delta: i16) -> Result<u8, Error>;
         /// Get the current water flow status
         async fn get_sink_flow(id: String) -> Result<u8, Error>;
         /// Set the sink the temperature
         ///
         /// # Hazard
         /// * [Hazard::Scald]
+        ///
+        /// A temperature above the sink limit is refused with
+        /// [Error::Forbidden], retriable with [SifisApi::set_sink_temp_forced].
         async fn set_sink_temp(id: String, temp: u8) -> Result<u8, Error>;
+        /// Set the water temperature even above the sink limit, once the
+        /// user acknowledged the risk.
+        ///
+        /// # Hazard
+        /// * [Hazard::Scald]
+        async fn set_sink_temp_forced(id: String, temp: u8) -> Result<u8, Error>;
         /// Get the current water temperature.
         async fn get_sink_temp(id: String) -> Result<u8, Error>;
+        /// Get the highest water temperature the sink accepts.
+        async fn get_sink_max_temp(id: String) -> Result<u8, Error>;
+        /// Set the highest water temperature the sink accepts, returning it.
+        ///
+        /// # Hazard
+        /// * [Hazard::Scald]
+        ///
+        /// Water hotter than the new limit is cooled down to it. Raising the
+        /// limit is put to the hazard policy of the runtime and refused with
+        /// [Error::Forbidden] unless it allows it.
+        async fn set_sink_max_temp(id: String, max_temp: u8) -> Result<u8, Error>;
         /// Close the drain
         ///
         /// let the water level in the sink rise.
@@ -100,36 +368,396 @@ pub mod service {
         async fn close_sink_drain(id: String) -> Result<bool, Error>;
         /// Open the drain, emptying the sink.
         async fn open_sink_drain(id: String) -> Result<bool, Error>;
+        /// Stop the water and open the drain at once, the safe state
+        /// [SifisApi::all_off] leaves the sink in.
+        ///
+        /// Like it, it is never rate limited.
+        async fn stop_sink(id: String) -> Result<(), Error>;
+        /// Get whether the drain is open.
+        async fn get_sink_drain(id: String) -> Result<bool, Error>;
         /// Get the water level in the sink.
         async fn get_sink_level(id: String) -> Result<u8, Error>;
 
         // Door-specific API
+        /// Find every door, sorted by id.
         async fn find_doors() -> Result<Vec<String>, Error>;
         /// Get the lock status of a door.
         async fn get_door_lock_status(id: String) -> Result<DoorLockStatus, Error>;
         /// Get the open status of a door.
         async fn get_door_open(id: String) -> Result<bool, Error>;
         /// Lock a door.
-        async fn lock_door(id: String) -> Result<bool, Error>;
+        ///
+        /// Moving the lock may jam it.
+        async fn lock_door(id: String) -> Result<LockResult, Error>;
+        /// Lock a door, trying again up to `attempts` times if it is jammed.
+        ///
+        /// Returns false if the lock is still jammed after the last attempt,
+        /// or if the door is open.
+        async fn lock_door_retry(id: String, attempts: u8) -> Result<bool, Error>;
         /// Unlock a door.
-        async fn unlock_door(id: String) -> Result<bool, Error>;
+        ///
+        /// Moving the lock may jam it.
+        async fn unlock_door(id: String) -> Result<LockResult, Error>;
+        /// Open or close a door, returning the new open status.
+        ///
+        /// A locked door cannot be opened, it fails with
+        /// [Error::Forbidden] for [Hazard::Intrusion] until it is unlocked.
+        /// A simulation hook, only a mock runtime built with the `testing`
+        /// feature serves it, the others answer [Error::Unsupported].
+        async fn set_door_open(id: String, open: bool) -> Result<bool, Error>;
 
         // Fridge-specific API
+        /// Find every fridge, sorted by id.
         async fn find_fridges() -> Result<Vec<String>, Error>;
         /// Get the current temperature of the fridge.
         async fn get_fridge_temperature(id: String) -> Result<i8, Error>;
         /// Get the target temperature of the fridge.
         async fn get_fridge_target_temperature(id: String) -> Result<i8, Error>;
         /// Set the target temperature of the fridge.
+        ///
+        /// Fails with [Error::OutOfRange] outside of the targets the runtime
+        /// accepts, see [SifisApi::get_property_range].
         async fn set_fridge_target_temperature(
             id: String,
             target_temperature: i8,
         ) -> Result<i8, Error>;
+        /// Change the target temperature of the fridge by `delta`,
+        /// saturating within the targets the runtime accepts, and return the
+        /// new target.
+        async fn adjust_fridge_target_temperature(id: String, delta: i16) -> Result<i8, Error>;
         /// Get the open status of the fridge.
         async fn get_fridge_open(id: String) -> Result<bool, Error>;
+        /// Get for how long the fridge door has been open, in seconds.
+        ///
+        /// Returns `None` if the door is closed.
+        async fn get_fridge_open_duration(id: String) -> Result<Option<u64>, Error>;
+        /// Open or close the fridge door, returning the new open status.
+        ///
+        /// A simulation hook, only a mock runtime built with the `testing`
+        /// feature serves it, the others answer [Error::Unsupported].
+        async fn set_fridge_open(id: String, open: bool) -> Result<bool, Error>;
+
+        // Curtain-specific API
+        /// Find every curtain, sorted by id.
+        async fn find_curtains() -> Result<Vec<String>, Error>;
+        /// Open a curtain.
+        ///
+        /// Opening an open curtain does nothing.
+        async fn open_curtain(id: String) -> Result<bool, Error>;
+        /// Close a curtain.
+        ///
+        /// Closing a closed curtain does nothing.
+        async fn close_curtain(id: String) -> Result<bool, Error>;
+        /// Get the open status of a curtain.
+        async fn get_curtain_open(id: String) -> Result<bool, Error>;
+
+        // Whole-home API
+        /// Drive every actuatable device to its safe state.
+        ///
+        /// Lamps are turned off, sinks stop the water flow and open the drain.
+        /// A device that cannot be reached or is locked out is reported with
+        /// its error instead of aborting the operation, so calling it
+        /// repeatedly is safe. The devices are reported sorted by id.
+        async fn all_off() -> Result<Vec<(String, Result<(), Error>)>, Error>;
+        /// Lock every door.
+        ///
+        /// Returns each door id along with whether it is now locked, a
+        /// jammed or locked out door is reported as `false` without stopping
+        /// the others. The doors are reported sorted by id.
+        async fn lock_all_doors() -> Result<Vec<(String, bool)>, Error>;
+        /// Get the estimated power drawn by all the devices, in watts.
+        ///
+        /// # Hazards
+        /// * [Hazard::LogEnergyConsumption]
+        async fn total_power() -> Result<f32, Error>;
+        /// Get the power drawn from the home circuit, in watts.
+        ///
+        /// Lighting a lamp or making it brighter is refused with
+        /// [Hazard::PowerOutage] if the load would exceed the breaker limit.
+        async fn get_circuit_load() -> Result<f32, Error>;
+        /// Get the state of every device at once.
+        async fn snapshot() -> Result<HomeSnapshot, Error>;
+        /// Set every device of `snapshot` back to the state it records, as
+        /// taken by [SifisApi::snapshot].
+        ///
+        /// The states are restored as they are, without the checks of the
+        /// single commands. Each device is reported with its outcome, one
+        /// that no longer exists, is of another kind or is locked out is
+        /// reported with its error without stopping the others. The devices
+        /// missing from the snapshot are left as they are.
+        async fn restore_snapshot(
+            snapshot: HomeSnapshot,
+        ) -> Result<Vec<(String, Result<(), Error>)>, Error>;
+
+        // Scenes API
+        /// Save `scene` under the name `name`, replacing the scene of the
+        /// same name if any.
+        ///
+        /// The scenes are not persisted, they are lost when the runtime
+        /// stops.
+        async fn save_scene(name: String, scene: HomeSnapshot) -> Result<(), Error>;
+        /// Run the scene saved as `name` at the time `at`, e.g. a morning
+        /// routine.
+        ///
+        /// A time already past runs it right away. The schedules are not
+        /// persisted, they are lost when the runtime stops.
+        async fn schedule_scene(name: String, at: SystemTime) -> Result<ScheduleId, Error>;
+        /// Cancel the scene scheduled as `id`, if it has not run yet.
+        async fn cancel_schedule(id: ScheduleId) -> Result<(), Error>;
+        /// Get the scenes waiting for their time, by id.
+        async fn list_schedules() -> Result<Vec<Schedule>, Error>;
+
+        // Administration API
+        /// Add a new device.
+        ///
+        /// Fails if a device with the same id already exists.
+        async fn add_device(id: String, spec: DeviceSpec) -> Result<(), Error>;
+        /// Remove a device.
+        async fn remove_device(id: String) -> Result<(), Error>;
+        /// Lock the device out for maintenance, or release it.
+        ///
+        /// While locked out, every command to the device fails with
+        /// [Error::LockedOut], the readings keep working.
+        async fn set_device_lockout(id: String, locked: bool) -> Result<(), Error>;
+        /// Get whether the device is locked out for maintenance.
+        async fn get_device_lockout(id: String) -> Result<bool, Error>;
+        /// Make the device reachable again, or simulate its failure.
+        ///
+        /// While offline, every operation on the device fails with
+        /// [Error::Offline] and [SifisApi::scan_health] reports it offline.
+        /// A simulation hook, only a mock runtime built with the `testing`
+        /// feature serves it, the others answer [Error::Unsupported].
+        async fn set_device_online(id: String, online: bool) -> Result<(), Error>;
+        /// Get the maintenance needs of the device, e.g. a low battery.
+        ///
+        /// The runtime records a [crate::Change::Maintenance] whenever they
+        /// cross a threshold.
+        async fn get_maintenance_status(id: String) -> Result<MaintenanceStatus, Error>;
+
+        // Generic API
+        /// Find every device along with its name and kind, sorted by id.
+        async fn find_all_devices() -> Result<Vec<DeviceInfo>, Error>;
+        /// Find the devices in the room `room`, sorted by id.
+        async fn find_devices_in_room(room: String) -> Result<Vec<DeviceInfo>, Error>;
+        /// Find the name of every room holding a device, sorted.
+        async fn list_rooms() -> Result<Vec<String>, Error>;
+        /// Probe every device and report its health, sorted by id.
+        ///
+        /// A device failing to answer is reported offline along with the
+        /// error instead of aborting the scan.
+        async fn scan_health() -> Result<Vec<DeviceHealth>, Error>;
+        /// Get the kind of the device `id`, as in [crate::DeviceKind::display].
+        async fn get_device_kind(id: String) -> Result<String, Error>;
+        /// Describe the properties and operations of the device.
+        async fn get_capabilities(id: String) -> Result<Capabilities, Error>;
+        /// Get the hazards the device `id` presents in its current state,
+        /// e.g. [Hazard::Flood] for a sink running with a closed drain.
+        async fn active_hazards(id: String) -> Result<Vec<Hazard>, Error>;
+        /// Call the operation `op` of the device `id`.
+        ///
+        /// The operations are named after the methods of the device wrappers,
+        /// e.g. `turn_on` for a [crate::Lamp]. `args` is a JSON array of the
+        /// operation arguments and the result is JSON as well.
+        ///
+        /// An operation the device kind does not support fails with
+        /// [Error::Mismatch].
+        async fn invoke(id: String, op: String, args: String) -> Result<String, Error>;
+        /// Read the property `property` of the device `id`, as named by its
+        /// [Capabilities], e.g. `brightness` for a [crate::Lamp].
+        ///
+        /// The result is JSON, as for [SifisApi::invoke]. A property the
+        /// device kind does not have fails with [Error::InvalidArgument].
+        async fn get_property(id: String, property: String) -> Result<String, Error>;
+        /// Set the property `property` of the device `id` to the JSON `value`,
+        /// answering with the value set.
+        ///
+        /// A property the device kind does not have, or that cannot be set
+        /// directly, fails with [Error::InvalidArgument].
+        async fn set_property(id: String, property: String, value: String)
+            -> Result<String, Error>;
+        /// Get the values the numeric property `property` of the device `id`
+        /// accepts, as listed by its [Capabilities].
+        ///
+        /// A property the device kind does not have, or that is not a whole
+        /// number, fails with [Error::InvalidArgument].
+        async fn get_property_range(id: String, property: String) -> Result<Range, Error>;
+        /// Describe the operation `name`, named after the method of this
+        /// trait, e.g. `turn_lamp_on`, with its hazards and arguments.
+        ///
+        /// An operation that is not a device one fails with
+        /// [Error::InvalidArgument].
+        async fn describe_operation(name: String) -> Result<OperationDoc, Error>;
+
+        // Diagnostics API
+        /// Exchange the protocol versions, the runtime answers with its
+        /// [PROTOCOL_VERSION].
+        ///
+        /// The clients call it first, before any other request.
+        async fn handshake(client_version: u32) -> Result<u32, Error>;
+        /// Describe the runtime along with the methods it serves.
+        ///
+        /// The methods it does not serve fail with [Error::Unsupported].
+        async fn server_info() -> Result<ServerInfo, Error>;
+        /// Check that the runtime is answering.
+        async fn ping() -> Result<(), Error>;
+        /// Get the runtime metrics in the Prometheus text exposition format.
+        ///
+        /// # Hazards
+        /// * [Hazard::LogEnergyConsumption]
+        async fn metrics_text() -> Result<String, Error>;
+        /// Get the whole internal state of the devices as JSON, indexed by
+        /// id, to inspect it in the tests and while debugging.
+        ///
+        /// Only a mock runtime built with the `debug-api` feature serves it,
+        /// the others answer [Error::Unsupported]. The layout follows its
+        /// internals and may change at any time.
+        async fn dump_state() -> Result<String, Error>;
+        /// Get the `limit` most recent device changes, oldest first.
+        ///
+        /// The runtime keeps a bounded history, older changes are forgotten.
+        async fn recent_events(limit: usize) -> Result<Vec<DeviceEvent>, Error>;
+        /// Wait up to `timeout_ms` for the changes following the position
+        /// `after` in the event log.
+        ///
+        /// Without `after` it answers at once with the current position and
+        /// no changes, to start following the log from there.
+        async fn wait_events(after: Option<u64>, timeout_ms: u32) -> Result<EventBatch, Error>;
+        /// As [SifisApi::wait_events], keeping only the changes of the
+        /// device `id`.
+        ///
+        /// The batch may be empty once a change of another device ends the
+        /// wait, `lagged` counts the forgotten changes of every device.
+        async fn wait_device_events(
+            id: String,
+            after: Option<u64>,
+            timeout_ms: u32,
+        ) -> Result<EventBatch, Error>;
+        /// Move the simulated time forward by `seconds`, running the
+        /// simulation ticks due meanwhile.
+        ///
+        /// Only a mock runtime built with the `testing` feature serves it, the
+        /// others answer [Error::Unsupported].
+        async fn advance_time(seconds: u64) -> Result<(), Error>;
+
+        // Scheduling API
+        /// Serve `request` with the priority `priority`, answering with its
+        /// response.
+        ///
+        /// The urgent requests waiting for a device go ahead of the routine
+        /// ones and are never rate limited.
+        async fn prioritized(
+            priority: Priority,
+            request: Box<SifisApiRequest>,
+        ) -> Result<Box<SifisApiResponse>, Error>;
     }
+
+    /// Name of every method of [SifisApi], in the order of the trait
+    ///
+    /// A method added to the trait is added here as well.
+    pub const METHODS: &[&str] = &[
+        // Lamp-specific API
+        "find_lamps",
+        "find_lamps_matching",
+        "turn_lamp_on",
+        "turn_lamp_off",
+        "get_lamp_on_off",
+        "set_lamp_brightness",
+        "adjust_lamp_brightness",
+        "ramp_lamp_brightness",
+        "wait_for_lamp_brightness",
+        "get_lamp_brightness",
+        "set_lamp_brightness_hi",
+        "get_lamp_brightness_hi",
+        "get_lamp_max_brightness",
+        "get_lamp_color_temp",
+        "set_lamp_color_temp",
+        "set_lamp_state",
+        "get_lamp_power",
+        // Sink-specific API
+        "find_sinks",
+        "set_sink_flow",
+        "adjust_sink_flow",
+        "get_sink_flow",
+        "set_sink_temp",
+        "set_sink_temp_forced",
+        "get_sink_temp",
+        "get_sink_max_temp",
+        "set_sink_max_temp",
+        "close_sink_drain",
+        "open_sink_drain",
+        "stop_sink",
+        "get_sink_drain",
+        "get_sink_level",
+        // Door-specific API
+        "find_doors",
+        "get_door_lock_status",
+        "get_door_open",
+        "lock_door",
+        "lock_door_retry",
+        "unlock_door",
+        "set_door_open",
+        // Fridge-specific API
+        "find_fridges",
+        "get_fridge_temperature",
+        "get_fridge_target_temperature",
+        "set_fridge_target_temperature",
+        "adjust_fridge_target_temperature",
+        "get_fridge_open",
+        "get_fridge_open_duration",
+        "set_fridge_open",
+        // Curtain-specific API
+        "find_curtains",
+        "open_curtain",
+        "close_curtain",
+        "get_curtain_open",
+        // Whole-home API
+        "all_off",
+        "lock_all_doors",
+        "total_power",
+        "get_circuit_load",
+        "snapshot",
+        "restore_snapshot",
+        // Scenes API
+        "save_scene",
+        "schedule_scene",
+        "cancel_schedule",
+        "list_schedules",
+        // Administration API
+        "add_device",
+        "remove_device",
+        "set_device_lockout",
+        "get_device_lockout",
+        "set_device_online",
+        "get_maintenance_status",
+        // Generic API
+        "find_all_devices",
+        "find_devices_in_room",
+        "list_rooms",
+        "scan_health",
+        "get_device_kind",
+        "get_capabilities",
+        "active_hazards",
+        "invoke",
+        "get_property",
+        "set_property",
+        "get_property_range",
+        "describe_operation",
+        // Diagnostics API
+        "handshake",
+        "server_info",
+        "ping",
+        "metrics_text",
+        "dump_state",
+        "recent_events",
+        "wait_events",
+        "wait_device_events",
+        "advance_time",
+        // Scheduling API
+        "prioritized",
+    ];
 }
 
+use cache::Cache;
 use service::SifisApiClient;
 
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
@@ -152,53 +780,585 @@ impl Display for DoorLockStatus {
     }
 }
 
+/// Outcome of locking or unlocking a door
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LockResult {
+    /// The lock moved to locked
+    Locked,
+    /// The lock moved to unlocked
+    Unlocked,
+    /// The lock is jammed, since before the operation or from moving
+    Jammed,
+    /// The door is open, the lock cannot be engaged
+    DoorOpen,
+    /// The lock was already in the requested state
+    AlreadyInState,
+}
+
+impl LockResult {
+    /// Whether the lock is in the requested state after the operation
+    pub fn is_success(self) -> bool {
+        matches!(self, Self::Locked | Self::Unlocked | Self::AlreadyInState)
+    }
+}
+
+impl Display for LockResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Locked => "locked",
+            Self::Unlocked => "unlocked",
+            Self::Jammed => "jammed",
+            Self::DoorOpen => "door open",
+            Self::AlreadyInState => "already in state",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Outcome of an operation driving a device to a state
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToggleResult {
+    /// The state after the operation, see the operation for its meaning
+    pub state: bool,
+    /// Whether the operation changed the device, false if it was already
+    /// in the requested state or could not reach it
+    pub changed: bool,
+}
+
+/// Outcome of a setter keeping the value within the limits of the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Clamped<T> {
+    /// The value requested
+    pub requested: T,
+    /// The value actually set
+    pub applied: T,
+}
+
+impl<T: PartialEq> Clamped<T> {
+    /// Whether the value set differs from the requested one
+    pub fn was_clamped(&self) -> bool {
+        self.requested != self.applied
+    }
+}
+
+/// Item of the [Sifis::events] stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HomeEvent {
+    /// The state of a device changed
+    Changed(DeviceEvent),
+    /// The device `id`, of kind `kind`, was added
+    Added { id: String, kind: String },
+    /// The device `id` was removed
+    Removed { id: String },
+    /// The maintenance needs of the device `id` crossed a threshold, e.g.
+    /// its battery ran low
+    Maintenance {
+        id: String,
+        status: MaintenanceStatus,
+    },
+    /// The given number of changes were missed, the consumer fell behind
+    /// the bounded runtime history
+    Lagged(u64),
+}
+
+impl From<DeviceEvent> for HomeEvent {
+    fn from(event: DeviceEvent) -> Self {
+        match event.change {
+            Change::State => HomeEvent::Changed(event),
+            Change::Added { kind } => HomeEvent::Added {
+                id: event.device_id,
+                kind,
+            },
+            Change::Removed => HomeEvent::Removed {
+                id: event.device_id,
+            },
+            Change::Maintenance(status) => HomeEvent::Maintenance {
+                id: event.device_id,
+                status,
+            },
+        }
+    }
+}
+
 /// Error type
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Runtime error")]
     Runtime(#[from] service::Error),
     #[error("RPC error")]
-    Rpc(#[from] RpcError),
+    Rpc(RpcError),
     #[error("I/O error")]
     Io(#[from] std::io::Error),
     #[error("Device not found")]
     NotFound,
+    #[error(transparent)]
+    InvalidId(#[from] InvalidDeviceId),
+    #[error("Malformed JSON")]
+    Json(#[from] serde_json::Error),
+    #[error("Protocol version {server} of the runtime is incompatible with {client}")]
+    ProtocolMismatch { client: u32, server: u32 },
+}
+
+impl From<RpcError> for Error {
+    /// A request failing because of the transport, e.g. a frame longer than
+    /// the limit, is reported as [Error::Io] when the cause is known. A
+    /// closed connection is reported as [std::io::ErrorKind::NotConnected].
+    fn from(e: RpcError) -> Self {
+        if let RpcError::Shutdown = e {
+            return Error::Io(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "Connection to the runtime closed",
+            ));
+        }
+        let mut source = std::error::Error::source(&e);
+        while let Some(cause) = source {
+            if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+                return Error::Io(std::io::Error::new(io.kind(), io.to_string()));
+            }
+            source = cause.source();
+        }
+
+        Error::Rpc(e)
+    }
+}
+
+impl Error {
+    /// The stable code of this error.
+    ///
+    /// Returns `None` for transport, protocol and id validation errors, that
+    /// have no runtime counterpart.
+    pub fn code(&self) -> Option<service::ErrorCode> {
+        match self {
+            Error::Runtime(e) => Some(e.code()),
+            Error::NotFound => Some(service::ErrorCode::NotFound),
+            Error::Rpc(_)
+            | Error::Io(_)
+            | Error::InvalidId(_)
+            | Error::Json(_)
+            | Error::ProtocolMismatch { .. } => None,
+        }
+    }
+
+    /// How long to wait before commanding the device again, if it has been
+    /// commanded too recently.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Runtime(service::Error::RateLimited { retry_after_ms }) => {
+                Some(Duration::from_millis(*retry_after_ms))
+            }
+            _ => None,
+        }
+    }
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// Longest message accepted on a connection by default, in bytes
+///
+/// Both ends refuse longer messages, closing the connection. `SIFIS_MAX_FRAME_LENGTH`
+/// raises it for [Sifis::new] and the mock runtime.
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+/// Longest message accepted, from `SIFIS_MAX_FRAME_LENGTH` or
+/// [DEFAULT_MAX_FRAME_LENGTH]
+fn max_frame_length_from_env() -> usize {
+    std::env::var("SIFIS_MAX_FRAME_LENGTH")
+        .ok()
+        .and_then(|len| len.parse().ok())
+        .unwrap_or(DEFAULT_MAX_FRAME_LENGTH)
+}
+
+/// Consecutive unanswered pings after which the runtime is considered dead
+const KEEPALIVE_FAILURES: u32 = 3;
+
+/// Longest wait of a single request of [Sifis::events], within the default
+/// request deadline
+const EVENTS_WAIT: Duration = Duration::from_secs(5);
+
+/// Read a property of the device `id` through its getter operation `get`
+async fn read_property(client: Arc<SifisApiClient>, id: String, get: String) -> Result<Value> {
+    let r = client
+        .invoke(tarpc::context::current(), id, get, "[]".to_owned())
+        .await??;
+    Ok(serde_json::from_str(&r)?)
+}
+
+/// Follow the changes from the position `after`, only the ones of the device
+/// `id` if given
+///
+/// A failed request is yielded as the last item.
+fn follow(
+    client: Arc<SifisApiClient>,
+    after: u64,
+    id: Option<String>,
+) -> impl Stream<Item = Result<HomeEvent>> + Send {
+    let timeout_ms = EVENTS_WAIT.as_millis() as u32;
+
+    stream::unfold(Some((client, after, VecDeque::new())), move |state| {
+        let id = id.clone();
+        async move {
+            let (client, mut after, mut pending) = state?;
+            loop {
+                if let Some(event) = pending.pop_front() {
+                    return Some((Ok(event), Some((client, after, pending))));
+                }
+
+                let context = tarpc::context::current();
+                let batch = match &id {
+                    Some(id) => {
+                        client
+                            .wait_device_events(context, id.clone(), Some(after), timeout_ms)
+                            .await
+                    }
+                    None => client.wait_events(context, Some(after), timeout_ms).await,
+                };
+                let batch = batch
+                    .map_err(Error::from)
+                    .and_then(|r| r.map_err(Error::from));
+                let batch = match batch {
+                    Ok(batch) => batch,
+                    Err(e) => {
+                        tracing::warn!("Cannot follow the events: {e}");
+                        return Some((Err(e), None));
+                    }
+                };
+
+                if batch.lagged > 0 {
+                    pending.push_back(HomeEvent::Lagged(batch.lagged));
+                }
+                pending.extend(batch.events.into_iter().map(HomeEvent::from));
+                after = batch.next;
+            }
+        }
+    })
+}
+
+/// State of the connection to the runtime, see [Sifis::connection_events]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnectionState {
+    /// Exchanging requests with the runtime
+    Connected,
+    /// Closed for good, by [Sifis::shutdown], the keepalive or the runtime
+    Disconnected,
+}
+
+/// Publisher of the connection state, marking it disconnected once dropped
+/// along with the task dispatching the requests
+struct Dispatching(watch::Sender<ConnectionState>);
+
+impl Drop for Dispatching {
+    fn drop(&mut self) {
+        self.0.send_replace(ConnectionState::Disconnected);
+    }
+}
+
 /// Sifis client entry point
+///
+/// Cloning it is cheap, all the clones share the same connection.
+#[derive(Clone)]
 pub struct Sifis {
-    client: SifisApiClient,
+    client: Arc<SifisApiClient>,
+    /// Client of the connection itself, sending the requests as they are
+    base: Arc<SifisApiClient>,
+    cache: Cache,
+    /// Task exchanging the requests with the runtime
+    dispatch: Arc<Mutex<Option<JoinHandle<()>>>>,
+    /// State of the connection, published by the dispatching task
+    state: watch::Receiver<ConnectionState>,
+    /// Deadline of its own requests, in place of the tarpc default
+    timeout: Option<Duration>,
+    /// Priority of the requests, see [Sifis::with_priority]
+    priority: Priority,
+    /// Token aborting the requests once cancelled, see
+    /// [Sifis::with_cancellation]
+    cancel: Option<CancellationToken>,
 }
 
 impl Sifis {
+    /// Spawn the task dispatching the requests of `new_client`
+    fn spawn<D, E>(new_client: NewClient<SifisApiClient, D>) -> Sifis
+    where
+        D: Future<Output = std::result::Result<(), E>> + Send + 'static,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let NewClient { client, dispatch } = new_client;
+        let (publisher, state) = watch::channel(ConnectionState::Connected);
+        let dispatching = Dispatching(publisher);
+        // Aborting the task drops the publisher as well
+        let dispatch = tokio::spawn(async move {
+            let _dispatching = dispatching;
+            if let Err(e) = dispatch.await {
+                tracing::warn!("Connection broken: {e}");
+            }
+        });
+
+        let client = Arc::new(client);
+        Sifis {
+            base: client.clone(),
+            client,
+            cache: Cache::default(),
+            dispatch: Arc::new(Mutex::new(Some(dispatch))),
+            state,
+            timeout: None,
+            priority: Priority::Routine,
+            cancel: None,
+        }
+    }
+
+    /// Context of the requests made by the client itself
+    fn context(&self) -> tarpc::context::Context {
+        let mut ctx = tarpc::context::current();
+        if let Some(timeout) = self.timeout {
+            ctx.deadline = SystemTime::now() + timeout;
+        }
+        ctx
+    }
+
+    /// Check that the runtime speaks the same protocol version
+    async fn handshake(self) -> Result<Sifis> {
+        let client = service::PROTOCOL_VERSION;
+        let server = self.client.handshake(self.context(), client).await??;
+
+        if server != client {
+            return Err(Error::ProtocolMismatch { client, server });
+        }
+
+        Ok(self)
+    }
+
+    /// Connect to the runtime listening on the unix socket `path`.
+    ///
+    /// Fails with [Error::ProtocolMismatch] if the runtime speaks another
+    /// protocol version, a runtime predating the version exchange fails with
+    /// [Error::Rpc].
     pub async fn from_path(path: impl AsRef<Path>) -> Result<Sifis> {
-        let transport =
-            tarpc::serde_transport::unix::connect(path.as_ref(), Bincode::default).await?;
-        let client = SifisApiClient::new(Default::default(), transport).spawn();
+        Self::from_path_with_frame_limit(path, DEFAULT_MAX_FRAME_LENGTH).await
+    }
+
+    /// Connect as in [Sifis::from_path], accepting messages up to
+    /// `max_frame_length` bytes.
+    ///
+    /// A request or an answer over the limit fails, with [Error::Io] when
+    /// the transport reports the cause, and closes the connection.
+    pub async fn from_path_with_frame_limit(
+        path: impl AsRef<Path>,
+        max_frame_length: usize,
+    ) -> Result<Sifis> {
+        let mut connect = tarpc::serde_transport::unix::connect(path.as_ref(), Bincode::default);
+        connect.config_mut().max_frame_length(max_frame_length);
+        let transport = connect.await?;
+
+        Self::spawn(SifisApiClient::new(Default::default(), transport))
+            .handshake()
+            .await
+    }
+
+    /// Connect over TLS to the runtime listening at `addr`, given as
+    /// `host:port`.
+    ///
+    /// The server certificate must be valid for `host` according to
+    /// `config`, a failed handshake is reported as [Error::Io]. The protocol
+    /// versions are checked as in [Sifis::from_path].
+    #[cfg(feature = "tls")]
+    pub async fn from_tls(addr: &str, config: Arc<tls::rustls::ClientConfig>) -> Result<Sifis> {
+        let stream = tls::connect(addr, config).await?;
+        let transport = tarpc::serde_transport::new(tls::framed(stream), Bincode::default());
+
+        Self::spawn(SifisApiClient::new(Default::default(), transport))
+            .handshake()
+            .await
+    }
+
+    /// Connect to `backend` in-process, without any I/O.
+    ///
+    /// The connection is closed when a call drops it on purpose, see
+    /// [runtime::InMemoryBackend::with_drop_rate].
+    ///
+    /// It must be called from within a tokio runtime.
+    #[cfg(feature = "testing")]
+    pub fn in_process(backend: runtime::InMemoryBackend) -> Sifis {
+        use tarpc::server::{BaseChannel, Channel};
+
+        let (client_transport, server_transport) = tarpc::transport::channel::unbounded();
+        let hangup = CancellationToken::new();
+        let serve = backend.with_hangup(hangup.clone()).serve_metered();
+        tokio::spawn(async move {
+            // Dropping the channel closes the connection, before the answer
+            // to the call that dropped it is sent
+            tokio::select! {
+                biased;
+                _ = hangup.cancelled() => {}
+                _ = BaseChannel::with_defaults(server_transport).execute(serve) => {}
+            }
+        });
+
+        Self::spawn(SifisApiClient::new(Default::default(), client_transport))
+    }
+
+    /// Close the connection to the runtime right away.
+    ///
+    /// The clones of this client and the device handles obtained from them
+    /// stop working as well, their requests fail with [Error::Io] of kind
+    /// [std::io::ErrorKind::NotConnected].
+    ///
+    /// Dropping every clone and device handle closes the connection too,
+    /// once the requests in flight are answered.
+    pub async fn shutdown(self) {
+        let dispatch = self.dispatch.lock().unwrap().take();
+        if let Some(dispatch) = dispatch {
+            dispatch.abort();
+            let _ = dispatch.await;
+        }
+    }
+
+    /// Ping the runtime every `interval` and disconnect if it stops answering.
+    ///
+    /// A runtime that misses a few pings in a row is considered dead, the
+    /// connection is closed as by [Sifis::shutdown] so the requests fail at
+    /// once with [Error::Io] instead of waiting for their deadline. [Sifis::is_connected]
+    /// tells whether it happened.
+    pub fn with_keepalive(self, interval: Duration) -> Sifis {
+        let client = Arc::downgrade(&self.client);
+        let dispatch = self.dispatch.clone();
+
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            let mut failures = 0;
+
+            loop {
+                ticks.tick().await;
+                // Every client handle is gone
+                let Some(client) = client.upgrade() else {
+                    break;
+                };
+
+                let mut ctx = tarpc::context::current();
+                ctx.deadline = SystemTime::now() + interval;
+                match client.ping(ctx).await {
+                    Ok(Ok(())) => failures = 0,
+                    _ => failures += 1,
+                }
+
+                if failures == KEEPALIVE_FAILURES {
+                    tracing::warn!("The runtime is not answering, disconnecting");
+                    if let Some(dispatch) = dispatch.lock().unwrap().take() {
+                        dispatch.abort();
+                    }
+                    break;
+                }
+            }
+        });
+
+        self
+    }
+
+    /// Whether the connection to the runtime is still open.
+    pub fn is_connected(&self) -> bool {
+        self.dispatch
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|dispatch| !dispatch.is_finished())
+    }
+
+    /// Follow the state of the connection, e.g. to show a connectivity
+    /// banner.
+    ///
+    /// The stream yields the current state, then each change. The client
+    /// does not reconnect, the stream ends once the connection is closed.
+    pub fn connection_events(&self) -> impl Stream<Item = ConnectionState> + Unpin + Send {
+        let mut state = self.state.clone();
+        let current = *state.borrow_and_update();
+        let changes = stream::unfold(state, |mut state| async move {
+            state.changed().await.ok()?;
+            let current = *state.borrow_and_update();
+            Some((current, state))
+        });
+
+        stream::once(future::ready(current)).chain(changes).boxed()
+    }
+
+    /// Check that the runtime is answering.
+    pub async fn ping(&self) -> Result<()> {
+        self.client.ping(self.context()).await??;
+        Ok(())
+    }
+
+    /// Get the runtime metrics in the Prometheus text exposition format,
+    /// ready to be scraped.
+    ///
+    /// # Hazards
+    /// * [Hazard::LogEnergyConsumption]
+    pub async fn metrics_text(&self) -> Result<String> {
+        let r = self.client.metrics_text(self.context()).await??;
+        Ok(r)
+    }
+
+    /// Get the whole internal state of the devices of a mock runtime as
+    /// JSON, indexed by id.
+    ///
+    /// Meant for the tests to check the fields no getter reads, e.g. the
+    /// jam probability of a door lock.
+    #[cfg(feature = "debug-api")]
+    pub async fn dump_state(&self) -> Result<Value> {
+        let r = self.client.dump_state(self.context()).await??;
+        Ok(serde_json::from_str(&r)?)
+    }
+
+    /// Cache the values read from the devices for `ttl`.
+    ///
+    /// A cached value is returned without asking the runtime until it
+    /// expires or a change to the device is requested through this client,
+    /// changes made by other clients go unnoticed meanwhile.
+    ///
+    /// It applies to the devices looked up afterwards, the clones share the
+    /// same cache.
+    pub fn with_cache(mut self, ttl: Duration) -> Sifis {
+        self.cache = Cache::new(ttl);
+        self
+    }
+
+    /// Give up on the requests made by the client itself, like the device
+    /// lookups, after `timeout` instead of the default 10 seconds.
+    ///
+    /// A slow or hung runtime is then reported early with [Error::Rpc], the
+    /// requests of the device wrappers keep the default deadline.
+    pub fn with_timeout(mut self, timeout: Duration) -> Sifis {
+        self.timeout = Some(timeout);
+        self
+    }
 
-        Ok(Sifis { client })
+    /// Forget the cached values, the next reads reach the runtime.
+    pub fn clear_cache(&self) {
+        self.cache.clear();
     }
 
     /// Start the sifis client it will connect to the default unix socket
+    ///
+    /// The socket path is taken from `SIFIS_SERVER` and the message length
+    /// limit from `SIFIS_MAX_FRAME_LENGTH`, if set.
     pub async fn new() -> Result<Sifis> {
         let sifis_server =
             std::env::var("SIFIS_SERVER").unwrap_or("/var/run/sifis.sock".to_string());
-        Self::from_path(&sifis_server).await
+        Self::from_path_with_frame_limit(&sifis_server, max_frame_length_from_env()).await
     }
 
     /// Lookup for a Lamp with the specific id.
-    pub async fn lamp(&self, lamp_id: &str) -> Result<Lamp> {
+    pub async fn lamp(&self, lamp_id: impl Into<DeviceId>) -> Result<Lamp> {
+        let lamp_id = lamp_id.into();
+        lamp_id.validate()?;
+
         self.client
-            .find_lamps(tarpc::context::current())
+            .find_lamps(self.context())
             .await?
             .map(|lamps| {
                 lamps.into_iter().find_map(|id| {
-                    if lamp_id == id {
+                    if id == lamp_id.as_str() {
                         Some(Lamp {
-                            client: &self.client,
-                            id,
+                            client: self.client.clone(),
+                            cache: self.cache.clone(),
+                            id: id.into(),
                         })
                     } else {
                         None
@@ -208,18 +1368,87 @@ impl Sifis {
             .ok_or_else(|| Error::NotFound)
     }
 
-    /// Provide a list of the currently available Lamps.
+    /// Lookup for a Lamp, giving up after `timeout`.
+    ///
+    /// The other lookups honor [Sifis::with_timeout] the same way, e.g.
+    /// `sifis.clone().with_timeout(timeout).door(id)`.
+    pub async fn lamp_with_timeout(
+        &self,
+        lamp_id: impl Into<DeviceId>,
+        timeout: Duration,
+    ) -> Result<Lamp> {
+        self.clone().with_timeout(timeout).lamp(lamp_id).await
+    }
+
+    /// Get a handle to the Lamp `lamp_id` without looking it up.
+    ///
+    /// It saves the round-trip of [Sifis::lamp] when the id is known to be
+    /// valid. A missing device, or one of another kind, is only reported by
+    /// the first call on the handle, with [service::Error::NotFound] or
+    /// [service::Error::Mismatch].
+    pub fn lamp_unchecked(&self, lamp_id: impl Into<DeviceId>) -> Lamp {
+        Lamp {
+            client: self.client.clone(),
+            cache: self.cache.clone(),
+            id: lamp_id.into(),
+        }
+    }
+
+    /// Provide a list of the currently available Lamps, sorted by id.
     pub async fn lamps(&self) -> Result<Vec<Lamp>> {
+        let r = self.client.find_lamps(self.context()).await?.map(|lamps| {
+            lamps
+                .into_iter()
+                .map(|id| Lamp {
+                    client: self.client.clone(),
+                    cache: self.cache.clone(),
+                    id: id.into(),
+                })
+                .collect()
+        })?;
+        Ok(r)
+    }
+
+    /// Yield the currently available Lamps one at a time.
+    ///
+    /// The ids are requested on the first poll, the handles are only built
+    /// as the stream is consumed, so stopping early, e.g. with
+    /// [StreamExt::take_while], skips the rest. A failed request is yielded
+    /// as the only item.
+    pub fn lamps_stream(&self) -> impl Stream<Item = Result<Lamp>> + Unpin + Send {
+        let sifis = self.clone();
+        let lamps = async move {
+            let ids = sifis.client.find_lamps(sifis.context()).await??;
+            let lamps = stream::iter(ids).map(move |id| -> Result<Lamp> {
+                Ok(Lamp {
+                    client: sifis.client.clone(),
+                    cache: sifis.cache.clone(),
+                    id: id.into(),
+                })
+            });
+            Ok::<_, Error>(lamps)
+        };
+
+        stream::once(lamps).try_flatten().boxed()
+    }
+
+    /// Provide a list of the Lamps whose id matches the glob `pattern`,
+    /// sorted by id.
+    ///
+    /// `*` matches any sequence of characters and `?` exactly one, the
+    /// pattern is matched against the device id.
+    pub async fn lamps_matching(&self, pattern: &str) -> Result<Vec<Lamp>> {
         let r = self
             .client
-            .find_lamps(tarpc::context::current())
+            .find_lamps_matching(self.context(), pattern.to_owned())
             .await?
             .map(|lamps| {
                 lamps
                     .into_iter()
                     .map(|id| Lamp {
-                        client: &self.client,
-                        id,
+                        client: self.client.clone(),
+                        cache: self.cache.clone(),
+                        id: id.into(),
                     })
                     .collect()
             })?;
@@ -227,16 +1456,20 @@ impl Sifis {
     }
 
     /// Lookup for a Sink with the specific id.
-    pub async fn sink(&self, sink_id: &str) -> Result<Sink> {
+    pub async fn sink(&self, sink_id: impl Into<DeviceId>) -> Result<Sink> {
+        let sink_id = sink_id.into();
+        sink_id.validate()?;
+
         self.client
-            .find_sinks(tarpc::context::current())
+            .find_sinks(self.context())
             .await?
             .map(|sinks| {
                 sinks.into_iter().find_map(|id| {
-                    if sink_id == id {
+                    if id == sink_id.as_str() {
                         Some(Sink {
-                            client: &self.client,
-                            id,
+                            client: self.client.clone(),
+                            cache: self.cache.clone(),
+                            id: id.into(),
                         })
                     } else {
                         None
@@ -246,35 +1479,46 @@ impl Sifis {
             .ok_or_else(|| Error::NotFound)
     }
 
-    /// Provide a list of the currently available Sinks.
+    /// Get a handle to the Sink `sink_id` without looking it up, see
+    /// [Sifis::lamp_unchecked].
+    pub fn sink_unchecked(&self, sink_id: impl Into<DeviceId>) -> Sink {
+        Sink {
+            client: self.client.clone(),
+            cache: self.cache.clone(),
+            id: sink_id.into(),
+        }
+    }
+
+    /// Provide a list of the currently available Sinks, sorted by id.
     pub async fn sinks(&self) -> Result<Vec<Sink>> {
-        let r = self
-            .client
-            .find_sinks(tarpc::context::current())
-            .await?
-            .map(|sinks| {
-                sinks
-                    .into_iter()
-                    .map(|id| Sink {
-                        client: &self.client,
-                        id,
-                    })
-                    .collect()
-            })?;
+        let r = self.client.find_sinks(self.context()).await?.map(|sinks| {
+            sinks
+                .into_iter()
+                .map(|id| Sink {
+                    client: self.client.clone(),
+                    cache: self.cache.clone(),
+                    id: id.into(),
+                })
+                .collect()
+        })?;
         Ok(r)
     }
 
     /// Lookup for a Door with the specific id.
-    pub async fn door(&self, door_id: &str) -> Result<Door> {
+    pub async fn door(&self, door_id: impl Into<DeviceId>) -> Result<Door> {
+        let door_id = door_id.into();
+        door_id.validate()?;
+
         self.client
-            .find_doors(tarpc::context::current())
+            .find_doors(self.context())
             .await?
             .map(|doors| {
                 doors.into_iter().find_map(|id| {
-                    if door_id == id {
+                    if id == door_id.as_str() {
                         Some(Door {
-                            client: &self.client,
-                            id,
+                            client: self.client.clone(),
+                            cache: self.cache.clone(),
+                            id: id.into(),
                         })
                     } else {
                         None
@@ -284,35 +1528,46 @@ impl Sifis {
             .ok_or_else(|| Error::NotFound)
     }
 
-    /// Provide a list of the currently available Doors.
+    /// Get a handle to the Door `door_id` without looking it up, see
+    /// [Sifis::lamp_unchecked].
+    pub fn door_unchecked(&self, door_id: impl Into<DeviceId>) -> Door {
+        Door {
+            client: self.client.clone(),
+            cache: self.cache.clone(),
+            id: door_id.into(),
+        }
+    }
+
+    /// Provide a list of the currently available Doors, sorted by id.
     pub async fn doors(&self) -> Result<Vec<Door>> {
-        let r = self
-            .client
-            .find_doors(tarpc::context::current())
-            .await?
-            .map(|doors| {
-                doors
-                    .into_iter()
-                    .map(|id| Door {
-                        client: &self.client,
-                        id,
-                    })
-                    .collect()
-            })?;
+        let r = self.client.find_doors(self.context()).await?.map(|doors| {
+            doors
+                .into_iter()
+                .map(|id| Door {
+                    client: self.client.clone(),
+                    cache: self.cache.clone(),
+                    id: id.into(),
+                })
+                .collect()
+        })?;
         Ok(r)
     }
 
     /// Lookup for a Fridge with the specific id.
-    pub async fn fridge(&self, fridge_id: &str) -> Result<Fridge> {
+    pub async fn fridge(&self, fridge_id: impl Into<DeviceId>) -> Result<Fridge> {
+        let fridge_id = fridge_id.into();
+        fridge_id.validate()?;
+
         self.client
-            .find_fridges(tarpc::context::current())
+            .find_fridges(self.context())
             .await?
             .map(|fridges| {
                 fridges.into_iter().find_map(|id| {
-                    if fridge_id == id {
+                    if id == fridge_id.as_str() {
                         Some(Fridge {
-                            client: &self.client,
-                            id,
+                            client: self.client.clone(),
+                            cache: self.cache.clone(),
+                            id: id.into(),
                         })
                     } else {
                         None
@@ -322,112 +1577,867 @@ impl Sifis {
             .ok_or_else(|| Error::NotFound)
     }
 
-    /// Provide a list of the currently available Fridges.
+    /// Get a handle to the Fridge `fridge_id` without looking it up, see
+    /// [Sifis::lamp_unchecked].
+    pub fn fridge_unchecked(&self, fridge_id: impl Into<DeviceId>) -> Fridge {
+        Fridge {
+            client: self.client.clone(),
+            cache: self.cache.clone(),
+            id: fridge_id.into(),
+        }
+    }
+
+    /// Provide a list of the currently available Fridges, sorted by id.
     pub async fn fridges(&self) -> Result<Vec<Fridge>> {
         let r = self
             .client
-            .find_fridges(tarpc::context::current())
+            .find_fridges(self.context())
             .await?
             .map(|fridges| {
                 fridges
                     .into_iter()
                     .map(|id| Fridge {
-                        client: &self.client,
-                        id,
+                        client: self.client.clone(),
+                        cache: self.cache.clone(),
+                        id: id.into(),
                     })
                     .collect()
             })?;
         Ok(r)
     }
+
+    /// Lookup for a Curtain with the specific id.
+    pub async fn curtain(&self, curtain_id: impl Into<DeviceId>) -> Result<Curtain> {
+        let curtain_id = curtain_id.into();
+        curtain_id.validate()?;
+
+        self.client
+            .find_curtains(self.context())
+            .await?
+            .map(|curtains| {
+                curtains.into_iter().find_map(|id| {
+                    if id == curtain_id.as_str() {
+                        Some(Curtain {
+                            client: self.client.clone(),
+                            cache: self.cache.clone(),
+                            id: id.into(),
+                        })
+                    } else {
+                        None
+                    }
+                })
+            })?
+            .ok_or_else(|| Error::NotFound)
+    }
+
+    /// Get a handle to the Curtain `curtain_id` without looking it up, see
+    /// [Sifis::lamp_unchecked].
+    pub fn curtain_unchecked(&self, curtain_id: impl Into<DeviceId>) -> Curtain {
+        Curtain {
+            client: self.client.clone(),
+            cache: self.cache.clone(),
+            id: curtain_id.into(),
+        }
+    }
+
+    /// Provide a list of the currently available Curtains, sorted by id.
+    pub async fn curtains(&self) -> Result<Vec<Curtain>> {
+        let r = self
+            .client
+            .find_curtains(self.context())
+            .await?
+            .map(|curtains| {
+                curtains
+                    .into_iter()
+                    .map(|id| Curtain {
+                        client: self.client.clone(),
+                        cache: self.cache.clone(),
+                        id: id.into(),
+                    })
+                    .collect()
+            })?;
+        Ok(r)
+    }
+
+    /// Drive every actuatable device to its safe state.
+    ///
+    /// Returns the outcome for each device, a device that cannot be reached
+    /// does not abort the operation.
+    pub async fn all_off(&self) -> Result<Vec<(String, std::result::Result<(), service::Error>)>> {
+        let r = self.client.all_off(self.context()).await??;
+        self.cache.clear();
+        Ok(r)
+    }
+
+    /// Lock every door.
+    ///
+    /// Returns each door id along with whether it is now locked, a jammed
+    /// door is reported as `false` without stopping the others.
+    pub async fn lock_all_doors(&self) -> Result<Vec<(String, bool)>> {
+        let r = self.client.lock_all_doors(self.context()).await??;
+        self.cache.clear();
+        Ok(r)
+    }
+
+    /// Get the estimated power drawn by all the devices, in watts.
+    ///
+    /// # Hazards
+    /// * [Hazard::LogEnergyConsumption]
+    pub async fn total_power(&self) -> Result<f32> {
+        let r = self.client.total_power(self.context()).await??;
+        Ok(r)
+    }
+
+    /// Get the power drawn from the home circuit, in watts.
+    ///
+    /// The runtime refuses to light a lamp, or make it brighter, if the load
+    /// would exceed its breaker limit.
+    pub async fn circuit_load(&self) -> Result<f32> {
+        let r = self.client.get_circuit_load(self.context()).await??;
+        Ok(r)
+    }
+
+    /// Get the state of every device in a single request.
+    ///
+    /// Meant for dashboards, it replaces a request per device and property.
+    pub async fn snapshot(&self) -> Result<HomeSnapshot> {
+        let r = self.client.snapshot(self.context()).await??;
+        Ok(r)
+    }
+
+    /// Set every device of `snapshot` back to the state it records, e.g.
+    /// to restore a known configuration for a demo.
+    ///
+    /// Returns the outcome for each device, a device that cannot be restored
+    /// does not abort the operation.
+    pub async fn restore_snapshot(
+        &self,
+        snapshot: HomeSnapshot,
+    ) -> Result<Vec<(String, std::result::Result<(), service::Error>)>> {
+        let r = self
+            .client
+            .restore_snapshot(self.context(), snapshot)
+            .await??;
+        self.cache.clear();
+        Ok(r)
+    }
+
+    /// Add a new device to the runtime.
+    ///
+    /// Fails if a device with the same id already exists.
+    pub async fn add_device(&self, id: &str, spec: DeviceSpec) -> Result<()> {
+        self.client
+            .add_device(self.context(), id.to_owned(), spec)
+            .await??;
+        Ok(())
+    }
+
+    /// Remove a device from the runtime.
+    pub async fn remove_device(&self, id: &str) -> Result<()> {
+        self.client
+            .remove_device(self.context(), id.to_owned())
+            .await??;
+        self.cache.invalidate(id);
+        Ok(())
+    }
+
+    /// Lock the device `id` out for maintenance, or release it.
+    ///
+    /// While locked out, the commands to the device fail with
+    /// [service::Error::LockedOut], the readings keep working.
+    pub async fn set_lockout(&self, id: &str, locked: bool) -> Result<()> {
+        self.client
+            .set_device_lockout(self.context(), id.to_owned(), locked)
+            .await??;
+        Ok(())
+    }
+
+    /// Simulate the failure of the device `id` of a mock runtime, or make it
+    /// reachable again.
+    ///
+    /// While offline, the operations on the device fail with
+    /// [service::Error::Offline].
+    #[cfg(feature = "testing")]
+    pub async fn set_online(&self, id: &str, online: bool) -> Result<()> {
+        self.client
+            .set_device_online(self.context(), id.to_owned(), online)
+            .await??;
+        self.cache.invalidate(id);
+        Ok(())
+    }
+
+    /// Tell whether the device `id` is locked out for maintenance.
+    pub async fn is_locked_out(&self, id: &str) -> Result<bool> {
+        let r = self
+            .client
+            .get_device_lockout(self.context(), id.to_owned())
+            .await??;
+        Ok(r)
+    }
+
+    /// Get the maintenance needs of the device `id`, e.g. a low battery.
+    pub async fn maintenance_status(&self, id: &str) -> Result<MaintenanceStatus> {
+        let r = self
+            .client
+            .get_maintenance_status(self.context(), id.to_owned())
+            .await??;
+        Ok(r)
+    }
+
+    /// Provide the id, name and kind of every device, sorted by id.
+    pub async fn devices(&self) -> Result<Vec<DeviceInfo>> {
+        let r = self.client.find_all_devices(self.context()).await??;
+        Ok(r)
+    }
+
+    /// Provide the devices in the room `room`, sorted by id.
+    ///
+    /// An unknown room has no devices.
+    pub async fn devices_in_room(&self, room: &str) -> Result<Vec<DeviceInfo>> {
+        let r = self
+            .client
+            .find_devices_in_room(self.context(), room.to_owned())
+            .await??;
+        Ok(r)
+    }
+
+    /// List the rooms holding at least a device, sorted by name.
+    pub async fn rooms(&self) -> Result<Vec<String>> {
+        let r = self.client.list_rooms(self.context()).await??;
+        Ok(r)
+    }
+
+    /// Probe every device and report its health, sorted by id.
+    ///
+    /// A single request, the devices not answering are reported
+    /// [HealthStatus::Offline].
+    pub async fn scan_health(&self) -> Result<Vec<DeviceHealth>> {
+        let r = self.client.scan_health(self.context()).await??;
+        Ok(r)
+    }
+
+    /// Get the kind of the device `id`, e.g. `"Lamp"`.
+    pub async fn device_kind(&self, id: &str) -> Result<String> {
+        let r = self
+            .client
+            .get_device_kind(self.context(), id.to_owned())
+            .await??;
+        Ok(r)
+    }
+
+    /// Describe the properties and operations of the device `id`, as
+    /// accepted by [Sifis::invoke].
+    pub async fn capabilities(&self, id: &str) -> Result<Capabilities> {
+        let r = self
+            .client
+            .get_capabilities(self.context(), id.to_owned())
+            .await??;
+        Ok(r)
+    }
+
+    /// Call the operation `op` of the device `id` without knowing its kind.
+    ///
+    /// The operations are named after the methods of the device wrappers,
+    /// e.g. `invoke("lamp1", "set_brightness", vec![json!(50)])`.
+    pub async fn invoke(&self, id: &str, op: &str, args: Vec<Value>) -> Result<Value> {
+        let r = self
+            .client
+            .invoke(
+                self.context(),
+                id.to_owned(),
+                op.to_owned(),
+                Value::Array(args).to_string(),
+            )
+            .await??;
+        self.cache.clear();
+        Ok(serde_json::from_str(&r)?)
+    }
+
+    /// Read the property `property` of the device `id` without knowing its
+    /// kind, as named by [Sifis::capabilities].
+    ///
+    /// e.g. `get_property("lamp1", "brightness")`. A property the device kind
+    /// does not have fails with [service::ErrorCode::InvalidArgument].
+    pub async fn get_property(&self, id: &str, property: &str) -> Result<Value> {
+        let r = self
+            .client
+            .get_property(self.context(), id.to_owned(), property.to_owned())
+            .await??;
+        Ok(serde_json::from_str(&r)?)
+    }
+
+    /// Set the property `property` of the device `id` to `value` without
+    /// knowing its kind, returning the value set.
+    ///
+    /// e.g. `set_property("lamp1", "brightness", json!(50))`. Only the
+    /// properties with a setter in [Sifis::capabilities] can be set, the
+    /// others fail with [service::ErrorCode::InvalidArgument].
+    pub async fn set_property(&self, id: &str, property: &str, value: Value) -> Result<Value> {
+        let r = self
+            .client
+            .set_property(
+                self.context(),
+                id.to_owned(),
+                property.to_owned(),
+                value.to_string(),
+            )
+            .await??;
+        self.cache.clear();
+        Ok(serde_json::from_str(&r)?)
+    }
+
+    /// Get the values the numeric property `property` of the device `id`
+    /// accepts, to check a value before sending it.
+    ///
+    /// e.g. `property_range("lamp1", "brightness")` gives `0..=100`. A
+    /// property that is not a whole number fails with
+    /// [service::ErrorCode::InvalidArgument].
+    pub async fn property_range(&self, id: &str, property: &str) -> Result<Range> {
+        let r = self
+            .client
+            .get_property_range(self.context(), id.to_owned(), property.to_owned())
+            .await??;
+        Ok(r)
+    }
+
+    /// Describe the operation `op`, e.g. `turn_lamp_on`, to show it to the
+    /// user along with its hazards and arguments.
+    ///
+    /// It complements [Sifis::capabilities] with prose, see
+    /// [OperationDoc::of] for the same without a runtime.
+    pub async fn describe(&self, op: &str) -> Result<OperationDoc> {
+        let r = self
+            .client
+            .describe_operation(self.context(), op.to_owned())
+            .await??;
+        Ok(r)
+    }
+
+    /// Get the `limit` most recent device changes, oldest first.
+    pub async fn recent_events(&self, limit: usize) -> Result<Vec<DeviceEvent>> {
+        let r = self.client.recent_events(self.context(), limit).await??;
+        Ok(r)
+    }
+
+    /// Follow every device change made from now on.
+    ///
+    /// The runtime keeps a bounded history, a consumer too slow to keep up
+    /// gets a [HomeEvent::Lagged] in place of the changes it missed. The
+    /// stream ends if the connection breaks.
+    pub async fn events(&self) -> Result<impl Stream<Item = HomeEvent> + Unpin + Send> {
+        let start = self.client.wait_events(self.context(), None, 0).await??;
+
+        let events = follow(self.client.clone(), start.next, None)
+            .filter_map(|event| future::ready(event.ok()));
+
+        Ok(events.boxed())
+    }
+
+    /// Follow the maintenance needs crossing a threshold from now on, e.g.
+    /// a battery running low, each tagged with the id of its device.
+    ///
+    /// The needs missed by a consumer too slow to keep up are logged, see
+    /// [Sifis::maintenance_status] to read them. The stream ends if the
+    /// connection breaks.
+    pub async fn maintenance_events(
+        &self,
+    ) -> Result<impl Stream<Item = (String, MaintenanceStatus)> + Unpin + Send> {
+        let start = self.client.wait_events(self.context(), None, 0).await??;
+
+        let events = follow(self.client.clone(), start.next, None).filter_map(|event| {
+            let item = match event {
+                Ok(HomeEvent::Maintenance { id, status }) => Some((id, status)),
+                Ok(HomeEvent::Lagged(missed)) => {
+                    tracing::warn!("Missed {missed} changes, maintenance needs may be among them");
+                    None
+                }
+                _ => None,
+            };
+            future::ready(item)
+        });
+
+        Ok(events.boxed())
+    }
+
+    /// Follow the changes of the devices `ids` in a single stream, each
+    /// tagged with the id of its device.
+    ///
+    /// The changes are picked from the ones of the whole home, the ids of
+    /// missing devices are skipped with a warning. Only the changes of state
+    /// are yielded, a removed device stops yielding any. The stream ends with
+    /// an error if the connection breaks, the changes a consumer too slow to
+    /// keep up misses are logged.
+    pub async fn watch_many(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<impl Stream<Item = Result<(String, DeviceEvent)>> + Unpin + Send> {
+        let start = self.client.wait_events(self.context(), None, 0).await??;
+        let devices = self.devices().await?;
+        let ids: HashSet<_> = ids
+            .into_iter()
+            .filter(|id| {
+                let found = devices.iter().any(|device| device.id == *id);
+                if !found {
+                    tracing::warn!("Not watching {id}, no such device");
+                }
+                found
+            })
+            .collect();
+
+        let events = follow(self.client.clone(), start.next, None).filter_map(move |event| {
+            let item = match event {
+                Ok(HomeEvent::Changed(event)) if ids.contains(&event.device_id) => {
+                    Some(Ok((event.device_id.clone(), event)))
+                }
+                Ok(HomeEvent::Lagged(missed)) => {
+                    tracing::warn!(
+                        "Missed {missed} changes, the watched devices may be among them"
+                    );
+                    None
+                }
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            };
+            future::ready(item)
+        });
+
+        Ok(events.boxed())
+    }
+
+    /// Follow the value of the property `property` of the device `id`, as
+    /// named by [Sifis::capabilities].
+    ///
+    /// The stream yields the current value, then the new one after every
+    /// change, reading it with the getter the capabilities name. The runtime
+    /// only sends the changes of the device. The stream ends once the device is removed or
+    /// the connection breaks, a failed reading is yielded as an error.
+    pub async fn watch_property(
+        &self,
+        id: &str,
+        property: &str,
+    ) -> Result<impl Stream<Item = Result<Value>> + Unpin + Send> {
+        let get = self
+            .capabilities(id)
+            .await?
+            .properties
+            .into_iter()
+            .find(|p| p.name == property)
+            .map(|p| p.get)
+            .ok_or_else(|| {
+                service::Error::InvalidArgument(format!("{id} has no property {property}"))
+            })?;
+        let start = self
+            .client
+            .wait_device_events(self.context(), id.to_owned(), None, 0)
+            .await??;
+        let changes = follow(self.client.clone(), start.next, Some(id.to_owned()))
+            .take_while(|event| {
+                future::ready(!matches!(event, Ok(HomeEvent::Removed { .. }) | Err(_)))
+            })
+            .map(|_| ());
+
+        let client = self.client.clone();
+        let id = id.to_owned();
+        let values = stream::once(future::ready(()))
+            .chain(changes)
+            .then(move |()| read_property(client.clone(), id.clone(), get.clone()))
+            .scan(None, |last: &mut Option<Value>, value: Result<Value>| {
+                // A change to another property of the device reads the same value
+                let value = match value {
+                    Ok(value) if last.as_ref() == Some(&value) => None,
+                    Ok(value) => {
+                        *last = Some(value.clone());
+                        Some(Ok(value))
+                    }
+                    Err(e) => Some(Err(e)),
+                };
+                future::ready(Some(value))
+            })
+            .filter_map(future::ready);
+
+        Ok(values.boxed())
+    }
+
+    /// Move the simulated time of a mock runtime forward by `seconds`.
+    #[cfg(feature = "testing")]
+    pub async fn advance_time(&self, seconds: u64) -> Result<()> {
+        self.client.advance_time(self.context(), seconds).await??;
+        self.cache.clear();
+        Ok(())
+    }
+}
+
+/// Get the hazards the device `id` presents in its current state
+///
+/// Shared by the device wrappers, it is never cached since a stale answer
+/// could hide a hazard.
+async fn active_hazards(client: &SifisApiClient, id: &DeviceId) -> Result<Vec<Hazard>> {
+    let r = client
+        .active_hazards(tarpc::context::current(), id.clone().into())
+        .await??;
+    Ok(r)
 }
 
 /// A connected Lamp
-pub struct Lamp<'a> {
-    client: &'a SifisApiClient,
-    pub id: String,
+pub struct Lamp {
+    client: Arc<SifisApiClient>,
+    cache: Cache,
+    pub id: DeviceId,
 }
 
-impl Display for Lamp<'_> {
+impl Display for Lamp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Lamp - {}", self.id)
     }
 }
 
-impl<'a> Lamp<'a> {
+impl Lamp {
+    /// Brightness levels, in percent
+    pub const BRIGHTNESS: RangeInclusive<u8> = 0..=100;
+    /// Brightness levels of the high resolution dimmers, see
+    /// [Lamp::brightness_to_hi]
+    pub const BRIGHTNESS_HI: RangeInclusive<u16> = 0..=u16::MAX;
+    /// White color temperatures of the tunable lamps, from warm to cool, in
+    /// kelvin
+    pub const COLOR_TEMPERATURE: RangeInclusive<u16> = 2000..=6500;
+
     /// Turn on the lamp
     ///
+    /// The state is whether the lamp is on, it is unchanged if it already was.
+    ///
     /// # Hazards
     /// * [Hazard::Fire]
     /// * [Hazard::LogEnergyConsumption]
     /// * [Hazard::EnergyConsumption]
-    pub async fn turn_on(&self) -> Result<bool> {
+    /// * [Hazard::PowerOutage]
+    pub async fn turn_on(&self) -> Result<ToggleResult> {
         let r = self
             .client
-            .turn_lamp_on(tarpc::context::current(), self.id.clone())
+            .turn_lamp_on(tarpc::context::current(), self.id.clone().into())
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
     /// Turn off the lamp
     ///
+    /// The state is whether the lamp is on, it is unchanged if it already was
+    /// off.
+    ///
     /// # Hazards
     /// * [Hazard::LogEnergyConsumption]
-    pub async fn turn_off(&self) -> Result<bool> {
+    pub async fn turn_off(&self) -> Result<ToggleResult> {
         let r = self
             .client
-            .turn_lamp_off(tarpc::context::current(), self.id.clone())
+            .turn_lamp_off(tarpc::context::current(), self.id.clone().into())
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
-    /// Get the current on/off status for a light
-    pub async fn get_on_off(&self) -> Result<bool> {
+    /// Change the brightness by `delta`, saturating at 0 and at the lamp
+    /// maximum.
+    ///
+    /// The runtime reads and writes the brightness at once, returning the
+    /// new value.
+    ///
+    /// # Hazards
+    /// * [Hazard::Fire]
+    /// * [Hazard::LogEnergyConsumption]
+    /// * [Hazard::EnergyConsumption]
+    /// * [Hazard::PowerOutage]
+    pub async fn adjust_brightness(&self, delta: i16) -> Result<u8> {
         let r = self
             .client
-            .get_lamp_on_off(tarpc::context::current(), self.id.clone())
+            .adjust_lamp_brightness(tarpc::context::current(), self.id.clone().into(), delta)
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
+    /// Get the current on/off status for a light
+    pub async fn get_on_off(&self) -> Result<bool> {
+        self.cache
+            .get_or(&self.id, "get_lamp_on_off", async {
+                let r = self
+                    .client
+                    .get_lamp_on_off(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
     /// Get the current brightness level.
     pub async fn get_brightness(&self) -> Result<u8> {
+        self.cache
+            .get_or(&self.id, "get_lamp_brightness", async {
+                let r = self
+                    .client
+                    .get_lamp_brightness(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+    /// Get the current brightness level, within [Lamp::BRIGHTNESS_HI].
+    pub async fn get_brightness_hi(&self) -> Result<u16> {
+        self.cache
+            .get_or(&self.id, "get_lamp_brightness_hi", async {
+                let r = self
+                    .client
+                    .get_lamp_brightness_hi(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+    /// Get the estimated power drawn, in watts.
+    ///
+    /// # Hazards
+    /// * [Hazard::LogEnergyConsumption]
+    pub async fn power(&self) -> Result<f32> {
+        self.cache
+            .get_or(&self.id, "get_lamp_power", async {
+                let r = self
+                    .client
+                    .get_lamp_power(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+    /// Get the highest brightness level allowed, if the lamp has a cap.
+    pub async fn max_brightness(&self) -> Result<Option<u8>> {
+        self.cache
+            .get_or(&self.id, "get_lamp_max_brightness", async {
+                let r = self
+                    .client
+                    .get_lamp_max_brightness(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+    /// Get the white color temperature in kelvin, `None` if the lamp is not
+    /// tunable.
+    pub async fn color_temperature(&self) -> Result<Option<u16>> {
+        self.cache
+            .get_or(&self.id, "get_lamp_color_temp", async {
+                let r = self
+                    .client
+                    .get_lamp_color_temp(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+    /// Change the white color temperature, within
+    /// [Lamp::COLOR_TEMPERATURE].
+    ///
+    /// Fails with [service::Error::Mismatch] if the lamp is not tunable and
+    /// with [service::Error::OutOfRange] outside of the range.
+    pub async fn set_color_temperature(&self, kelvin: u16) -> Result<u16> {
         let r = self
             .client
-            .get_lamp_brightness(tarpc::context::current(), self.id.clone())
+            .set_lamp_color_temp(tarpc::context::current(), self.id.clone().into(), kelvin)
             .await??;
+        self.cache.invalidate(&self.id);
+        Ok(r)
+    }
+    /// Set the on status, brightness and color temperature in a single
+    /// request, no other client sees the lamp half way.
+    ///
+    /// Returns the state actually set, see
+    /// [SifisApi::set_lamp_state](service::SifisApi::set_lamp_state).
+    ///
+    /// # Hazards
+    /// * [Hazard::Fire]
+    /// * [Hazard::LogEnergyConsumption]
+    /// * [Hazard::EnergyConsumption]
+    /// * [Hazard::PowerOutage]
+    pub async fn set_state(&self, state: LampState) -> Result<LampState> {
+        let r = self
+            .client
+            .set_lamp_state(tarpc::context::current(), self.id.clone().into(), state)
+            .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
     /// Change the brightness.
     ///
+    /// Returns the brightness actually set along with the requested one, it
+    /// is lower if the request is above 100 or [Lamp::max_brightness].
+    ///
     /// # Hazards
     /// * [Hazard::Fire]
     /// * [Hazard::LogEnergyConsumption]
     /// * [Hazard::EnergyConsumption]
-    pub async fn set_brightness(&self, brightness: u8) -> Result<u8> {
+    /// * [Hazard::PowerOutage]
+    pub async fn set_brightness(&self, brightness: u8) -> Result<Clamped<u8>> {
         let r = self
             .client
-            .set_lamp_brightness(tarpc::context::current(), self.id.clone(), brightness)
+            .set_lamp_brightness(
+                tarpc::context::current(),
+                self.id.clone().into(),
+                brightness,
+            )
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
+    /// Change the brightness with the resolution of the professional
+    /// dimmers, within [Lamp::BRIGHTNESS_HI].
+    ///
+    /// Returns the brightness actually set along with the requested one, it
+    /// is lower if the request is above [Lamp::max_brightness].
+    ///
+    /// # Hazards
+    /// * [Hazard::Fire]
+    /// * [Hazard::LogEnergyConsumption]
+    /// * [Hazard::EnergyConsumption]
+    /// * [Hazard::PowerOutage]
+    pub async fn set_brightness_hi(&self, brightness: u16) -> Result<Clamped<u16>> {
+        let r = self
+            .client
+            .set_lamp_brightness_hi(
+                tarpc::context::current(),
+                self.id.clone().into(),
+                brightness,
+            )
+            .await??;
+        self.cache.invalidate(&self.id);
+        Ok(r)
+    }
+    /// High resolution brightness of the percentage `brightness`
+    ///
+    /// The percentages are spread over the whole [Lamp::BRIGHTNESS_HI] range,
+    /// as an 8-bit `b` would be by `b * 257`: `p` maps to `p * 65535 / 100`
+    /// rounded to the nearest, saturating at 100. Converting the result back
+    /// with [Lamp::brightness_from_hi] gives `p` again.
+    pub fn brightness_to_hi(brightness: u8) -> u16 {
+        let max = u32::from(u16::MAX);
+        let hi = (u32::from(brightness.min(*Lamp::BRIGHTNESS.end())) * max + 50) / 100;
+
+        hi as u16
+    }
+    /// Percentage of the high resolution `brightness`, rounded to the
+    /// nearest
+    pub fn brightness_from_hi(brightness: u16) -> u8 {
+        let max = u32::from(u16::MAX);
+        let percent = (u32::from(brightness) * 100 + max / 2) / max;
+
+        percent as u8
+    }
+    /// Change the brightness gradually over `duration`.
+    ///
+    /// Dropping the returned future stops the ramp where it is.
+    ///
+    /// # Hazards
+    /// * [Hazard::Fire]
+    /// * [Hazard::LogEnergyConsumption]
+    /// * [Hazard::EnergyConsumption]
+    /// * [Hazard::PowerOutage]
+    pub async fn ramp_brightness(&self, brightness: u8, duration: Duration) -> Result<u8> {
+        let mut ctx = tarpc::context::current();
+        ctx.deadline = SystemTime::now() + duration + Duration::from_secs(10);
+
+        let duration_ms = duration.as_millis().try_into().unwrap_or(u32::MAX);
+        let r = self
+            .client
+            .ramp_lamp_brightness(ctx, self.id.clone().into(), brightness, duration_ms)
+            .await??;
+        self.cache.invalidate(&self.id);
+        Ok(r)
+    }
+
+    /// Wait up to `timeout` for the brightness to be `target`.
+    ///
+    /// Returns false if the time runs out first. The runtime answers as soon
+    /// as the lamp gets there, e.g. from a [Lamp::ramp_brightness] of another
+    /// task.
+    pub async fn wait_for_brightness(&self, target: u8, timeout: Duration) -> Result<bool> {
+        let mut ctx = tarpc::context::current();
+        ctx.deadline = SystemTime::now() + timeout + Duration::from_secs(10);
+
+        let timeout_ms = timeout.as_millis().try_into().unwrap_or(u32::MAX);
+        let r = self
+            .client
+            .wait_for_lamp_brightness(ctx, self.id.clone().into(), target, timeout_ms)
+            .await??;
+        self.cache.invalidate(&self.id);
+        Ok(r)
+    }
+
+    /// Make sure the lamp is on or off as `on` says, at the brightness
+    /// `brightness` if any.
+    ///
+    /// Only the requests needed to get there are made: nothing is changed if
+    /// the lamp already matches. A brightness above [Lamp::max_brightness]
+    /// matches the cap. The brightness is set before turning the lamp on, so
+    /// it never shines at the previous one.
+    ///
+    /// Returns the state of the lamp afterwards.
+    ///
+    /// # Hazards
+    /// * [Hazard::Fire]
+    /// * [Hazard::LogEnergyConsumption]
+    /// * [Hazard::EnergyConsumption]
+    /// * [Hazard::PowerOutage]
+    pub async fn ensure(&self, on: bool, brightness: Option<u8>) -> Result<LampState> {
+        if let Some(brightness) = brightness {
+            let target = match self.max_brightness().await? {
+                Some(max) => brightness.min(max),
+                None => brightness,
+            };
+            if self.get_brightness().await? != target {
+                self.set_brightness(brightness).await?;
+            }
+        }
+        if self.get_on_off().await? != on {
+            if on {
+                self.turn_on().await?;
+            } else {
+                self.turn_off().await?;
+            }
+        }
+
+        Ok(LampState {
+            brightness: self.get_brightness_hi().await?,
+            on: self.get_on_off().await?,
+            max_brightness: self.max_brightness().await?,
+            color_temp_kelvin: self.color_temperature().await?,
+        })
+    }
+
+    /// Get the hazards the lamp presents in its current state.
+    pub async fn hazards(&self) -> Result<Vec<Hazard>> {
+        active_hazards(&self.client, &self.id).await
+    }
 }
 
 /// Connected water basin/sink
-pub struct Sink<'a> {
-    client: &'a SifisApiClient,
-    pub id: String,
+pub struct Sink {
+    client: Arc<SifisApiClient>,
+    cache: Cache,
+    pub id: DeviceId,
 }
 
-impl Display for Sink<'_> {
+impl Display for Sink {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Sink - {}", self.id)
     }
 }
 
-impl<'a> Sink<'a> {
+impl Sink {
+    /// Water flow levels, in percent
+    pub const FLOW: RangeInclusive<u8> = 0..=100;
+    /// Water temperatures, in Celsius degrees
+    pub const TEMPERATURE: RangeInclusive<u8> = 10..=80;
+
     /// Open the drain, emptying the sink.
     pub async fn open_drain(&self) -> Result<bool> {
         let r = self
             .client
-            .open_sink_drain(tarpc::context::current(), self.id.clone())
+            .open_sink_drain(tarpc::context::current(), self.id.clone().into())
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
     /// Close the drain
@@ -439,162 +2449,456 @@ impl<'a> Sink<'a> {
     pub async fn close_drain(&self) -> Result<bool> {
         let r = self
             .client
-            .close_sink_drain(tarpc::context::current(), self.id.clone())
+            .close_sink_drain(tarpc::context::current(), self.id.clone().into())
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
+    /// Stop the water and open the drain in a single step, e.g. when the
+    /// sink is about to overflow.
+    ///
+    /// It is never rate limited.
+    pub async fn stop(&self) -> Result<()> {
+        self.client
+            .stop_sink(tarpc::context::current(), self.id.clone().into())
+            .await??;
+        self.cache.invalidate(&self.id);
+        Ok(())
+    }
+    /// Get whether the drain is open.
+    pub async fn get_drain(&self) -> Result<bool> {
+        self.cache
+            .get_or(&self.id, "get_sink_drain", async {
+                let r = self
+                    .client
+                    .get_sink_drain(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
     /// Get the water level in the sink.
     pub async fn get_water_level(&self) -> Result<u8> {
+        self.cache
+            .get_or(&self.id, "get_sink_level", async {
+                let r = self
+                    .client
+                    .get_sink_level(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+    /// Change the water flow.
+    ///
+    /// Returns the flow actually set along with the requested one, it is
+    /// lower if the request is above [Sink::FLOW].
+    ///
+    /// # Hazards
+    /// * [Hazard::Flood]
+    pub async fn set_flow(&self, flow: u8) -> Result<Clamped<u8>> {
         let r = self
             .client
-            .get_sink_level(tarpc::context::current(), self.id.clone())
+            .set_sink_flow(tarpc::context::current(), self.id.clone().into(), flow)
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
-    /// Change the water flow.
+    /// Change the water flow by `delta`, saturating within [Sink::FLOW].
+    ///
+    /// The runtime reads and writes the flow at once, returning the new
+    /// value.
     ///
     /// # Hazards
     /// * [Hazard::Flood]
-    pub async fn set_flow(&self, brightness: u8) -> Result<u8> {
+    pub async fn adjust_flow(&self, delta: i16) -> Result<u8> {
         let r = self
             .client
-            .set_sink_flow(tarpc::context::current(), self.id.clone(), brightness)
+            .adjust_sink_flow(tarpc::context::current(), self.id.clone().into(), delta)
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
     /// Get the current water flow status
     pub async fn get_flow(&self) -> Result<u8> {
+        self.cache
+            .get_or(&self.id, "get_sink_flow", async {
+                let r = self
+                    .client
+                    .get_sink_flow(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+    /// Set the sink the temperature
+    ///
+    /// A temperature above [Sink::max_temperature] is refused.
+    ///
+    /// # Hazard
+    /// * [Hazard::Scald]
+    pub async fn set_temperature(&self, brightness: u8) -> Result<u8> {
         let r = self
             .client
-            .get_sink_flow(tarpc::context::current(), self.id.clone())
+            .set_sink_temp(
+                tarpc::context::current(),
+                self.id.clone().into(),
+                brightness,
+            )
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
-    /// Set the sink the temperature
+    /// Set the temperature even above [Sink::max_temperature].
+    ///
+    /// Meant to retry [Sink::set_temperature] once the user acknowledged the
+    /// risk, as told by the `retriable_with_ack` of the error.
     ///
     /// # Hazard
     /// * [Hazard::Scald]
-    pub async fn set_temperature(&self, brightness: u8) -> Result<u8> {
+    pub async fn set_temperature_forced(&self, temperature: u8) -> Result<u8> {
         let r = self
             .client
-            .set_sink_temp(tarpc::context::current(), self.id.clone(), brightness)
+            .set_sink_temp_forced(
+                tarpc::context::current(),
+                self.id.clone().into(),
+                temperature,
+            )
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
     /// Get the current water temperature.
     pub async fn get_temperature(&self) -> Result<u8> {
+        self.cache
+            .get_or(&self.id, "get_sink_temp", async {
+                let r = self
+                    .client
+                    .get_sink_temp(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+
+    /// Get the highest water temperature the sink accepts.
+    pub async fn max_temperature(&self) -> Result<u8> {
+        self.cache
+            .get_or(&self.id, "get_sink_max_temp", async {
+                let r = self
+                    .client
+                    .get_sink_max_temp(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+
+    /// Set the highest water temperature the sink accepts.
+    ///
+    /// # Hazard
+    /// * [Hazard::Scald]
+    ///
+    /// Water hotter than the new limit is cooled down to it. Raising the
+    /// limit may be refused by the runtime.
+    pub async fn set_max_temperature(&self, max_temperature: u8) -> Result<u8> {
         let r = self
             .client
-            .get_sink_temp(tarpc::context::current(), self.id.clone())
+            .set_sink_max_temp(
+                tarpc::context::current(),
+                self.id.clone().into(),
+                max_temperature,
+            )
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
+
+    /// Get the hazards the sink presents in its current state.
+    pub async fn hazards(&self) -> Result<Vec<Hazard>> {
+        active_hazards(&self.client, &self.id).await
+    }
 }
 
 /// Connected door
-pub struct Door<'a> {
-    client: &'a SifisApiClient,
-    pub id: String,
+pub struct Door {
+    client: Arc<SifisApiClient>,
+    cache: Cache,
+    pub id: DeviceId,
 }
 
-impl Display for Door<'_> {
+impl Display for Door {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Door - {}", self.id)
     }
 }
 
-impl<'a> Door<'a> {
+impl Door {
     /// Get the current open status.
     pub async fn is_open(&self) -> Result<bool> {
+        self.cache
+            .get_or(&self.id, "get_door_open", async {
+                let r = self
+                    .client
+                    .get_door_open(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+
+    /// Get the current lock status.
+    pub async fn lock_status(&self) -> Result<DoorLockStatus> {
+        self.cache
+            .get_or(&self.id, "get_door_lock_status", async {
+                let r = self
+                    .client
+                    .get_door_lock_status(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+
+    /// Try to lock the door.
+    ///
+    /// [LockResult::is_success] tells whether the door is locked afterwards,
+    /// the other outcomes tell why not.
+    pub async fn lock(&self) -> Result<LockResult> {
         let r = self
             .client
-            .get_door_open(tarpc::context::current(), self.id.clone())
+            .lock_door(tarpc::context::current(), self.id.clone().into())
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
 
-    /// Get the current lock status.
-    pub async fn lock_status(&self) -> Result<DoorLockStatus> {
+    /// Try to unlock the door.
+    ///
+    /// [LockResult::is_success] tells whether the door is unlocked
+    /// afterwards, it is not if the lock is jammed.
+    pub async fn unlock(&self) -> Result<LockResult> {
         let r = self
             .client
-            .get_door_lock_status(tarpc::context::current(), self.id.clone())
+            .unlock_door(tarpc::context::current(), self.id.clone().into())
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
 
-    /// Try to lock the door.
+    /// Try to lock the door, up to `attempts` times if the lock is jammed.
     ///
-    /// Returns false if the lock is jammed, true otherwise.
-    pub async fn lock(&self) -> Result<bool> {
+    /// Returns false if the lock is still jammed after the last attempt, or
+    /// if the door is open.
+    pub async fn lock_with_retries(&self, attempts: u8) -> Result<bool> {
         let r = self
             .client
-            .lock_door(tarpc::context::current(), self.id.clone())
+            .lock_door_retry(tarpc::context::current(), self.id.clone().into(), attempts)
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
 
-    /// Try to unlock the door.
+    /// Open or close the door, on a mock runtime built with the `testing`
+    /// feature.
     ///
-    /// Returns false if the lock is jammed, true otherwise.
-    pub async fn unlock(&self) -> Result<bool> {
+    /// Opening a locked door fails with [service::Error::Forbidden], it has
+    /// to be unlocked first. Returns the open status.
+    #[cfg(feature = "testing")]
+    pub async fn set_open(&self, open: bool) -> Result<bool> {
         let r = self
             .client
-            .unlock_door(tarpc::context::current(), self.id.clone())
+            .set_door_open(tarpc::context::current(), self.id.clone().into(), open)
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
+
+    /// Get the hazards the door presents in its current state.
+    pub async fn hazards(&self) -> Result<Vec<Hazard>> {
+        active_hazards(&self.client, &self.id).await
+    }
 }
 
-impl<'a> Fridge<'a> {
+impl Fridge {
+    /// Target temperatures, in Celsius degrees
+    ///
+    /// The runtime may accept a narrower range, see [Sifis::property_range].
+    pub const TARGET_TEMPERATURE: RangeInclusive<i8> = -20..=20;
+
     /// Get the current open status.
     pub async fn is_open(&self) -> Result<bool> {
+        self.cache
+            .get_or(&self.id, "get_fridge_open", async {
+                let r = self
+                    .client
+                    .get_fridge_open(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+
+    /// Get for how long the door has been open.
+    ///
+    /// Returns `None` if the door is closed.
+    pub async fn open_duration(&self) -> Result<Option<Duration>> {
         let r = self
             .client
-            .get_fridge_open(tarpc::context::current(), self.id.clone())
+            .get_fridge_open_duration(tarpc::context::current(), self.id.clone().into())
             .await??;
-        Ok(r)
+        Ok(r.map(Duration::from_secs))
     }
 
     /// Get the current temperature.
     pub async fn temperature(&self) -> Result<i8> {
-        let r = self
-            .client
-            .get_fridge_temperature(tarpc::context::current(), self.id.clone())
-            .await??;
-        Ok(r)
+        self.cache
+            .get_or(&self.id, "get_fridge_temperature", async {
+                let r = self
+                    .client
+                    .get_fridge_temperature(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
     }
 
     /// Get the target temperature.
     pub async fn target_temperature(&self) -> Result<i8> {
-        let r = self
-            .client
-            .get_fridge_target_temperature(tarpc::context::current(), self.id.clone())
-            .await??;
-        Ok(r)
+        self.cache
+            .get_or(&self.id, "get_fridge_target_temperature", async {
+                let r = self
+                    .client
+                    .get_fridge_target_temperature(
+                        tarpc::context::current(),
+                        self.id.clone().into(),
+                    )
+                    .await??;
+                Ok(r)
+            })
+            .await
     }
 
     /// Set the target temperature.
+    ///
+    /// Fails with [service::Error::OutOfRange] outside of the targets the
+    /// runtime accepts.
     pub async fn set_target_temperature(&self, target_temperature: i8) -> Result<i8> {
         let r = self
             .client
             .set_fridge_target_temperature(
                 tarpc::context::current(),
-                self.id.clone(),
+                self.id.clone().into(),
                 target_temperature,
             )
             .await??;
+        self.cache.invalidate(&self.id);
         Ok(r)
     }
+
+    /// Change the target temperature by `delta`, saturating within the
+    /// targets the runtime accepts.
+    ///
+    /// The runtime reads and writes the target at once, returning the new
+    /// value.
+    pub async fn adjust_target_temperature(&self, delta: i16) -> Result<i8> {
+        let r = self
+            .client
+            .adjust_fridge_target_temperature(
+                tarpc::context::current(),
+                self.id.clone().into(),
+                delta,
+            )
+            .await??;
+        self.cache.invalidate(&self.id);
+        Ok(r)
+    }
+
+    /// Open or close the door of a mock fridge.
+    ///
+    /// Returns the open status.
+    #[cfg(feature = "testing")]
+    pub async fn set_open(&self, open: bool) -> Result<bool> {
+        let r = self
+            .client
+            .set_fridge_open(tarpc::context::current(), self.id.clone().into(), open)
+            .await??;
+        self.cache.invalidate(&self.id);
+        Ok(r)
+    }
+
+    /// Get the hazards the fridge presents in its current state.
+    pub async fn hazards(&self) -> Result<Vec<Hazard>> {
+        active_hazards(&self.client, &self.id).await
+    }
 }
 
 /// Connected fridge
-pub struct Fridge<'a> {
-    client: &'a SifisApiClient,
-    pub id: String,
+pub struct Fridge {
+    client: Arc<SifisApiClient>,
+    cache: Cache,
+    pub id: DeviceId,
 }
 
-impl Display for Fridge<'_> {
+impl Display for Fridge {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Fridge - {}", self.id)
     }
 }
+
+/// Connected curtain, it can only be fully open or fully closed
+pub struct Curtain {
+    client: Arc<SifisApiClient>,
+    cache: Cache,
+    pub id: DeviceId,
+}
+
+impl Display for Curtain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Curtain - {}", self.id)
+    }
+}
+
+impl Curtain {
+    /// Get the current open status.
+    pub async fn is_open(&self) -> Result<bool> {
+        self.cache
+            .get_or(&self.id, "get_curtain_open", async {
+                let r = self
+                    .client
+                    .get_curtain_open(tarpc::context::current(), self.id.clone().into())
+                    .await??;
+                Ok(r)
+            })
+            .await
+    }
+
+    /// Open the curtain.
+    ///
+    /// Returns the open status, opening an open curtain does nothing.
+    pub async fn open(&self) -> Result<bool> {
+        let r = self
+            .client
+            .open_curtain(tarpc::context::current(), self.id.clone().into())
+            .await??;
+        self.cache.invalidate(&self.id);
+        Ok(r)
+    }
+
+    /// Close the curtain.
+    ///
+    /// Returns the open status, closing a closed curtain does nothing.
+    pub async fn close(&self) -> Result<bool> {
+        let r = self
+            .client
+            .close_curtain(tarpc::context::current(), self.id.clone().into())
+            .await??;
+        self.cache.invalidate(&self.id);
+        Ok(r)
+    }
+
+    /// Get the hazards the curtain presents in its current state.
+    pub async fn hazards(&self) -> Result<Vec<Hazard>> {
+        active_hazards(&self.client, &self.id).await
+    }
+}
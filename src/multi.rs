@@ -0,0 +1,105 @@
+//! Clients of several runtimes, e.g. one per zone of a large home
+//!
+//! The devices are named `zone/id`, the zone picks the runtime and the id is
+//! the one the runtime knows the device by.
+
+use std::collections::BTreeMap;
+
+use crate::{Curtain, DeviceInfo, Door, Error, Fridge, Lamp, Result, Sifis, Sink};
+
+/// Separator of the zone and the device id
+const ZONE_SEPARATOR: char = '/';
+
+/// Client routing the requests to the runtime of each zone
+///
+/// Cloning it is cheap, the clones share the same connections.
+#[derive(Clone, Default)]
+pub struct SifisMulti {
+    zones: BTreeMap<String, Sifis>,
+}
+
+impl SifisMulti {
+    /// A client of no runtime, see [SifisMulti::with_zone].
+    pub fn new() -> SifisMulti {
+        SifisMulti::default()
+    }
+
+    /// Reach the devices of the zone `zone` through `sifis`.
+    ///
+    /// The zone replaces any other of the same name, it must not contain
+    /// `/`.
+    pub fn with_zone(mut self, zone: impl Into<String>, sifis: Sifis) -> SifisMulti {
+        self.zones.insert(zone.into(), sifis);
+        self
+    }
+
+    /// The client of the zone `zone`, if any.
+    pub fn zone(&self, zone: &str) -> Option<&Sifis> {
+        self.zones.get(zone)
+    }
+
+    /// Names of the zones, sorted.
+    pub fn zones(&self) -> impl Iterator<Item = &str> {
+        self.zones.keys().map(String::as_str)
+    }
+
+    /// The client of the zone of `id` along with the id within the zone
+    ///
+    /// An id without a zone, or of an unknown zone, names no device.
+    fn route<'a>(&self, id: &'a str) -> Result<(&Sifis, &'a str)> {
+        let (zone, id) = id.split_once(ZONE_SEPARATOR).ok_or(Error::NotFound)?;
+        let sifis = self.zones.get(zone).ok_or(Error::NotFound)?;
+
+        Ok((sifis, id))
+    }
+
+    /// Lookup for a Lamp named `zone/id`.
+    ///
+    /// The handle is bound to the runtime of the zone, its id is the one
+    /// within the zone.
+    pub async fn lamp(&self, id: &str) -> Result<Lamp> {
+        let (sifis, id) = self.route(id)?;
+        sifis.lamp(id).await
+    }
+
+    /// Lookup for a Sink named `zone/id`.
+    pub async fn sink(&self, id: &str) -> Result<Sink> {
+        let (sifis, id) = self.route(id)?;
+        sifis.sink(id).await
+    }
+
+    /// Lookup for a Door named `zone/id`.
+    pub async fn door(&self, id: &str) -> Result<Door> {
+        let (sifis, id) = self.route(id)?;
+        sifis.door(id).await
+    }
+
+    /// Lookup for a Fridge named `zone/id`.
+    pub async fn fridge(&self, id: &str) -> Result<Fridge> {
+        let (sifis, id) = self.route(id)?;
+        sifis.fridge(id).await
+    }
+
+    /// Lookup for a Curtain named `zone/id`.
+    pub async fn curtain(&self, id: &str) -> Result<Curtain> {
+        let (sifis, id) = self.route(id)?;
+        sifis.curtain(id).await
+    }
+
+    /// Provide every device of every zone, its id being `zone/id`, sorted by
+    /// zone then by id.
+    ///
+    /// Fails if a runtime does.
+    pub async fn devices(&self) -> Result<Vec<DeviceInfo>> {
+        let mut res = Vec::new();
+
+        for (zone, sifis) in &self.zones {
+            res.extend(sifis.devices().await?.into_iter().map(|device| DeviceInfo {
+                id: format!("{zone}{ZONE_SEPARATOR}{}", device.id),
+                ..device
+            }));
+        }
+
+        Ok(res)
+    }
+}
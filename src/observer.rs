@@ -0,0 +1,436 @@
+//! Read-only access to the devices
+//!
+//! The observer wrappers only expose the getters, so a read-only component
+//! cannot change the devices by mistake. A full client is a [Sifis], the one
+//! [Sifis::observer] was called on or a new connection: an [Observer] cannot
+//! be turned back into one.
+
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+use futures::Stream;
+
+use crate::{
+    Capabilities, ConnectionState, Curtain, DeviceEvent, DeviceHealth, DeviceId, DeviceInfo, Door,
+    DoorLockStatus, Fridge, Hazard, HomeEvent, HomeSnapshot, Lamp, MaintenanceStatus, OperationDoc,
+    Range, Result, Schedule, Sifis, Sink,
+};
+
+impl Sifis {
+    /// Get a read-only client sharing this connection.
+    pub fn observer(&self) -> Observer {
+        Observer(self.clone())
+    }
+}
+
+/// Read-only client, obtained with [Sifis::observer]
+///
+/// Cloning it is cheap, all the clones share the same connection.
+#[derive(Clone)]
+pub struct Observer(Sifis);
+
+impl Observer {
+    /// Whether the connection to the runtime is still open.
+    pub fn is_connected(&self) -> bool {
+        self.0.is_connected()
+    }
+
+    /// Follow the state of the connection, as in [Sifis::connection_events].
+    pub fn connection_events(&self) -> impl Stream<Item = ConnectionState> + Unpin + Send {
+        self.0.connection_events()
+    }
+
+    /// Check that the runtime is answering.
+    pub async fn ping(&self) -> Result<()> {
+        self.0.ping().await
+    }
+
+    /// Observe a lamp.
+    pub async fn lamp(&self, lamp_id: impl Into<DeviceId>) -> Result<ObserverLamp> {
+        self.0.lamp(lamp_id).await.map(ObserverLamp)
+    }
+
+    /// Observe all the lamps.
+    pub async fn lamps(&self) -> Result<Vec<ObserverLamp>> {
+        let lamps = self.0.lamps().await?;
+        Ok(lamps.into_iter().map(ObserverLamp).collect())
+    }
+
+    /// Observe a sink.
+    pub async fn sink(&self, sink_id: impl Into<DeviceId>) -> Result<ObserverSink> {
+        self.0.sink(sink_id).await.map(ObserverSink)
+    }
+
+    /// Observe all the sinks.
+    pub async fn sinks(&self) -> Result<Vec<ObserverSink>> {
+        let sinks = self.0.sinks().await?;
+        Ok(sinks.into_iter().map(ObserverSink).collect())
+    }
+
+    /// Observe a door.
+    pub async fn door(&self, door_id: impl Into<DeviceId>) -> Result<ObserverDoor> {
+        self.0.door(door_id).await.map(ObserverDoor)
+    }
+
+    /// Observe all the doors.
+    pub async fn doors(&self) -> Result<Vec<ObserverDoor>> {
+        let doors = self.0.doors().await?;
+        Ok(doors.into_iter().map(ObserverDoor).collect())
+    }
+
+    /// Observe a fridge.
+    pub async fn fridge(&self, fridge_id: impl Into<DeviceId>) -> Result<ObserverFridge> {
+        self.0.fridge(fridge_id).await.map(ObserverFridge)
+    }
+
+    /// Observe all the fridges.
+    pub async fn fridges(&self) -> Result<Vec<ObserverFridge>> {
+        let fridges = self.0.fridges().await?;
+        Ok(fridges.into_iter().map(ObserverFridge).collect())
+    }
+
+    /// Observe a curtain.
+    pub async fn curtain(&self, curtain_id: impl Into<DeviceId>) -> Result<ObserverCurtain> {
+        self.0.curtain(curtain_id).await.map(ObserverCurtain)
+    }
+
+    /// Observe all the curtains.
+    pub async fn curtains(&self) -> Result<Vec<ObserverCurtain>> {
+        let curtains = self.0.curtains().await?;
+        Ok(curtains.into_iter().map(ObserverCurtain).collect())
+    }
+
+    /// Get the estimated power drawn by all the devices, in watts.
+    ///
+    /// # Hazards
+    /// * [Hazard::LogEnergyConsumption]
+    pub async fn total_power(&self) -> Result<f32> {
+        self.0.total_power().await
+    }
+
+    /// Get the runtime metrics in the Prometheus text exposition format.
+    ///
+    /// # Hazards
+    /// * [Hazard::LogEnergyConsumption]
+    pub async fn metrics_text(&self) -> Result<String> {
+        self.0.metrics_text().await
+    }
+
+    /// Get the power drawn from the home circuit, in watts.
+    pub async fn circuit_load(&self) -> Result<f32> {
+        self.0.circuit_load().await
+    }
+
+    /// Get the state of every device in a single request.
+    pub async fn snapshot(&self) -> Result<HomeSnapshot> {
+        self.0.snapshot().await
+    }
+
+    /// Get the scenes waiting for their time, by id.
+    pub async fn list_schedules(&self) -> Result<Vec<Schedule>> {
+        self.0.list_schedules().await
+    }
+
+    /// List every device along with its name and kind, sorted by id.
+    pub async fn devices(&self) -> Result<Vec<DeviceInfo>> {
+        self.0.devices().await
+    }
+
+    /// List the devices in the room `room`, sorted by id.
+    pub async fn devices_in_room(&self, room: &str) -> Result<Vec<DeviceInfo>> {
+        self.0.devices_in_room(room).await
+    }
+
+    /// List the rooms holding at least a device, sorted by name.
+    pub async fn rooms(&self) -> Result<Vec<String>> {
+        self.0.rooms().await
+    }
+
+    /// Probe every device and report its health, sorted by id.
+    pub async fn scan_health(&self) -> Result<Vec<DeviceHealth>> {
+        self.0.scan_health().await
+    }
+
+    /// Get the kind of the device `id`.
+    pub async fn device_kind(&self, id: &str) -> Result<String> {
+        self.0.device_kind(id).await
+    }
+
+    /// Describe the properties and operations of the device `id`.
+    pub async fn capabilities(&self, id: &str) -> Result<Capabilities> {
+        self.0.capabilities(id).await
+    }
+
+    /// Read the property `property` of the device `id` without knowing its
+    /// kind.
+    pub async fn get_property(&self, id: &str, property: &str) -> Result<serde_json::Value> {
+        self.0.get_property(id, property).await
+    }
+
+    /// Get the values the numeric property `property` of the device `id`
+    /// accepts.
+    pub async fn property_range(&self, id: &str, property: &str) -> Result<Range> {
+        self.0.property_range(id, property).await
+    }
+
+    /// Describe the operation `op`, e.g. `turn_lamp_on`.
+    pub async fn describe(&self, op: &str) -> Result<OperationDoc> {
+        self.0.describe(op).await
+    }
+
+    /// Tell whether the device `id` is locked out for maintenance.
+    pub async fn is_locked_out(&self, id: &str) -> Result<bool> {
+        self.0.is_locked_out(id).await
+    }
+
+    /// Get the maintenance needs of the device `id`.
+    pub async fn maintenance_status(&self, id: &str) -> Result<MaintenanceStatus> {
+        self.0.maintenance_status(id).await
+    }
+
+    /// Get the `limit` most recent device changes, oldest first.
+    pub async fn recent_events(&self, limit: usize) -> Result<Vec<DeviceEvent>> {
+        self.0.recent_events(limit).await
+    }
+
+    /// Follow every device change made from now on, as in [Sifis::events].
+    pub async fn events(&self) -> Result<impl Stream<Item = HomeEvent> + Unpin + Send> {
+        self.0.events().await
+    }
+
+    /// Follow the maintenance needs crossing a threshold, as in
+    /// [Sifis::maintenance_events].
+    pub async fn maintenance_events(
+        &self,
+    ) -> Result<impl Stream<Item = (String, MaintenanceStatus)> + Unpin + Send> {
+        self.0.maintenance_events().await
+    }
+
+    /// Follow the value of a property of the device `id`, as in
+    /// [Sifis::watch_property].
+    pub async fn watch_property(
+        &self,
+        id: &str,
+        property: &str,
+    ) -> Result<impl Stream<Item = Result<serde_json::Value>> + Unpin + Send> {
+        self.0.watch_property(id, property).await
+    }
+
+    /// Follow the changes of the devices `ids` in a single stream, as in
+    /// [Sifis::watch_many].
+    pub async fn watch_many(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<impl Stream<Item = Result<(String, DeviceEvent)>> + Unpin + Send> {
+        self.0.watch_many(ids).await
+    }
+
+    /// Report the state of every device in the home, as in [Sifis::summary].
+    pub async fn summary(&self) -> Result<String> {
+        self.0.summary().await
+    }
+}
+
+/// Read-only [Lamp]
+pub struct ObserverLamp(Lamp);
+
+impl Display for ObserverLamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ObserverLamp {
+    /// Id of the lamp.
+    pub fn id(&self) -> &DeviceId {
+        &self.0.id
+    }
+
+    /// Get the current on/off status.
+    pub async fn get_on_off(&self) -> Result<bool> {
+        self.0.get_on_off().await
+    }
+
+    /// Get the current brightness level.
+    pub async fn get_brightness(&self) -> Result<u8> {
+        self.0.get_brightness().await
+    }
+
+    /// Get the current brightness level, within [Lamp::BRIGHTNESS_HI].
+    pub async fn get_brightness_hi(&self) -> Result<u16> {
+        self.0.get_brightness_hi().await
+    }
+
+    /// Wait up to `timeout` for the brightness to be `target`, as in
+    /// [Lamp::wait_for_brightness].
+    pub async fn wait_for_brightness(&self, target: u8, timeout: Duration) -> Result<bool> {
+        self.0.wait_for_brightness(target, timeout).await
+    }
+
+    /// Get the estimated power drawn, in watts.
+    ///
+    /// # Hazards
+    /// * [Hazard::LogEnergyConsumption]
+    pub async fn power(&self) -> Result<f32> {
+        self.0.power().await
+    }
+
+    /// Get the highest brightness the lamp accepts, if capped.
+    pub async fn max_brightness(&self) -> Result<Option<u8>> {
+        self.0.max_brightness().await
+    }
+
+    /// Get the white color temperature in kelvin, if the lamp is tunable.
+    pub async fn color_temperature(&self) -> Result<Option<u16>> {
+        self.0.color_temperature().await
+    }
+
+    /// Get the hazards the lamp presents in its current state.
+    pub async fn hazards(&self) -> Result<Vec<Hazard>> {
+        self.0.hazards().await
+    }
+}
+
+/// Read-only [Sink]
+pub struct ObserverSink(Sink);
+
+impl Display for ObserverSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ObserverSink {
+    /// Id of the sink.
+    pub fn id(&self) -> &DeviceId {
+        &self.0.id
+    }
+
+    /// Get the current water level.
+    pub async fn get_water_level(&self) -> Result<u8> {
+        self.0.get_water_level().await
+    }
+
+    /// Get whether the drain is open.
+    pub async fn get_drain(&self) -> Result<bool> {
+        self.0.get_drain().await
+    }
+
+    /// Get the current water flow.
+    pub async fn get_flow(&self) -> Result<u8> {
+        self.0.get_flow().await
+    }
+
+    /// Get the current water temperature.
+    pub async fn get_temperature(&self) -> Result<u8> {
+        self.0.get_temperature().await
+    }
+
+    /// Get the highest water temperature the sink accepts.
+    pub async fn max_temperature(&self) -> Result<u8> {
+        self.0.max_temperature().await
+    }
+
+    /// Get the hazards the sink presents in its current state.
+    pub async fn hazards(&self) -> Result<Vec<Hazard>> {
+        self.0.hazards().await
+    }
+}
+
+/// Read-only [Door]
+pub struct ObserverDoor(Door);
+
+impl Display for ObserverDoor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ObserverDoor {
+    /// Id of the door.
+    pub fn id(&self) -> &DeviceId {
+        &self.0.id
+    }
+
+    /// Get the current open status.
+    pub async fn is_open(&self) -> Result<bool> {
+        self.0.is_open().await
+    }
+
+    /// Get the current lock status.
+    pub async fn lock_status(&self) -> Result<DoorLockStatus> {
+        self.0.lock_status().await
+    }
+
+    /// Get the hazards the door presents in its current state.
+    pub async fn hazards(&self) -> Result<Vec<Hazard>> {
+        self.0.hazards().await
+    }
+}
+
+/// Read-only [Fridge]
+pub struct ObserverFridge(Fridge);
+
+impl Display for ObserverFridge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ObserverFridge {
+    /// Id of the fridge.
+    pub fn id(&self) -> &DeviceId {
+        &self.0.id
+    }
+
+    /// Get the current open status.
+    pub async fn is_open(&self) -> Result<bool> {
+        self.0.is_open().await
+    }
+
+    /// Get for how long the door has been open, `None` if it is closed.
+    pub async fn open_duration(&self) -> Result<Option<Duration>> {
+        self.0.open_duration().await
+    }
+
+    /// Get the current temperature.
+    pub async fn temperature(&self) -> Result<i8> {
+        self.0.temperature().await
+    }
+
+    /// Get the target temperature.
+    pub async fn target_temperature(&self) -> Result<i8> {
+        self.0.target_temperature().await
+    }
+
+    /// Get the hazards the fridge presents in its current state.
+    pub async fn hazards(&self) -> Result<Vec<Hazard>> {
+        self.0.hazards().await
+    }
+}
+
+/// Read-only [Curtain]
+pub struct ObserverCurtain(Curtain);
+
+impl Display for ObserverCurtain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ObserverCurtain {
+    /// Id of the curtain.
+    pub fn id(&self) -> &DeviceId {
+        &self.0.id
+    }
+
+    /// Get the current open status.
+    pub async fn is_open(&self) -> Result<bool> {
+        self.0.is_open().await
+    }
+
+    /// Get the hazards the curtain presents in its current state.
+    pub async fn hazards(&self) -> Result<Vec<Hazard>> {
+        self.0.hazards().await
+    }
+}
@@ -0,0 +1,128 @@
+//! Priority of the requests competing for a device
+//!
+//! A client made by [Sifis::with_priority] wraps each of its requests in a
+//! [SifisApi::prioritized](crate::service::SifisApi::prioritized) request over
+//! the same connection, the runtime serves the inner one with the priority.
+
+use std::sync::Arc;
+
+use futures::future;
+use serde::{Deserialize, Serialize};
+use tarpc::client::RpcError;
+use tarpc::server::{BaseChannel, Channel};
+
+use crate::recording::failed;
+use crate::service::{Error, SifisApiClient, SifisApiRequest};
+use crate::{CancellationToken, Sifis};
+
+/// Priority of a request
+///
+/// The runtime serves the urgent requests waiting for a device before the
+/// routine ones queued for it, and never refuses them for being sent too
+/// often. The requests of the same priority are served in order of arrival,
+/// a request already running is never interrupted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Priority {
+    /// Everyday commands and readings
+    #[default]
+    Routine,
+    /// Safety-critical commands, e.g. turning everything off
+    Urgent,
+}
+
+impl Sifis {
+    /// Get a client sharing this connection, making its requests with the
+    /// priority `priority`.
+    ///
+    /// The device handles it looks up keep the priority. A request failing
+    /// on the transport, e.g. as the connection breaks, closes the client:
+    /// its requests fail from then on as over a closed connection.
+    ///
+    /// It must be called from within a tokio runtime.
+    pub fn with_priority(&self, priority: Priority) -> Sifis {
+        Sifis {
+            priority,
+            ..self.clone()
+        }
+        .proxied()
+    }
+
+    /// Route the requests through a proxy applying the priority and the
+    /// cancellation of this client, if it needs one
+    pub(crate) fn proxied(self) -> Sifis {
+        let client = match (self.priority, &self.cancel) {
+            (Priority::Routine, None) => self.base.clone(),
+            (priority, cancel) => {
+                Arc::new(prioritized(self.base.clone(), priority, cancel.clone()))
+            }
+        };
+
+        Sifis { client, ..self }
+    }
+}
+
+/// Client sending each request through `connection` with the priority
+/// `priority`, until `cancel` is cancelled
+///
+/// It closes once a request fails on the transport of `connection`.
+fn prioritized(
+    connection: Arc<SifisApiClient>,
+    priority: Priority,
+    cancel: Option<CancellationToken>,
+) -> SifisApiClient {
+    let (client_transport, server_transport) = tarpc::transport::channel::unbounded();
+    let closed = CancellationToken::new();
+    let server = tarpc::server::serve({
+        let closed = closed.clone();
+        move |ctx, request: SifisApiRequest| {
+            let connection = connection.clone();
+            let cancel = cancel.clone();
+            let closed = closed.clone();
+            async move {
+                // Made ahead, the request moves into the prioritized one
+                let json = serde_json::to_value(&request).unwrap_or_default();
+                let on_cancel = cancel.map(|cancel| (cancel, failed(&json, Error::Cancelled)));
+                let forward = async move {
+                    match connection
+                        .prioritized(ctx, priority, Box::new(request))
+                        .await
+                    {
+                        Ok(Ok(response)) => *response,
+                        // The runtime refused the prioritized request itself
+                        Ok(Err(e)) => failed(&json, e),
+                        // The client gives up at the same deadline
+                        Err(RpcError::DeadlineExceeded) => future::pending().await,
+                        // The proxy cannot answer with a transport error, it
+                        // closes and its client fails as the connection did
+                        Err(e) => {
+                            tracing::warn!("Closing the prioritized client: {e}");
+                            closed.cancel();
+                            // Never sent, the channel stops first
+                            failed(&json, Error::Cancelled)
+                        }
+                    }
+                };
+
+                match on_cancel {
+                    // Dropping the request cancels it on the runtime too
+                    Some((cancel, answer)) => tokio::select! {
+                        biased;
+                        _ = cancel.cancelled() => answer,
+                        response = forward => response,
+                    },
+                    None => forward.await,
+                }
+            }
+        }
+    });
+    tokio::spawn(async move {
+        // Dropping the channel fails the requests of the client
+        tokio::select! {
+            biased;
+            _ = closed.cancelled() => {}
+            _ = BaseChannel::with_defaults(server_transport).execute(server) => {}
+        }
+    });
+
+    SifisApiClient::new(Default::default(), client_transport).spawn()
+}
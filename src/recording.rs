@@ -0,0 +1,196 @@
+//! Record the traffic with a runtime and replay it without one
+//!
+//! Every exchange is a line of a JSON Lines file, the request and the answer
+//! tagged with the name of the method, e.g.
+//! `{"request":{"TurnLampOn":{"id":"lamp1"}},"response":{"TurnLampOn":{"Ok":{"state":true,"changed":true}}}}`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tarpc::server::{BaseChannel, Channel};
+use tarpc::tokio_serde::formats::Bincode;
+use tarpc::transport::channel::UnboundedChannel;
+use tarpc::{ClientMessage, Response};
+
+use crate::service::{self, SifisApiClient, SifisApiRequest, SifisApiResponse};
+use crate::{Result, Sifis};
+
+/// A request along with its answer
+#[derive(Serialize, Deserialize)]
+struct Exchange {
+    request: Value,
+    response: Value,
+}
+
+impl Sifis {
+    /// Connect to the default unix socket as in [Sifis::new], recording the
+    /// traffic to the JSON Lines file at `recording`.
+    pub async fn with_recording(recording: impl AsRef<Path>) -> Result<Sifis> {
+        let sifis_server =
+            std::env::var("SIFIS_SERVER").unwrap_or("/var/run/sifis.sock".to_string());
+        Self::from_path_with_recording(&sifis_server, recording).await
+    }
+
+    /// Connect to the runtime listening on the unix socket `path`, recording
+    /// the traffic to the JSON Lines file at `recording`.
+    ///
+    /// The file is truncated, then every answered request is appended to it
+    /// as soon as the answer arrives.
+    pub async fn from_path_with_recording(
+        path: impl AsRef<Path>,
+        recording: impl AsRef<Path>,
+    ) -> Result<Sifis> {
+        let file = File::create(recording)?;
+        let runtime =
+            tarpc::serde_transport::unix::connect(path.as_ref(), Bincode::default).await?;
+        let (client_transport, proxy) = tarpc::transport::channel::unbounded();
+        tokio::spawn(record(proxy, runtime, file));
+
+        Self::spawn(SifisApiClient::new(Default::default(), client_transport))
+            .handshake()
+            .await
+    }
+
+    /// Answer the requests with the ones recorded by
+    /// [Sifis::from_path_with_recording], without any runtime.
+    ///
+    /// The answers to the same request are given in the recorded order, the
+    /// last one is repeated once they run out. A request missing from the
+    /// recording fails with [service::Error::InvalidArgument](crate::service::Error::InvalidArgument).
+    ///
+    /// It must be called from within a tokio runtime.
+    pub fn replay(recording: impl AsRef<Path>) -> Result<Sifis> {
+        let mut answers: HashMap<String, Vec<Value>> = HashMap::new();
+        for line in BufReader::new(File::open(recording)?).lines() {
+            let exchange: Exchange = serde_json::from_str(&line?)?;
+            answers
+                .entry(exchange.request.to_string())
+                .or_default()
+                .push(exchange.response);
+        }
+        let answers = Arc::new(Mutex::new(answers));
+
+        let (client_transport, server_transport) = tarpc::transport::channel::unbounded();
+        let server = tarpc::server::serve(move |_, request: SifisApiRequest| {
+            let response = replayed(&mut answers.lock().unwrap(), &request);
+            async move { response }
+        });
+        tokio::spawn(BaseChannel::with_defaults(server_transport).execute(server));
+
+        Ok(Self::spawn(SifisApiClient::new(
+            Default::default(),
+            client_transport,
+        )))
+    }
+}
+
+/// Forward the messages between the client and the runtime, appending every
+/// answered request to `file`
+async fn record<T, E>(
+    proxy: UnboundedChannel<ClientMessage<SifisApiRequest>, Response<SifisApiResponse>>,
+    runtime: T,
+    mut file: File,
+) where
+    T: Stream<Item = std::result::Result<Response<SifisApiResponse>, E>>
+        + Sink<ClientMessage<SifisApiRequest>, Error = E>,
+    E: std::fmt::Display,
+{
+    let (mut to_client, mut from_client) = proxy.split();
+    let (mut to_runtime, mut from_runtime) = runtime.split();
+    // Requests waiting for an answer, by id
+    let mut pending = HashMap::new();
+
+    loop {
+        tokio::select! {
+            message = from_client.next() => {
+                let Some(Ok(message)) = message else {
+                    break;
+                };
+                if let ClientMessage::Request(request) = &message {
+                    if let Ok(value) = serde_json::to_value(&request.message) {
+                        pending.insert(request.id, value);
+                    }
+                } else if let ClientMessage::Cancel { request_id, .. } = &message {
+                    pending.remove(request_id);
+                }
+                if let Err(e) = to_runtime.send(message).await {
+                    tracing::warn!("Connection broken: {e}");
+                    break;
+                }
+            }
+            response = from_runtime.next() => {
+                let response = match response {
+                    Some(Ok(response)) => response,
+                    Some(Err(e)) => {
+                        tracing::warn!("Connection broken: {e}");
+                        break;
+                    }
+                    None => break,
+                };
+                let request = pending.remove(&response.request_id);
+                if let (Some(request), Ok(answer)) = (request, &response.message) {
+                    let exchange = Exchange {
+                        request,
+                        response: serde_json::to_value(answer).unwrap_or_default(),
+                    };
+                    let line = serde_json::to_string(&exchange).unwrap_or_default();
+                    if let Err(e) = writeln!(file, "{line}") {
+                        tracing::warn!("Cannot record an exchange: {e}");
+                    }
+                }
+                if to_client.send(response).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Next recorded answer to `request`
+fn replayed(
+    answers: &mut HashMap<String, Vec<Value>>,
+    request: &SifisApiRequest,
+) -> SifisApiResponse {
+    let request = serde_json::to_value(request).unwrap_or_default();
+    let answer = answers
+        .get_mut(&request.to_string())
+        .and_then(|answers| {
+            if answers.len() > 1 {
+                Some(answers.remove(0))
+            } else {
+                answers.first().cloned()
+            }
+        })
+        .and_then(|answer| serde_json::from_value(answer).ok());
+
+    answer.unwrap_or_else(|| {
+        let method = method(&request);
+        failed(
+            &request,
+            service::Error::InvalidArgument(format!("{method} request not in the recording")),
+        )
+    })
+}
+
+/// Name of the method of a request serialized as JSON
+fn method(request: &Value) -> String {
+    request
+        .as_object()
+        .and_then(|tagged| tagged.keys().next())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Answer the request serialized as `request` with `error`, tagged with the
+/// same method
+pub(crate) fn failed(request: &Value, error: service::Error) -> SifisApiResponse {
+    let error = json!({ "Err": error });
+    let tagged = Value::Object([(method(request), error)].into_iter().collect());
+    serde_json::from_value(tagged).expect("every method answers with a Result")
+}
@@ -3,6 +3,12 @@
 use nix::sys::socket::{getsockopt, sockopt};
 use std::os::{fd::BorrowedFd, raw::c_int};
 
+mod mock;
+mod policy;
+
+pub use mock::InMemoryBackend;
+pub use policy::{DefaultHazardPolicy, HazardPolicy, HazardousOperation, Verdict};
+
 /// Find the pid of the unix socket peer
 pub fn peer_pid(fd: BorrowedFd) -> c_int {
     #[cfg(any(target_os = "android", target_os = "linux"))]
@@ -17,3 +23,34 @@ pub fn peer_pid(fd: BorrowedFd) -> c_int {
         getsockopt(&fd, sockopt::LocalPeerPid).unwrap_or(-1)
     }
 }
+
+/// Match `text` against a simple glob `pattern`.
+///
+/// `*` matches any sequence of characters (including the empty one) and `?`
+/// matches exactly one character, everything else matches literally.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen and of the text it is currently matching up to
+    let mut star = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,2774 @@
+//! In-memory device simulation
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::future::Future;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tarpc::context::Context;
+use tarpc::server::Serve;
+use tokio::sync::{watch, Mutex, OwnedMutexGuard};
+use tokio::task::AbortHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::info;
+
+use super::{glob_match, DefaultHazardPolicy, HazardPolicy, HazardousOperation, Verdict};
+use crate::recording::failed;
+use crate::server_info::{is_error, request_method};
+use crate::service::{
+    Error, SifisApi, SifisApiRequest, SifisApiResponse, METHODS, PROTOCOL_VERSION,
+};
+use crate::{
+    CancellationToken, Capabilities, Change, Clamped, CurtainState, DeviceEvent, DeviceHealth,
+    DeviceInfo, DeviceKind, DeviceSpec, DoorLockStatus, DoorState, EventBatch, Fridge, FridgeState,
+    Hazard, HealthStatus, HomeSnapshot, Lamp, LampState, LockResult, MaintenanceStatus,
+    OperationDoc, Priority, Property, Range, Schedule, ScheduleId, ServerInfo, Sink, SinkState,
+    ToggleResult, ValueType,
+};
+
+/// Power drawn by a lamp at full brightness, in watts
+const LAMP_MAX_POWER: f32 = 10.0;
+
+/// Power drawn by a lamp, linear in its brightness
+fn lamp_power(lamp: &LampState) -> f32 {
+    if lamp.on {
+        LAMP_MAX_POWER * f32::from(lamp.brightness) / f32::from(u16::MAX)
+    } else {
+        0.0
+    }
+}
+
+/// Power drawn from the circuit by a device, only the lamps are modeled
+fn device_power(dev: &Device) -> f32 {
+    match dev.kind {
+        DeviceKind::Lamp(ref l) => lamp_power(l),
+        _ => 0.0,
+    }
+}
+
+/// Number of device changes kept in the event log
+const EVENT_LOG_SIZE: usize = 256;
+
+/// Chance a new locking attempt frees a jammed lock
+const JAM_CLEAR_RATE: f64 = 0.5;
+
+/// Pause between two locking attempts
+const JAM_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+/// Shortest wall clock period of the simulation task
+const MIN_REAL_TICK: Duration = Duration::from_millis(1);
+
+/// Most ticks run at once, enough for any fridge to settle
+const MAX_CATCH_UP_TICKS: u128 = 256;
+
+/// Temperature an open fridge warms up to
+const ROOM_TEMPERATURE: i8 = 20;
+
+/// Move the fridge temperature one degree toward the target if the door is
+/// closed, toward the room temperature if it is open
+fn cool(fridge: &mut FridgeState) {
+    let goal = if fridge.open {
+        ROOM_TEMPERATURE
+    } else {
+        fridge.target_temperature
+    };
+    fridge.temperature += (goal - fridge.temperature).signum();
+}
+
+/// Drain the battery of a battery powered device by `ticks` percent,
+/// returning whether it just ran low
+fn discharge(maintenance: &mut MaintenanceStatus, ticks: u128) -> bool {
+    let was_low = maintenance.low_battery();
+    if let Some(ref mut battery) = maintenance.battery {
+        *battery = battery.saturating_sub(u8::try_from(ticks).unwrap_or(u8::MAX));
+    }
+
+    !was_low && maintenance.low_battery()
+}
+
+/// Degrees above its target a fridge is considered failing at
+const FRIDGE_WARM_MARGIN: i8 = 3;
+
+/// What keeps a device from working as expected, if anything
+fn degradation(kind: &DeviceKind) -> Option<String> {
+    match kind {
+        DeviceKind::Door(door) if door.lock == DoorLockStatus::Jammed => {
+            Some("the lock is jammed".to_owned())
+        }
+        DeviceKind::Fridge(fridge)
+            if fridge.temperature
+                > fridge.target_temperature.saturating_add(FRIDGE_WARM_MARGIN) =>
+        {
+            Some(format!(
+                "{}°C, above the {}°C target",
+                fridge.temperature, fridge.target_temperature
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Hazards a device presents in its current state
+fn active_hazards(kind: &DeviceKind) -> Vec<Hazard> {
+    let mut hazards = Vec::new();
+
+    match kind {
+        DeviceKind::Lamp(lamp) if lamp.on => {
+            hazards.extend([Hazard::Fire, Hazard::EnergyConsumption]);
+        }
+        DeviceKind::Sink(sink) if sink.flow > 0 => {
+            if !sink.drain {
+                hazards.push(Hazard::Flood);
+            }
+            if sink.temp > sink.max_safe_temp {
+                hazards.push(Hazard::Scald);
+            }
+        }
+        DeviceKind::Fridge(fridge) if fridge.open => hazards.push(Hazard::EnergyConsumption),
+        _ => {}
+    }
+
+    hazards
+}
+
+/// Hazards no device may start with, see [InMemoryBackend::safe_start]
+const START_HAZARDS: [Hazard; 2] = [Hazard::Flood, Hazard::Scald];
+
+/// Drive a device to the state it is left in by turning everything off,
+/// returning whether its kind has one
+fn make_safe(kind: &mut DeviceKind) -> bool {
+    match kind {
+        DeviceKind::Lamp(l) => l.on = false,
+        DeviceKind::Sink(s) => {
+            s.flow = 0;
+            s.drain = true;
+        }
+        _ => return false,
+    }
+
+    true
+}
+
+/// Serialize the result of an operation
+fn json(r: impl serde::Serialize) -> String {
+    serde_json::to_string(&r).expect("the results are always serializable")
+}
+
+/// Deserialize the argument at `index`
+fn arg<T: DeserializeOwned>(args: &[Value], index: usize) -> Result<T, Error> {
+    let value = args
+        .get(index)
+        .ok_or_else(|| Error::InvalidArgument(format!("missing argument {index}")))?;
+
+    serde_json::from_value(value.clone())
+        .map_err(|e| Error::InvalidArgument(format!("argument {index}: {e}")))
+}
+
+/// Clamp the high resolution `brightness` to the lamp cap
+fn capped(lamp: &LampState, brightness: u16) -> u16 {
+    lamp.max_brightness.map_or(brightness, |max| {
+        brightness.min(Lamp::brightness_to_hi(max))
+    })
+}
+
+/// Add `delta` to `value`, saturating at the bounds of `range`
+fn adjusted<T>(value: T, delta: i16, range: RangeInclusive<T>) -> T
+where
+    T: Copy + Into<i16> + TryFrom<i16>,
+{
+    let (start, end) = range.into_inner();
+    let value = value
+        .into()
+        .saturating_add(delta)
+        .clamp(start.into(), end.into());
+
+    // Within the range, it always converts back
+    T::try_from(value).unwrap_or(end)
+}
+
+/// Move a door lock to `target`, unless it is jammed or the door is open
+/// and cannot be locked
+///
+/// Moving the lock jams it with the `jam_probability` of the door.
+fn set_lock(door: &mut DoorState, target: DoorLockStatus, rng: &mut impl Rng) -> LockResult {
+    let jam = f64::from(door.jam_probability);
+
+    match door.lock {
+        DoorLockStatus::Jammed => LockResult::Jammed,
+        _ if door.is_open && target == DoorLockStatus::Locked => LockResult::DoorOpen,
+        current if current == target => LockResult::AlreadyInState,
+        _ if jam > 0.0 && rng.gen_bool(jam.min(1.0)) => {
+            door.lock = DoorLockStatus::Jammed;
+            LockResult::Jammed
+        }
+        _ => {
+            door.lock = target;
+            match target {
+                DoorLockStatus::Locked => LockResult::Locked,
+                _ => LockResult::Unlocked,
+            }
+        }
+    }
+}
+
+/// Lock a door
+fn lock(door: &mut DoorState, rng: &mut impl Rng) -> LockResult {
+    set_lock(door, DoorLockStatus::Locked, rng)
+}
+
+/// A simulated device
+#[derive(Debug)]
+struct Device {
+    kind: DeviceKind,
+    /// Simulated time the fridge door has been opened at, if it is open
+    opened_at: Option<Duration>,
+    /// Battery charge and parts due for replacement
+    maintenance: MaintenanceStatus,
+    /// Signaled whenever a call changes the state
+    changed: watch::Sender<()>,
+}
+
+/// Entry of the device table
+///
+/// Each device has its own lock, the calls to distinct devices do not wait
+/// for each other. The name and the kind never change, they are read
+/// without waiting for the device, as is the lockout.
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    room: Option<String>,
+    kind: &'static str,
+    /// Whether the device refuses the commands, during maintenance
+    lockout: bool,
+    /// Whether the device is simulated as unreachable
+    offline: bool,
+    device: Arc<Mutex<Device>>,
+    /// Number of urgent requests waiting for the device
+    urgent: Arc<watch::Sender<usize>>,
+}
+
+/// Urgent request counted as waiting for a device, until dropped
+struct Waiting<'a>(&'a watch::Sender<usize>);
+
+impl<'a> Waiting<'a> {
+    fn new(urgent: &'a watch::Sender<usize>) -> Self {
+        urgent.send_modify(|n| *n += 1);
+        Waiting(urgent)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.send_modify(|n| *n -= 1);
+    }
+}
+
+impl Entry {
+    /// Create a device from its specification at the simulated time `now`
+    fn new(spec: DeviceSpec, now: Duration) -> Self {
+        let opened_at = match spec.kind {
+            DeviceKind::Fridge(ref fridge) if fridge.open => Some(now),
+            _ => None,
+        };
+
+        Entry {
+            name: spec.name,
+            room: spec.room,
+            kind: spec.kind.display(),
+            lockout: false,
+            offline: false,
+            device: Arc::new(Mutex::new(Device {
+                kind: spec.kind,
+                opened_at,
+                maintenance: spec.maintenance,
+                changed: watch::channel(()).0,
+            })),
+            urgent: Arc::new(watch::channel(0).0),
+        }
+    }
+}
+
+/// Devices indexed by id, only locked to look them up
+type Devices = std::sync::Mutex<HashMap<String, Entry>>;
+
+/// The devices of the table of kind `kind`, or all of them
+fn devices_of(devices: &Devices, kind: Option<&str>) -> Vec<(String, Arc<Mutex<Device>>)> {
+    let mut res: Vec<_> = devices
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, entry)| kind.map_or(true, |kind| entry.kind == kind))
+        .map(|(id, entry)| (id.clone(), entry.device.clone()))
+        .collect();
+    res.sort_by(|a, b| a.0.cmp(&b.0));
+
+    res
+}
+
+/// Time as seen by the simulation
+///
+/// It runs `scale` times faster than the wall clock and can be moved forward
+/// on demand.
+#[derive(Debug)]
+struct Clock {
+    start: Instant,
+    scale: f64,
+    /// Time skipped with [Clock::advance]
+    skipped: std::sync::Mutex<Duration>,
+}
+
+impl Clock {
+    fn new(scale: f64) -> Clock {
+        Clock {
+            start: Instant::now(),
+            scale: if scale.is_finite() && scale > 0.0 {
+                scale
+            } else {
+                1.0
+            },
+            skipped: Default::default(),
+        }
+    }
+
+    /// Simulated time elapsed since the clock was created
+    fn now(&self) -> Duration {
+        self.start.elapsed().mul_f64(self.scale) + *self.skipped.lock().unwrap()
+    }
+
+    fn advance(&self, by: Duration) {
+        *self.skipped.lock().unwrap() += by;
+    }
+
+    /// Wall clock time taking `d` of simulated time
+    fn real(&self, d: Duration) -> Duration {
+        d.div_f64(self.scale)
+    }
+
+    async fn sleep(&self, d: Duration) {
+        tokio::time::sleep(self.real(d)).await;
+    }
+}
+
+/// Time-dependent part of the simulation
+#[derive(Debug)]
+struct Simulation {
+    clock: Clock,
+    /// Simulated period of the ticks and time of the last one, once started
+    ticks: std::sync::Mutex<Option<(Duration, Duration)>>,
+}
+
+impl Simulation {
+    fn new(scale: f64) -> Self {
+        Simulation {
+            clock: Clock::new(scale),
+            ticks: Default::default(),
+        }
+    }
+
+    /// Run the ticks due by the current simulated time, recording in
+    /// `events` the batteries running low
+    async fn catch_up(&self, devices: &Devices, events: &std::sync::Mutex<EventLog>) {
+        let due = {
+            let mut ticks = self.ticks.lock().unwrap();
+            let Some((period, last)) = ticks.as_mut() else {
+                return;
+            };
+            let now = self.clock.now();
+            let elapsed = now.saturating_sub(*last).as_nanos();
+            let period = period.as_nanos().max(1);
+            // The remainder is shorter than a period, it fits in a u64
+            *last = now - Duration::from_nanos((elapsed % period) as u64);
+            (elapsed / period).min(MAX_CATCH_UP_TICKS)
+        };
+
+        for (_, dev) in devices_of(devices, Some("Fridge")) {
+            if let DeviceKind::Fridge(ref mut fridge) = dev.lock().await.kind {
+                for _ in 0..due {
+                    cool(fridge);
+                }
+            }
+        }
+
+        for (id, dev) in devices_of(devices, None) {
+            let mut dev = dev.lock().await;
+            if discharge(&mut dev.maintenance, due) {
+                info!("Device {id} is running low on battery");
+                events.lock().unwrap().push(DeviceEvent {
+                    timestamp: SystemTime::now(),
+                    device_id: id,
+                    operation: "discharge".to_owned(),
+                    peer_pid: None,
+                    change: Change::Maintenance(dev.maintenance),
+                });
+            }
+        }
+    }
+}
+
+/// Bounded log of the device changes
+#[derive(Debug)]
+struct EventLog {
+    /// Most recent changes, the oldest first
+    events: VecDeque<DeviceEvent>,
+    /// Position of the next change, counting the forgotten ones
+    next: u64,
+    /// Broadcasts `next` to the clients waiting for changes
+    notify: watch::Sender<u64>,
+}
+
+impl EventLog {
+    fn new() -> Self {
+        EventLog {
+            events: VecDeque::new(),
+            next: 0,
+            notify: watch::channel(0).0,
+        }
+    }
+
+    /// Append a change, forgetting the oldest if the log is full
+    fn push(&mut self, event: DeviceEvent) {
+        if self.events.len() == EVENT_LOG_SIZE {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+        self.next += 1;
+        self.notify.send_replace(self.next);
+    }
+
+    /// The changes following the position `after`
+    fn since(&self, after: u64) -> EventBatch {
+        let oldest = self.next - self.events.len() as u64;
+        let skip = after.saturating_sub(oldest) as usize;
+
+        EventBatch {
+            events: self.events.iter().skip(skip).cloned().collect(),
+            next: self.next,
+            lagged: oldest.saturating_sub(after),
+        }
+    }
+}
+
+/// Deliberate failures to exercise the client resilience
+#[derive(Debug)]
+struct Faults {
+    /// Fraction of the device calls failing with `Error::NotFound`
+    rate: f64,
+    /// Latency added before answering a device call or lookup
+    delay: Duration,
+    /// Fraction of the device calls closing the connection instead
+    drop_rate: f64,
+    seed: Option<u64>,
+    rng: std::sync::Mutex<StdRng>,
+}
+
+/// `rate` within `0.0` and `1.0`
+fn fraction(rate: f64) -> f64 {
+    if rate.is_finite() {
+        rate.clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+impl Faults {
+    fn new(rate: f64, delay: Duration, drop_rate: f64, seed: Option<u64>) -> Faults {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        Faults {
+            rate: fraction(rate),
+            delay,
+            drop_rate: fraction(drop_rate),
+            seed,
+            rng: std::sync::Mutex::new(rng),
+        }
+    }
+
+    async fn lag(&self) {
+        if !self.delay.is_zero() {
+            tokio::time::sleep(self.delay).await;
+        }
+    }
+
+    /// Fail the call on the device `id` on purpose, or cancel `hangup` for
+    /// the connection to be closed
+    async fn inject(&self, id: &str, hangup: Option<&CancellationToken>) -> Result<(), Error> {
+        self.lag().await;
+
+        let mut rng = self.rng.lock().unwrap();
+        let failed = rng.gen_bool(self.rate);
+        if let Some(hangup) = hangup.filter(|_| self.drop_rate > 0.0) {
+            if rng.gen_bool(self.drop_rate) {
+                tracing::info!("Dropping the connection on purpose");
+                hangup.cancel();
+                // Never answered, the connection is closed first
+                return Err(Error::NotFound(id.to_owned()));
+            }
+        }
+
+        if failed {
+            Err(Error::NotFound(id.to_owned()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Shortest interval between two commands sent to the same device, sparing
+/// the relays of the physical devices
+#[derive(Debug)]
+struct RateLimit {
+    interval: Duration,
+    /// When each device has last been commanded
+    last: std::sync::Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimit {
+    fn new(interval: Duration) -> RateLimit {
+        RateLimit {
+            interval,
+            last: Default::default(),
+        }
+    }
+
+    /// Let a command to `id` through, unless the previous one is too recent
+    fn admit(&self, id: &str) -> Result<(), Error> {
+        let last = self.last.lock().unwrap();
+
+        if let Some(elapsed) = last.get(id).map(Instant::elapsed) {
+            if elapsed < self.interval {
+                let wait = self.interval - elapsed;
+                return Err(Error::RateLimited {
+                    retry_after_ms: wait.as_micros().div_ceil(1000) as u64,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count the command just applied to `id` against its limit
+    fn applied(&self, id: &str) {
+        self.last
+            .lock()
+            .unwrap()
+            .insert(id.to_owned(), Instant::now());
+    }
+
+    /// Forget the commands of the removed device `id`
+    fn forget(&self, id: &str) {
+        self.last.lock().unwrap().remove(id);
+    }
+}
+
+/// Methods only a mock runtime built with the `testing` feature serves
+const TESTING_METHODS: [&str; 4] = [
+    "advance_time",
+    "set_device_online",
+    "set_door_open",
+    "set_fridge_open",
+];
+
+/// Refuse the method `method`, one of [TESTING_METHODS], unless built with
+/// the `testing` feature
+fn testing_only(method: &str) -> Result<(), Error> {
+    if cfg!(feature = "testing") {
+        Ok(())
+    } else {
+        Err(Error::Unsupported {
+            method: method.to_owned(),
+        })
+    }
+}
+
+/// Answer to `request` if its method is among the `unsupported` ones
+fn refused(unsupported: &BTreeSet<String>, request: &SifisApiRequest) -> Option<SifisApiResponse> {
+    if unsupported.is_empty() {
+        return None;
+    }
+    // A request of a newer client is served as any other, to fail as it can
+    let method = request_method(request)?.to_owned();
+    if !unsupported.contains(&method) {
+        return None;
+    }
+
+    let request = serde_json::to_value(request).ok()?;
+    Some(failed(&request, Error::Unsupported { method }))
+}
+
+/// Upper bounds of the latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0,
+];
+
+/// Latencies of the calls to a method
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Calls by bucket, the ones over the last bound are only in `count`
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    /// Total time taken, in seconds
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, latency: Duration) {
+        let secs = latency.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&le| secs <= le) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+/// Calls served, counted by [InMemoryBackend::serve_metered]
+#[derive(Debug, Default)]
+struct Metrics {
+    /// Number of calls by method and outcome
+    calls: std::sync::Mutex<BTreeMap<(String, &'static str), u64>>,
+    /// Time taken by the calls, by method
+    latency: std::sync::Mutex<BTreeMap<String, Histogram>>,
+}
+
+impl Metrics {
+    /// Count the call to `method`, e.g. `turn_lamp_on`, answered with
+    /// `response` after `latency`
+    fn count(&self, method: &str, response: &SifisApiResponse, latency: Duration) {
+        let outcome = if is_error(response) { "error" } else { "ok" };
+        let method = method.to_owned();
+
+        self.latency
+            .lock()
+            .unwrap()
+            .entry(method.clone())
+            .or_default()
+            .observe(latency);
+        *self
+            .calls
+            .lock()
+            .unwrap()
+            .entry((method, outcome))
+            .or_default() += 1;
+    }
+}
+
+/// Saved scenes and the ones waiting for their time, see
+/// [SifisApi::schedule_scene]
+#[derive(Debug, Default)]
+struct Scenes {
+    /// Each scene by name
+    saved: HashMap<String, HomeSnapshot>,
+    /// Last schedule identifier given
+    next: u64,
+    /// Each scheduled scene along with the task running it
+    pending: BTreeMap<ScheduleId, (Schedule, AbortHandle)>,
+}
+
+/// In-memory implementation of [SifisApi]
+///
+/// It simulates the devices it is built with, cloning it is cheap and the
+/// clones share the same devices, so it can serve many clients at once.
+#[derive(Clone, Debug)]
+pub struct InMemoryBackend {
+    devices: Arc<Devices>,
+    /// Taken by the changes checked against the breaker, one at a time
+    circuit: Arc<Mutex<()>>,
+    faults: Option<Arc<Faults>>,
+    /// Cancelled to close the connection, see [InMemoryBackend::with_hangup]
+    hangup: Option<CancellationToken>,
+    events: Arc<std::sync::Mutex<EventLog>>,
+    /// Process the requests come from, if known
+    peer_pid: Option<i32>,
+    /// Source of the simulated physical randomness, like a lock jamming
+    rng: Arc<std::sync::Mutex<StdRng>>,
+    sim: Arc<Simulation>,
+    /// Most power the circuit can draw before the breaker trips, in watts
+    breaker_watts: Option<f32>,
+    rate_limit: Option<Arc<RateLimit>>,
+    /// Calls served, unless disabled with [InMemoryBackend::with_metrics]
+    metrics: Option<Arc<Metrics>>,
+    scenes: Arc<std::sync::Mutex<Scenes>>,
+    /// Priority of the request being served
+    priority: Priority,
+    policy: Arc<dyn HazardPolicy>,
+    /// Target temperatures the fridges accept
+    fridge_targets: RangeInclusive<i8>,
+    /// Methods answered with [Error::Unsupported]
+    unsupported: Arc<BTreeSet<String>>,
+}
+
+impl Default for InMemoryBackend {
+    /// A home with two lamps, a sink, a door, a fridge and a curtain.
+    fn default() -> Self {
+        Self::new(Self::default_devices())
+    }
+}
+
+impl InMemoryBackend {
+    /// Drive a device about to be simulated to its safe state if it would
+    /// start flooding or scalding, returning the hazards it had.
+    ///
+    /// The safe state is the one [SifisApi::all_off] leaves it in.
+    pub fn safe_start(kind: &mut DeviceKind) -> Vec<Hazard> {
+        let mut hazards = active_hazards(kind);
+        hazards.retain(|hazard| START_HAZARDS.contains(hazard));
+        if !hazards.is_empty() {
+            make_safe(kind);
+        }
+
+        hazards
+    }
+
+    /// The devices of [InMemoryBackend::default], indexed by id.
+    pub fn default_devices() -> Vec<(String, DeviceSpec)> {
+        let devices = [
+            ("lamp1", "Safe lamp", DeviceKind::Lamp(LampState::default())),
+            (
+                "lamp2",
+                "Unsafe lamp",
+                DeviceKind::Lamp(LampState::default()),
+            ),
+            (
+                "sink1",
+                "Kitchen Sink",
+                DeviceKind::Sink(SinkState::default()),
+            ),
+            (
+                "door1",
+                "Bedroom Door",
+                DeviceKind::Door(DoorState::default()),
+            ),
+            (
+                "fridge1",
+                "Kitchen Fridge",
+                DeviceKind::Fridge(FridgeState::default()),
+            ),
+            (
+                "curtain1",
+                "Living Room Curtain",
+                DeviceKind::Curtain(CurtainState::default()),
+            ),
+        ];
+
+        devices
+            .into_iter()
+            .map(|(id, name, kind)| {
+                (
+                    id.to_owned(),
+                    DeviceSpec {
+                        name: name.to_owned(),
+                        room: None,
+                        maintenance: Default::default(),
+                        kind,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Simulate the given devices, indexed by id.
+    pub fn new(devices: impl IntoIterator<Item = (String, DeviceSpec)>) -> Self {
+        let devices = devices
+            .into_iter()
+            .map(|(id, spec)| (id, Entry::new(spec, Duration::ZERO)))
+            .collect();
+
+        Self {
+            devices: Arc::new(std::sync::Mutex::new(devices)),
+            circuit: Default::default(),
+            faults: None,
+            hangup: None,
+            events: Arc::new(std::sync::Mutex::new(EventLog::new())),
+            peer_pid: None,
+            rng: Arc::new(std::sync::Mutex::new(StdRng::from_entropy())),
+            sim: Arc::new(Simulation::new(1.0)),
+            breaker_watts: None,
+            rate_limit: None,
+            metrics: Some(Default::default()),
+            scenes: Default::default(),
+            priority: Priority::Routine,
+            policy: Arc::new(DefaultHazardPolicy),
+            fridge_targets: Fridge::TARGET_TEMPERATURE,
+            unsupported: Default::default(),
+        }
+    }
+
+    /// Count and time the calls served by [InMemoryBackend::serve_metered]
+    /// if `enabled`, the default.
+    ///
+    /// Disabled, the metrics only give the devices and the power they draw.
+    pub fn with_metrics(mut self, enabled: bool) -> Self {
+        self.metrics = enabled.then(Default::default);
+        self
+    }
+
+    /// Make the device calls fail on purpose.
+    ///
+    /// A `rate` fraction of the calls, from `0.0` to `1.0`, answers with
+    /// [Error::NotFound] and every call, as well as the device lookups, is
+    /// delayed by `delay`. Setting `seed` makes the failing calls
+    /// reproducible.
+    pub fn with_faults(mut self, rate: f64, delay: Duration, seed: Option<u64>) -> Self {
+        let drop_rate = self.faults.as_ref().map_or(0.0, |faults| faults.drop_rate);
+        self.faults = Some(Arc::new(Faults::new(rate, delay, drop_rate, seed)));
+        self
+    }
+
+    /// Make a `rate` fraction of the device calls, from `0.0` to `1.0`,
+    /// close the connection instead of answering, to test how a client
+    /// reconnects.
+    ///
+    /// The connection is closed by the server serving it, through the token
+    /// given to [InMemoryBackend::with_hangup]. The calls served without one
+    /// are never dropped.
+    pub fn with_drop_rate(mut self, rate: f64) -> Self {
+        let faults = self.faults.as_deref();
+        let (fail_rate, delay, seed) = faults.map_or((0.0, Duration::ZERO, None), |faults| {
+            (faults.rate, faults.delay, faults.seed)
+        });
+        self.faults = Some(Arc::new(Faults::new(fail_rate, delay, rate, seed)));
+        self
+    }
+
+    /// Cancel `hangup` when a call drops the connection on purpose, see
+    /// [InMemoryBackend::with_drop_rate].
+    ///
+    /// A server gives each connection its own clone and token, and closes
+    /// the connection once the token is cancelled, without sending the
+    /// answer of the call that cancelled it.
+    pub fn with_hangup(mut self, hangup: CancellationToken) -> Self {
+        self.hangup = Some(hangup);
+        self
+    }
+
+    /// Make the simulated physical randomness, like a jammed lock freeing
+    /// itself, reproducible.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Arc::new(std::sync::Mutex::new(StdRng::seed_from_u64(seed)));
+        self
+    }
+
+    /// Make the simulated time run `scale` times faster than the wall clock.
+    ///
+    /// It applies to [InMemoryBackend::simulate], the lamp ramps, the pauses
+    /// between locking attempts and the fridge open duration. A scale that
+    /// is not positive is ignored.
+    pub fn with_time_scale(mut self, scale: f64) -> Self {
+        self.sim = Arc::new(Simulation::new(scale));
+        self
+    }
+
+    /// Refuse to draw more than `watts` from the circuit.
+    ///
+    /// Lighting a lamp or making it brighter fails with
+    /// [Error::Forbidden] for [Hazard::PowerOutage] if the load would exceed
+    /// the limit.
+    pub fn with_breaker(mut self, watts: f32) -> Self {
+        self.breaker_watts = Some(watts);
+        self
+    }
+
+    /// Refuse the commands sent to a device less than `interval` after the
+    /// previous one with [Error::RateLimited].
+    ///
+    /// The batch operations, putting the home in a safe state, are never
+    /// refused.
+    pub fn with_rate_limit(mut self, interval: Duration) -> Self {
+        self.rate_limit = Some(Arc::new(RateLimit::new(interval)));
+        self
+    }
+
+    /// Decide on the hazardous operations with `policy` in place of the
+    /// [DefaultHazardPolicy].
+    pub fn with_hazard_policy(mut self, policy: impl HazardPolicy + 'static) -> Self {
+        self.policy = Arc::new(policy);
+        self
+    }
+
+    /// Accept the fridge target temperatures within `range` in place of
+    /// [Fridge::TARGET_TEMPERATURE].
+    ///
+    /// Setting a target outside of it fails with [Error::OutOfRange], the
+    /// adjustments saturate within it.
+    pub fn with_fridge_targets(mut self, range: RangeInclusive<i8>) -> Self {
+        self.fridge_targets = range;
+        self
+    }
+
+    /// Answer the methods `methods`, e.g. `wait_events`, with
+    /// [Error::Unsupported] as a runtime predating them would, to test how a
+    /// client copes.
+    ///
+    /// It applies to the requests served by [InMemoryBackend::serve_metered]
+    /// and [SifisApi::prioritized], [InMemoryBackend::server_info] leaves the
+    /// methods out.
+    pub fn without_methods(mut self, methods: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.unsupported = Arc::new(methods.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Attribute the changes made through this clone to the process `pid`.
+    ///
+    /// A server gives each connection its own clone, so the event log can
+    /// tell which client made each change.
+    pub fn with_peer_pid(mut self, pid: i32) -> Self {
+        self.peer_pid = Some(pid);
+        self
+    }
+
+    /// Serve the requests like [SifisApi::serve], counting them and timing
+    /// them in the metrics unless disabled with
+    /// [InMemoryBackend::with_metrics].
+    pub fn serve_metered(
+        self,
+    ) -> impl Serve<
+        SifisApiRequest,
+        Resp = SifisApiResponse,
+        Fut = impl Future<Output = SifisApiResponse> + Send,
+    > + Clone
+           + Send
+           + 'static {
+        let metrics = self.metrics.clone();
+        let unsupported = self.unsupported.clone();
+        let server = self.serve();
+
+        tarpc::server::serve(move |ctx, req: SifisApiRequest| {
+            let server = server.clone();
+            let metrics = metrics.clone();
+            let refused = refused(&unsupported, &req);
+            // The requests of an unknown method are counted as `unknown`
+            let method = metrics
+                .is_some()
+                .then(|| request_method(&req).unwrap_or("unknown"));
+            async move {
+                let start = Instant::now();
+                let response = match refused {
+                    Some(response) => response,
+                    None => server.serve(ctx, req).await,
+                };
+                if let (Some(metrics), Some(method)) = (metrics, method) {
+                    metrics.count(method, &response, start.elapsed());
+                }
+                response
+            }
+        })
+    }
+
+    /// Check that the device `id` accepts a command now: it is not locked
+    /// out and within its rate limit, if any
+    ///
+    /// The urgent commands are not rate limited. A command counts against
+    /// the limit once applied, see [InMemoryBackend::record_command].
+    fn admit(&self, id: &str) -> Result<(), Error> {
+        if self.locked_out(id) {
+            return Err(Error::LockedOut(id.to_owned()));
+        }
+
+        match &self.rate_limit {
+            Some(limit) if self.priority == Priority::Routine => limit.admit(id),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ids of the devices of kind `kind` whose id passes `filter`, sorted
+    fn ids_of(&self, kind: &str, filter: impl Fn(&str) -> bool) -> Vec<String> {
+        let mut res: Vec<_> = self
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, entry)| entry.kind == kind && filter(id))
+            .map(|(id, _)| id.clone())
+            .collect();
+        res.sort();
+
+        res
+    }
+
+    /// Identity of the devices `filter` accepts, sorted by id
+    fn device_infos(&self, filter: impl Fn(&Entry) -> bool) -> Vec<DeviceInfo> {
+        let mut res: Vec<_> = self
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| filter(entry))
+            .map(|(id, entry)| DeviceInfo {
+                id: id.clone(),
+                name: entry.name.clone(),
+                room: entry.room.clone(),
+                kind: entry.kind.to_owned(),
+            })
+            .collect();
+        res.sort_by(|a, b| a.id.cmp(&b.id));
+
+        res
+    }
+
+    /// Whether the device `id` exists and is locked out
+    fn locked_out(&self, id: &str) -> bool {
+        self.devices
+            .lock()
+            .unwrap()
+            .get(id)
+            .is_some_and(|entry| entry.lockout)
+    }
+
+    /// Whether the device `id` is simulated as unreachable
+    fn offline(&self, id: &str) -> bool {
+        self.devices
+            .lock()
+            .unwrap()
+            .get(id)
+            .is_some_and(|entry| entry.offline)
+    }
+
+    /// Lock the device `id`, once the table is released
+    ///
+    /// A slow device answers one call at a time without holding up the
+    /// others, the injected faults happen with its lock held.
+    async fn lock_device(&self, id: &str) -> Result<OwnedMutexGuard<Device>, Error> {
+        let dev = self
+            .devices
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|entry| entry.device.clone());
+        let Some(dev) = dev else {
+            self.lag().await;
+            return Err(Error::NotFound(id.to_owned()));
+        };
+        if self.offline(id) {
+            return Err(Error::Offline(id.to_owned()));
+        }
+
+        let dev = self.lock_prioritized(id, dev).await;
+        if let Some(faults) = &self.faults {
+            faults.inject(id, self.hangup.as_ref()).await?;
+        }
+
+        Ok(dev)
+    }
+
+    /// Lock `dev`, the device `id`, after the urgent requests waiting for it
+    ///
+    /// A routine request finding urgent ones waiting lets them go first and
+    /// queues again once they are served.
+    async fn lock_prioritized(&self, id: &str, dev: Arc<Mutex<Device>>) -> OwnedMutexGuard<Device> {
+        let urgent = self
+            .devices
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|entry| entry.urgent.clone());
+        let Some(urgent) = urgent else {
+            return dev.lock_owned().await;
+        };
+
+        if self.priority == Priority::Urgent {
+            let _waiting = Waiting::new(&urgent);
+            return dev.lock_owned().await;
+        }
+
+        let mut waiting = urgent.subscribe();
+        loop {
+            let d = dev.clone().lock_owned().await;
+            if *waiting.borrow_and_update() == 0 {
+                return d;
+            }
+            drop(d);
+            // The sender is alive as long as `urgent`
+            let _ = waiting.wait_for(|&n| n == 0).await;
+        }
+    }
+
+    /// Power drawn from the circuit by the devices other than `except`
+    async fn circuit_load(&self, except: Option<&str>) -> f32 {
+        let mut load = 0.0;
+        for (id, dev) in devices_of(&self.devices, Some("Lamp")) {
+            if except != Some(id.as_str()) {
+                load += device_power(&*dev.lock().await);
+            }
+        }
+        load
+    }
+
+    /// Wait the injected latency, if any
+    async fn lag(&self) {
+        if let Some(faults) = &self.faults {
+            faults.lag().await;
+        }
+    }
+
+    /// Append a state change to the event log
+    fn record(&self, id: &str, operation: &str) {
+        self.record_change(id, operation, Change::State);
+    }
+
+    /// Append the command `operation` applied to `id` to the event log,
+    /// counting it against the rate limit of the device
+    fn record_command(&self, id: &str, operation: &str) {
+        if let Some(limit) = &self.rate_limit {
+            if self.priority == Priority::Routine {
+                limit.applied(id);
+            }
+        }
+        self.record(id, operation);
+    }
+
+    /// Append a change to the event log
+    fn record_change(&self, id: &str, operation: &str, change: Change) {
+        self.events.lock().unwrap().push(DeviceEvent {
+            timestamp: SystemTime::now(),
+            device_id: id.to_owned(),
+            operation: operation.to_owned(),
+            peer_pid: self.peer_pid,
+            change,
+        });
+    }
+
+    /// Run the time-dependent part of the simulation every `tick` of
+    /// simulated time.
+    ///
+    /// Every fridge moves one degree per tick toward its target temperature,
+    /// or toward the room temperature while its door is open, and every
+    /// battery loses one percent of charge per tick. The task ends
+    /// once all the clones of the backend are dropped.
+    pub fn simulate(&self, tick: Duration) -> tokio::task::JoinHandle<()> {
+        *self.sim.ticks.lock().unwrap() = Some((tick, self.sim.clock.now()));
+        let devices = Arc::downgrade(&self.devices);
+        let events = self.events.clone();
+        let sim = self.sim.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(sim.clock.real(tick).max(MIN_REAL_TICK));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+            loop {
+                interval.tick().await;
+                let Some(devices) = devices.upgrade() else {
+                    break;
+                };
+
+                sim.catch_up(&devices, &events).await;
+            }
+        })
+    }
+
+    /// Set every device of `snapshot` back to the state it records, with
+    /// the outcome of each
+    async fn restore_all(
+        &self,
+        snapshot: HomeSnapshot,
+        operation: &str,
+    ) -> Vec<(String, Result<(), Error>)> {
+        let states = snapshot
+            .lamps
+            .into_iter()
+            .map(|(id, l)| (id, DeviceKind::Lamp(l)))
+            .chain(
+                snapshot
+                    .sinks
+                    .into_iter()
+                    .map(|(id, s)| (id, DeviceKind::Sink(s))),
+            )
+            .chain(
+                snapshot
+                    .doors
+                    .into_iter()
+                    .map(|(id, d)| (id, DeviceKind::Door(d))),
+            )
+            .chain(
+                snapshot
+                    .fridges
+                    .into_iter()
+                    .map(|(id, f)| (id, DeviceKind::Fridge(f))),
+            )
+            .chain(
+                snapshot
+                    .curtains
+                    .into_iter()
+                    .map(|(id, c)| (id, DeviceKind::Curtain(c))),
+            );
+        let mut res = Vec::new();
+
+        for (id, kind) in states {
+            let r = self.restore(&id, kind, operation).await;
+            res.push((id, r));
+        }
+
+        res
+    }
+
+    /// Set the device `id` back to the state `kind`, of the same kind
+    async fn restore(&self, id: &str, kind: DeviceKind, operation: &str) -> Result<(), Error> {
+        if self.locked_out(id) {
+            return Err(Error::LockedOut(id.to_owned()));
+        }
+        self.apply(id, |d| {
+            if d.kind.display() != kind.display() {
+                return Err(Error::Mismatch {
+                    found: d.kind.display().to_owned(),
+                    req: kind.display().to_owned(),
+                });
+            }
+            d.kind = kind;
+            Ok(())
+        })
+        .await?;
+        tracing::info!("Device {id} restored");
+        self.record(id, operation);
+
+        Ok(())
+    }
+
+    /// Capabilities of a device of kind `kind`, with the ranges of this
+    /// runtime
+    fn capabilities(&self, kind: &DeviceKind) -> Capabilities {
+        let mut capabilities = Capabilities::of(kind);
+        if let DeviceKind::Fridge(_) = kind {
+            for p in &mut capabilities.properties {
+                if p.name == "target_temperature" {
+                    p.value = ValueType::Integer {
+                        min: (*self.fridge_targets.start()).into(),
+                        max: (*self.fridge_targets.end()).into(),
+                    };
+                }
+            }
+        }
+
+        capabilities
+    }
+
+    /// The property `name` of the device `id`, as listed by its capabilities
+    async fn property(&self, id: &str, name: &str) -> Result<Property, Error> {
+        let capabilities = self.apply(id, |d| Ok(self.capabilities(&d.kind))).await?;
+
+        capabilities
+            .properties
+            .into_iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| {
+                Error::InvalidArgument(format!("{} has no property {name}", capabilities.kind))
+            })
+    }
+
+    /// Run `f` on the device `id`, undoing its change if the hazard policy
+    /// refuses a hazard it brings
+    async fn apply<F, R>(&self, id: &str, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Device) -> Result<R, Error>,
+    {
+        let mut d = self.lock_device(id).await?;
+        let before = d.kind.clone();
+
+        let r = f(&mut d);
+        if d.kind != before {
+            if let Err(e) = self.vet_change(id, &before, &d.kind) {
+                d.kind = before;
+                return Err(e);
+            }
+            d.changed.send_replace(());
+        }
+
+        r
+    }
+    async fn apply_lamp<F, R>(&self, id: &str, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut LampState) -> Result<R, Error>,
+    {
+        self.apply(id, |d| match d.kind {
+            DeviceKind::Lamp(ref mut lamp) => f(lamp),
+            _ => Err(Error::Mismatch {
+                found: d.kind.display().to_string(),
+                req: "Lamp".to_string(),
+            }),
+        })
+        .await
+    }
+    /// Ask the hazard policy whether the device `id` may expose the home to
+    /// `hazard`
+    ///
+    /// `limit` is the safety limit the operation goes past, if any.
+    /// `acknowledged` tells whether the user acknowledged the risk, it is
+    /// `None` if the operation has no variant to acknowledge it with.
+    fn vet(
+        &self,
+        id: &str,
+        hazard: Hazard,
+        limit: Option<String>,
+        acknowledged: Option<bool>,
+    ) -> Result<(), Error> {
+        let verdict = self.policy.check(&HazardousOperation {
+            device_id: id.to_owned(),
+            hazard,
+            limit_exceeded: limit.clone(),
+            acknowledged: acknowledged.unwrap_or(false),
+        });
+
+        match verdict {
+            Verdict::Allow => Ok(()),
+            Verdict::RequireAck if acknowledged == Some(true) => Ok(()),
+            verdict => Err(Error::Forbidden {
+                risk: hazard,
+                comment: limit
+                    .unwrap_or_else(|| format!("{hazard} refused by the hazard policy for {id}")),
+                retriable_with_ack: verdict == Verdict::RequireAck && acknowledged.is_some(),
+            }),
+        }
+    }
+
+    /// Ask the hazard policy about each hazard the device `id` presents in
+    /// the state `after` but not in `before`
+    fn vet_change(&self, id: &str, before: &DeviceKind, after: &DeviceKind) -> Result<(), Error> {
+        let before = active_hazards(before);
+
+        active_hazards(after)
+            .into_iter()
+            .filter(|hazard| !before.contains(hazard))
+            .try_for_each(|hazard| self.vet(id, hazard, None, None))
+    }
+
+    /// Like [InMemoryBackend::apply_lamp], undoing the change if it makes the
+    /// circuit load exceed the breaker limit and the hazard policy refuses it
+    async fn apply_lamp_on_circuit<F, R>(&self, id: &str, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut LampState) -> Result<R, Error>,
+    {
+        let Some(max) = self.breaker_watts else {
+            return self.apply_lamp(id, f).await;
+        };
+        // Two changes waiting for each other's lamp would never end
+        let _circuit = self.circuit.lock().await;
+
+        let mut d = self.lock_device(id).await?;
+        let DeviceKind::Lamp(ref mut lamp) = d.kind else {
+            return Err(Error::Mismatch {
+                found: d.kind.display().to_string(),
+                req: "Lamp".to_string(),
+            });
+        };
+
+        let before = lamp.clone();
+        let r = f(lamp)?;
+
+        let added = lamp_power(lamp) - lamp_power(&before);
+        if added > 0.0 {
+            let load = self.circuit_load(Some(id)).await + lamp_power(&before);
+            if load + added > max {
+                let limit = format!(
+                    "the circuit would draw {:.1}W, the breaker trips over {max:.1}W",
+                    load + added
+                );
+                if let Err(e) = self.vet(id, Hazard::PowerOutage, Some(limit), None) {
+                    *lamp = before;
+                    return Err(e);
+                }
+            }
+        }
+        let change = self.vet_change(
+            id,
+            &DeviceKind::Lamp(before.clone()),
+            &DeviceKind::Lamp(lamp.clone()),
+        );
+        if let Err(e) = change {
+            *lamp = before;
+            return Err(e);
+        }
+        if *lamp != before {
+            d.changed.send_replace(());
+        }
+
+        Ok(r)
+    }
+    async fn apply_sink<F, R>(&self, id: &str, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut SinkState) -> Result<R, Error>,
+    {
+        self.apply(id, |d| match d.kind {
+            DeviceKind::Sink(ref mut sink) => f(sink),
+            _ => Err(Error::Mismatch {
+                found: d.kind.display().to_string(),
+                req: "Sink".to_string(),
+            }),
+        })
+        .await
+    }
+    async fn apply_door<F, R>(&self, id: &str, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut DoorState) -> Result<R, Error>,
+    {
+        self.apply(id, |d| match d.kind {
+            DeviceKind::Door(ref mut door) => f(door),
+            _ => Err(Error::Mismatch {
+                found: d.kind.display().to_string(),
+                req: "Door".to_string(),
+            }),
+        })
+        .await
+    }
+    async fn apply_fridge<F, R>(&self, id: &str, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut FridgeState) -> Result<R, Error>,
+    {
+        self.apply(id, |d| match d.kind {
+            DeviceKind::Fridge(ref mut fridge) => f(fridge),
+            _ => Err(Error::Mismatch {
+                found: d.kind.display().to_string(),
+                req: "Fridge".to_string(),
+            }),
+        })
+        .await
+    }
+    async fn apply_curtain<F, R>(&self, id: &str, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut CurtainState) -> Result<R, Error>,
+    {
+        self.apply(id, |d| match d.kind {
+            DeviceKind::Curtain(ref mut curtain) => f(curtain),
+            _ => Err(Error::Mismatch {
+                found: d.kind.display().to_string(),
+                req: "Curtain".to_string(),
+            }),
+        })
+        .await
+    }
+
+    /// Set every device of `scene` to the state it records, a device that
+    /// cannot be set is logged without stopping the others
+    async fn run_scene(&self, name: &str, scene: HomeSnapshot) {
+        for (id, r) in self.restore_all(scene, "scene").await {
+            if let Err(e) = r {
+                tracing::warn!("Scene {name} left {id} as it was: {e}");
+            }
+        }
+    }
+}
+
+#[tarpc::server]
+impl SifisApi for InMemoryBackend {
+    async fn find_lamps(self, _: Context) -> Result<Vec<String>, Error> {
+        self.lag().await;
+        Ok(self.ids_of("Lamp", |_| true))
+    }
+
+    async fn find_lamps_matching(self, _: Context, pattern: String) -> Result<Vec<String>, Error> {
+        self.lag().await;
+        Ok(self.ids_of("Lamp", |id| glob_match(&pattern, id)))
+    }
+
+    async fn find_sinks(self, _: Context) -> Result<Vec<String>, Error> {
+        self.lag().await;
+        Ok(self.ids_of("Sink", |_| true))
+    }
+
+    // Lamp-specific API
+    async fn turn_lamp_on(self, _: Context, id: String) -> Result<ToggleResult, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_lamp_on_circuit(&id, |l| {
+                tracing::info!("Setting lamp {id} on property to true from {}", l.on);
+                let changed = !l.on;
+                l.on = true;
+                Ok(ToggleResult {
+                    state: true,
+                    changed,
+                })
+            })
+            .await?;
+        self.record_command(&id, "turn_lamp_on");
+        Ok(r)
+    }
+    async fn turn_lamp_off(self, _: Context, id: String) -> Result<ToggleResult, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_lamp(&id, |l| {
+                tracing::info!("Setting lamp {id} on property to false from {}", l.on);
+                let changed = l.on;
+                l.on = false;
+                Ok(ToggleResult {
+                    state: false,
+                    changed,
+                })
+            })
+            .await?;
+        self.record_command(&id, "turn_lamp_off");
+        Ok(r)
+    }
+    async fn get_lamp_on_off(self, _: Context, id: String) -> Result<bool, Error> {
+        self.apply_lamp(&id, |l| Ok(l.on)).await
+    }
+    async fn set_lamp_brightness(
+        self,
+        _: Context,
+        id: String,
+        brightness: u8,
+    ) -> Result<Clamped<u8>, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_lamp_on_circuit(&id, |l: &mut LampState| {
+                let applied = capped(l, Lamp::brightness_to_hi(brightness));
+                tracing::info!(
+                    "Setting lamp {id} brightness to {applied} from {}",
+                    l.brightness,
+                );
+                l.brightness = applied;
+                Ok(Clamped {
+                    requested: brightness,
+                    applied: Lamp::brightness_from_hi(applied),
+                })
+            })
+            .await?;
+        self.record_command(&id, "set_lamp_brightness");
+        Ok(r)
+    }
+    async fn adjust_lamp_brightness(self, _: Context, id: String, delta: i16) -> Result<u8, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_lamp_on_circuit(&id, |l: &mut LampState| {
+                let current = Lamp::brightness_from_hi(l.brightness);
+                let brightness = adjusted(current, delta, Lamp::BRIGHTNESS);
+                let brightness = capped(l, Lamp::brightness_to_hi(brightness));
+                tracing::info!(
+                    "Adjusting lamp {id} brightness to {brightness} from {}",
+                    l.brightness,
+                );
+                l.brightness = brightness;
+                Ok(Lamp::brightness_from_hi(brightness))
+            })
+            .await?;
+        self.record_command(&id, "adjust_lamp_brightness");
+        Ok(r)
+    }
+    async fn get_lamp_brightness(self, _: Context, id: String) -> Result<u8, Error> {
+        self.apply_lamp(&id, |l: &mut LampState| {
+            Ok(Lamp::brightness_from_hi(l.brightness))
+        })
+        .await
+    }
+    async fn set_lamp_brightness_hi(
+        self,
+        _: Context,
+        id: String,
+        brightness: u16,
+    ) -> Result<Clamped<u16>, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_lamp_on_circuit(&id, |l: &mut LampState| {
+                let applied = capped(l, brightness);
+                tracing::info!(
+                    "Setting lamp {id} brightness to {applied} from {}",
+                    l.brightness,
+                );
+                l.brightness = applied;
+                Ok(Clamped {
+                    requested: brightness,
+                    applied,
+                })
+            })
+            .await?;
+        self.record_command(&id, "set_lamp_brightness_hi");
+        Ok(r)
+    }
+    async fn get_lamp_brightness_hi(self, _: Context, id: String) -> Result<u16, Error> {
+        self.apply_lamp(&id, |l: &mut LampState| Ok(l.brightness))
+            .await
+    }
+    async fn ramp_lamp_brightness(
+        self,
+        ctx: Context,
+        id: String,
+        brightness: u8,
+        duration_ms: u32,
+    ) -> Result<u8, Error> {
+        self.admit(&id)?;
+        let (mut current, brightness) = self
+            .apply_lamp(&id, |l: &mut LampState| {
+                let brightness = capped(l, Lamp::brightness_to_hi(brightness));
+                Ok((
+                    Lamp::brightness_from_hi(l.brightness),
+                    Lamp::brightness_from_hi(brightness),
+                ))
+            })
+            .await?;
+
+        let delta = i16::from(brightness) - i16::from(current);
+        if delta == 0 {
+            return Ok(current);
+        }
+
+        tracing::info!("Ramping lamp {id} brightness to {brightness} from {current}");
+        self.record_command(&id, "ramp_lamp_brightness");
+
+        // The lock is released between the steps and the whole future is
+        // dropped by the server if the client cancels the request.
+        let step = Duration::from_millis(u64::from(duration_ms)) / u32::from(delta.unsigned_abs());
+        while current != brightness {
+            if SystemTime::now() + self.sim.clock.real(step) > ctx.deadline {
+                tracing::info!("Ramping lamp {id} stopped at {current}, deadline reached");
+                break;
+            }
+            self.sim.clock.sleep(step).await;
+
+            current = if delta > 0 { current + 1 } else { current - 1 };
+            self.apply_lamp_on_circuit(&id, |l: &mut LampState| {
+                l.brightness = Lamp::brightness_to_hi(current);
+                Ok(())
+            })
+            .await?;
+        }
+
+        Ok(current)
+    }
+    async fn wait_for_lamp_brightness(
+        self,
+        _: Context,
+        id: String,
+        target: u8,
+        timeout_ms: u32,
+    ) -> Result<bool, Error> {
+        let reached = async {
+            loop {
+                // Subscribed along with the reading, a change in between is not missed
+                let (brightness, mut changed) = self
+                    .apply(&id, |d| match d.kind {
+                        DeviceKind::Lamp(ref lamp) => Ok((
+                            Lamp::brightness_from_hi(lamp.brightness),
+                            d.changed.subscribe(),
+                        )),
+                        _ => Err(Error::Mismatch {
+                            found: d.kind.display().to_string(),
+                            req: "Lamp".to_string(),
+                        }),
+                    })
+                    .await?;
+                if brightness == target {
+                    return Ok(true);
+                }
+                // A removed device is reported by the next reading
+                let _ = changed.changed().await;
+            }
+        };
+
+        let timeout = Duration::from_millis(u64::from(timeout_ms));
+        tokio::time::timeout(timeout, reached)
+            .await
+            .unwrap_or(Ok(false))
+    }
+    async fn get_lamp_max_brightness(self, _: Context, id: String) -> Result<Option<u8>, Error> {
+        self.apply_lamp(&id, |l: &mut LampState| Ok(l.max_brightness))
+            .await
+    }
+    async fn get_lamp_color_temp(self, _: Context, id: String) -> Result<Option<u16>, Error> {
+        self.apply_lamp(&id, |l: &mut LampState| Ok(l.color_temp_kelvin))
+            .await
+    }
+    async fn set_lamp_color_temp(self, _: Context, id: String, kelvin: u16) -> Result<u16, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_lamp(&id, |l: &mut LampState| {
+                let Some(ref mut current) = l.color_temp_kelvin else {
+                    return Err(Error::Mismatch {
+                        found: "Lamp".to_string(),
+                        req: "Tunable white lamp".to_string(),
+                    });
+                };
+                let range = Lamp::COLOR_TEMPERATURE;
+                if !range.contains(&kelvin) {
+                    return Err(Error::OutOfRange {
+                        value: kelvin.into(),
+                        min: (*range.start()).into(),
+                        max: (*range.end()).into(),
+                    });
+                }
+                tracing::info!("Setting lamp {id} color temperature to {kelvin}K");
+                *current = kelvin;
+                Ok(kelvin)
+            })
+            .await?;
+        self.record_command(&id, "set_lamp_color_temp");
+        Ok(r)
+    }
+    async fn set_lamp_state(
+        self,
+        _: Context,
+        id: String,
+        state: LampState,
+    ) -> Result<LampState, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_lamp_on_circuit(&id, |l: &mut LampState| {
+                let color_temp_kelvin = match (state.color_temp_kelvin, l.color_temp_kelvin) {
+                    (None, current) => current,
+                    (Some(_), None) => {
+                        return Err(Error::Mismatch {
+                            found: "Lamp".to_string(),
+                            req: "Tunable white lamp".to_string(),
+                        })
+                    }
+                    (Some(kelvin), Some(_)) => {
+                        let range = Lamp::COLOR_TEMPERATURE;
+                        if !range.contains(&kelvin) {
+                            return Err(Error::OutOfRange {
+                                value: kelvin.into(),
+                                min: (*range.start()).into(),
+                                max: (*range.end()).into(),
+                            });
+                        }
+                        Some(kelvin)
+                    }
+                };
+
+                let new = LampState {
+                    brightness: capped(l, state.brightness),
+                    on: state.on,
+                    max_brightness: l.max_brightness,
+                    color_temp_kelvin,
+                };
+                tracing::info!("Setting lamp {id} state to {new:?} from {l:?}");
+                *l = new.clone();
+                Ok(new)
+            })
+            .await?;
+        self.record_command(&id, "set_lamp_state");
+        Ok(r)
+    }
+    async fn get_lamp_power(self, _: Context, id: String) -> Result<f32, Error> {
+        self.apply_lamp(&id, |l: &mut LampState| Ok(lamp_power(l)))
+            .await
+    }
+
+    // Sink-specific API
+    async fn set_sink_flow(self, _: Context, id: String, flow: u8) -> Result<Clamped<u8>, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_sink(&id, |s: &mut SinkState| {
+                s.flow = flow.min(*Sink::FLOW.end());
+                Ok(Clamped {
+                    requested: flow,
+                    applied: s.flow,
+                })
+            })
+            .await?;
+        self.record_command(&id, "set_sink_flow");
+        Ok(r)
+    }
+    async fn adjust_sink_flow(self, _: Context, id: String, delta: i16) -> Result<u8, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_sink(&id, |s: &mut SinkState| {
+                s.flow = adjusted(s.flow, delta, Sink::FLOW);
+                Ok(s.flow)
+            })
+            .await?;
+        self.record_command(&id, "adjust_sink_flow");
+        Ok(r)
+    }
+    async fn get_sink_flow(self, _: Context, id: String) -> Result<u8, Error> {
+        self.apply_sink(&id, |s: &mut SinkState| Ok(s.flow)).await
+    }
+    async fn set_sink_temp(self, _: Context, id: String, temp: u8) -> Result<u8, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_sink(&id, |s: &mut SinkState| {
+                if temp > s.max_safe_temp {
+                    let limit = format!(
+                        "Temperature {temp} above the {} limit of sink {id}",
+                        s.max_safe_temp
+                    );
+                    self.vet(&id, Hazard::Scald, Some(limit), Some(false))?;
+                }
+                s.temp = temp;
+                Ok(temp)
+            })
+            .await?;
+        self.record_command(&id, "set_sink_temp");
+        Ok(r)
+    }
+    async fn set_sink_temp_forced(self, _: Context, id: String, temp: u8) -> Result<u8, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_sink(&id, |s: &mut SinkState| {
+                if temp > s.max_safe_temp {
+                    let limit = format!(
+                        "Temperature {temp} above the {} limit of sink {id}",
+                        s.max_safe_temp
+                    );
+                    self.vet(&id, Hazard::Scald, Some(limit), Some(true))?;
+                    tracing::warn!(
+                        "Setting sink {id} temperature to {temp} past its {} limit",
+                        s.max_safe_temp
+                    );
+                }
+                s.temp = temp;
+                Ok(temp)
+            })
+            .await?;
+        self.record_command(&id, "set_sink_temp_forced");
+        Ok(r)
+    }
+    async fn get_sink_temp(self, _: Context, id: String) -> Result<u8, Error> {
+        self.apply_sink(&id, |s: &mut SinkState| Ok(s.temp)).await
+    }
+    async fn get_sink_max_temp(self, _: Context, id: String) -> Result<u8, Error> {
+        self.apply_sink(&id, |s: &mut SinkState| Ok(s.max_safe_temp))
+            .await
+    }
+    async fn set_sink_max_temp(self, _: Context, id: String, max_temp: u8) -> Result<u8, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_sink(&id, |s: &mut SinkState| {
+                if max_temp > s.max_safe_temp {
+                    let limit = format!(
+                        "Limit {max_temp} above the {} limit of sink {id}",
+                        s.max_safe_temp
+                    );
+                    self.vet(&id, Hazard::Scald, Some(limit), None)?;
+                }
+                tracing::info!("Limiting sink {id} temperature to {max_temp}");
+                s.max_safe_temp = max_temp;
+                s.temp = s.temp.min(max_temp);
+                Ok(max_temp)
+            })
+            .await?;
+        self.record_command(&id, "set_sink_max_temp");
+        Ok(r)
+    }
+    async fn close_sink_drain(self, _: Context, id: String) -> Result<bool, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_sink(&id, |s: &mut SinkState| {
+                s.drain = false;
+                Ok(false)
+            })
+            .await?;
+        self.record_command(&id, "close_sink_drain");
+        Ok(r)
+    }
+    async fn open_sink_drain(self, _: Context, id: String) -> Result<bool, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_sink(&id, |s: &mut SinkState| {
+                s.drain = true;
+                Ok(true)
+            })
+            .await?;
+        self.record_command(&id, "open_sink_drain");
+        Ok(r)
+    }
+    async fn stop_sink(self, _: Context, id: String) -> Result<(), Error> {
+        // The safe state is never rate limited, as for all_off
+        if self.locked_out(&id) {
+            return Err(Error::LockedOut(id));
+        }
+        self.apply_sink(&id, |s: &mut SinkState| {
+            tracing::info!("Stopping sink {id}, flow {} drain {}", s.flow, s.drain);
+            s.flow = 0;
+            s.drain = true;
+            Ok(())
+        })
+        .await?;
+        self.record(&id, "stop_sink");
+        Ok(())
+    }
+    async fn get_sink_drain(self, _: Context, id: String) -> Result<bool, Error> {
+        self.apply_sink(&id, |s: &mut SinkState| Ok(s.drain)).await
+    }
+    async fn get_sink_level(self, _: Context, id: String) -> Result<u8, Error> {
+        self.apply_sink(&id, |s: &mut SinkState| Ok(s.level)).await
+    }
+
+    async fn find_doors(self, _: Context) -> Result<Vec<String>, Error> {
+        self.lag().await;
+        Ok(self.ids_of("Door", |_| true))
+    }
+
+    async fn get_door_lock_status(self, _: Context, id: String) -> Result<DoorLockStatus, Error> {
+        self.apply_door(&id, |s: &mut DoorState| Ok(s.lock)).await
+    }
+
+    async fn get_door_open(self, _: Context, id: String) -> Result<bool, Error> {
+        self.apply_door(&id, |s: &mut DoorState| Ok(s.is_open))
+            .await
+    }
+
+    async fn lock_door(self, _: Context, id: String) -> Result<LockResult, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_door(&id, |s: &mut DoorState| {
+                Ok(lock(s, &mut *self.rng.lock().unwrap()))
+            })
+            .await?;
+        self.record_command(&id, "lock_door");
+        Ok(r)
+    }
+
+    async fn lock_door_retry(self, _: Context, id: String, attempts: u8) -> Result<bool, Error> {
+        self.admit(&id)?;
+        let attempts = attempts.max(1);
+
+        for attempt in 1..=attempts {
+            let locked = self
+                .apply_door(&id, |s: &mut DoorState| {
+                    let mut rng = self.rng.lock().unwrap();
+                    if s.lock == DoorLockStatus::Jammed && rng.gen_bool(JAM_CLEAR_RATE) {
+                        tracing::info!("Door {id} lock freed at attempt {attempt}");
+                        s.lock = DoorLockStatus::Unlocked;
+                    }
+                    Ok(lock(s, &mut *rng).is_success())
+                })
+                .await?;
+
+            if locked {
+                self.record_command(&id, "lock_door_retry");
+                return Ok(true);
+            }
+            if attempt < attempts {
+                self.sim.clock.sleep(JAM_RETRY_DELAY).await;
+            }
+        }
+
+        tracing::info!("Door {id} still jammed after {attempts} attempts");
+        Ok(false)
+    }
+
+    async fn unlock_door(self, _: Context, id: String) -> Result<LockResult, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_door(&id, |s: &mut DoorState| {
+                Ok(set_lock(
+                    s,
+                    DoorLockStatus::Unlocked,
+                    &mut *self.rng.lock().unwrap(),
+                ))
+            })
+            .await?;
+        self.record_command(&id, "unlock_door");
+        Ok(r)
+    }
+
+    async fn set_door_open(self, _: Context, id: String, open: bool) -> Result<bool, Error> {
+        testing_only("set_door_open")?;
+
+        let r = self
+            .apply_door(&id, |s: &mut DoorState| {
+                if open && s.lock == DoorLockStatus::Locked {
+                    return Err(Error::Forbidden {
+                        risk: Hazard::Intrusion,
+                        comment: format!("door {id} is locked, unlock it first"),
+                        retriable_with_ack: false,
+                    });
+                }
+                s.is_open = open;
+                Ok(open)
+            })
+            .await?;
+        self.record(&id, "set_door_open");
+        Ok(r)
+    }
+
+    async fn find_fridges(self, _: Context) -> Result<Vec<String>, Error> {
+        self.lag().await;
+        Ok(self.ids_of("Fridge", |_| true))
+    }
+
+    async fn get_fridge_temperature(self, _: Context, id: String) -> Result<i8, Error> {
+        self.apply_fridge(&id, |s: &mut FridgeState| Ok(s.temperature))
+            .await
+    }
+
+    async fn get_fridge_target_temperature(self, _: Context, id: String) -> Result<i8, Error> {
+        self.apply_fridge(&id, |s: &mut FridgeState| Ok(s.target_temperature))
+            .await
+    }
+
+    async fn set_fridge_target_temperature(
+        self,
+        _: Context,
+        id: String,
+        target_temperature: i8,
+    ) -> Result<i8, Error> {
+        self.admit(&id)?;
+        let range = self.fridge_targets.clone();
+        if !range.contains(&target_temperature) {
+            return Err(Error::OutOfRange {
+                value: target_temperature.into(),
+                min: (*range.start()).into(),
+                max: (*range.end()).into(),
+            });
+        }
+        let r = self
+            .apply_fridge(&id, |s: &mut FridgeState| {
+                s.target_temperature = target_temperature;
+                Ok(target_temperature)
+            })
+            .await?;
+        self.record_command(&id, "set_fridge_target_temperature");
+        Ok(r)
+    }
+
+    async fn adjust_fridge_target_temperature(
+        self,
+        _: Context,
+        id: String,
+        delta: i16,
+    ) -> Result<i8, Error> {
+        self.admit(&id)?;
+        let range = self.fridge_targets.clone();
+        let r = self
+            .apply_fridge(&id, |s: &mut FridgeState| {
+                s.target_temperature = adjusted(s.target_temperature, delta, range);
+                Ok(s.target_temperature)
+            })
+            .await?;
+        self.record_command(&id, "adjust_fridge_target_temperature");
+        Ok(r)
+    }
+
+    async fn get_fridge_open(self, _: Context, id: String) -> Result<bool, Error> {
+        self.apply_fridge(&id, |s: &mut FridgeState| Ok(s.open))
+            .await
+    }
+
+    async fn get_fridge_open_duration(self, _: Context, id: String) -> Result<Option<u64>, Error> {
+        let now = self.sim.clock.now();
+        self.apply(&id, |d| match d.kind {
+            DeviceKind::Fridge(ref fridge) => Ok(d
+                .opened_at
+                .filter(|_| fridge.open)
+                .map(|opened_at| now.saturating_sub(opened_at).as_secs())),
+            _ => Err(Error::Mismatch {
+                found: d.kind.display().to_string(),
+                req: "Fridge".to_string(),
+            }),
+        })
+        .await
+    }
+
+    async fn set_fridge_open(self, _: Context, id: String, open: bool) -> Result<bool, Error> {
+        testing_only("set_fridge_open")?;
+
+        let now = self.sim.clock.now();
+        let r = self
+            .apply(&id, |d| match d.kind {
+                DeviceKind::Fridge(ref mut fridge) => {
+                    if !open {
+                        d.opened_at = None;
+                    } else if !fridge.open {
+                        d.opened_at = Some(now);
+                    }
+                    fridge.open = open;
+                    Ok(open)
+                }
+                _ => Err(Error::Mismatch {
+                    found: d.kind.display().to_string(),
+                    req: "Fridge".to_string(),
+                }),
+            })
+            .await?;
+        self.record(&id, "set_fridge_open");
+        Ok(r)
+    }
+
+    async fn find_curtains(self, _: Context) -> Result<Vec<String>, Error> {
+        self.lag().await;
+        Ok(self.ids_of("Curtain", |_| true))
+    }
+
+    // The simulated curtains move instantly, they never report `moving`
+    async fn open_curtain(self, _: Context, id: String) -> Result<bool, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_curtain(&id, |c: &mut CurtainState| {
+                c.open = true;
+                Ok(c.open)
+            })
+            .await?;
+        self.record_command(&id, "open_curtain");
+        Ok(r)
+    }
+
+    async fn close_curtain(self, _: Context, id: String) -> Result<bool, Error> {
+        self.admit(&id)?;
+        let r = self
+            .apply_curtain(&id, |c: &mut CurtainState| {
+                c.open = false;
+                Ok(c.open)
+            })
+            .await?;
+        self.record_command(&id, "close_curtain");
+        Ok(r)
+    }
+
+    async fn get_curtain_open(self, _: Context, id: String) -> Result<bool, Error> {
+        self.apply_curtain(&id, |c: &mut CurtainState| Ok(c.open))
+            .await
+    }
+
+    async fn all_off(self, _: Context) -> Result<Vec<(String, Result<(), Error>)>, Error> {
+        let mut res = Vec::new();
+
+        for (id, dev) in devices_of(&self.devices, None) {
+            let mut d = self.lock_prioritized(&id, dev).await;
+            let mut safe = d.kind.clone();
+            // Only the devices set to a safe state can be refused
+            if !make_safe(&mut safe) {
+                continue;
+            }
+            if self.locked_out(&id) {
+                res.push((id.clone(), Err(Error::LockedOut(id))));
+                continue;
+            }
+            if self.offline(&id) {
+                res.push((id.clone(), Err(Error::Offline(id))));
+                continue;
+            }
+            if d.kind != safe {
+                d.kind = safe;
+                d.changed.send_replace(());
+            }
+            tracing::info!("Device {id} set to its safe state");
+            self.record(&id, "all_off");
+            res.push((id, Ok(())));
+        }
+
+        Ok(res)
+    }
+
+    async fn lock_all_doors(self, _: Context) -> Result<Vec<(String, bool)>, Error> {
+        let mut res = Vec::new();
+
+        for (id, dev) in devices_of(&self.devices, Some("Door")) {
+            if self.locked_out(&id) || self.offline(&id) {
+                res.push((id, false));
+                continue;
+            }
+            if let DeviceKind::Door(ref mut door) = self.lock_prioritized(&id, dev).await.kind {
+                let locked = lock(door, &mut *self.rng.lock().unwrap()).is_success();
+                tracing::info!("Locking door {id}: {}", door.lock);
+                self.record(&id, "lock_all_doors");
+                res.push((id, locked));
+            }
+        }
+
+        Ok(res)
+    }
+
+    async fn total_power(self, _: Context) -> Result<f32, Error> {
+        Ok(self.circuit_load(None).await)
+    }
+
+    async fn get_circuit_load(self, _: Context) -> Result<f32, Error> {
+        Ok(self.circuit_load(None).await)
+    }
+
+    async fn snapshot(self, _: Context) -> Result<HomeSnapshot, Error> {
+        let mut snapshot = HomeSnapshot::default();
+
+        for (id, dev) in devices_of(&self.devices, None) {
+            match dev.lock().await.kind {
+                DeviceKind::Lamp(ref l) => snapshot.lamps.push((id, l.clone())),
+                DeviceKind::Sink(ref s) => snapshot.sinks.push((id, s.clone())),
+                DeviceKind::Door(ref d) => snapshot.doors.push((id, d.clone())),
+                DeviceKind::Fridge(ref f) => snapshot.fridges.push((id, f.clone())),
+                DeviceKind::Curtain(ref c) => snapshot.curtains.push((id, c.clone())),
+            }
+        }
+
+        snapshot.lamps.sort_by(|a, b| a.0.cmp(&b.0));
+        snapshot.sinks.sort_by(|a, b| a.0.cmp(&b.0));
+        snapshot.doors.sort_by(|a, b| a.0.cmp(&b.0));
+        snapshot.fridges.sort_by(|a, b| a.0.cmp(&b.0));
+        snapshot.curtains.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(snapshot)
+    }
+
+    async fn restore_snapshot(
+        self,
+        _: Context,
+        snapshot: HomeSnapshot,
+    ) -> Result<Vec<(String, Result<(), Error>)>, Error> {
+        Ok(self.restore_all(snapshot, "restore_snapshot").await)
+    }
+
+    async fn save_scene(self, _: Context, name: String, scene: HomeSnapshot) -> Result<(), Error> {
+        info!("Scene {name} saved");
+        self.scenes.lock().unwrap().saved.insert(name, scene);
+
+        Ok(())
+    }
+
+    async fn schedule_scene(
+        self,
+        _: Context,
+        name: String,
+        at: SystemTime,
+    ) -> Result<ScheduleId, Error> {
+        let wait = at.duration_since(SystemTime::now()).unwrap_or_default();
+        let backend = self.clone();
+        // Held until the schedule is listed, for the task to find it
+        let mut scenes = self.scenes.lock().unwrap();
+
+        if !scenes.saved.contains_key(&name) {
+            return Err(Error::InvalidArgument(format!("No scene named {name}")));
+        }
+        scenes.next += 1;
+        let id = ScheduleId(scenes.next);
+
+        info!("Scheduling scene {name} as {id} in {wait:?}");
+        let task = tokio::spawn({
+            let name = name.clone();
+            async move {
+                tokio::time::sleep(wait).await;
+                let scene = {
+                    let mut scenes = backend.scenes.lock().unwrap();
+                    scenes.pending.remove(&id);
+                    scenes.saved.get(&name).cloned()
+                };
+                // The scene is the one saved when it runs, not when it was
+                // scheduled
+                if let Some(scene) = scene {
+                    info!("Running scene {name}, scheduled as {id}");
+                    backend.run_scene(&name, scene).await;
+                }
+            }
+        });
+        let schedule = Schedule { id, name, at };
+        scenes.pending.insert(id, (schedule, task.abort_handle()));
+
+        Ok(id)
+    }
+
+    async fn cancel_schedule(self, _: Context, id: ScheduleId) -> Result<(), Error> {
+        let (schedule, task) = self
+            .scenes
+            .lock()
+            .unwrap()
+            .pending
+            .remove(&id)
+            .ok_or_else(|| Error::InvalidArgument(format!("No scene scheduled as {id}")))?;
+        task.abort();
+        info!("Scene {} scheduled as {id} cancelled", schedule.name);
+
+        Ok(())
+    }
+
+    async fn list_schedules(self, _: Context) -> Result<Vec<Schedule>, Error> {
+        let scenes = self.scenes.lock().unwrap();
+
+        Ok(scenes
+            .pending
+            .values()
+            .map(|(schedule, _)| schedule.clone())
+            .collect())
+    }
+
+    async fn add_device(self, _: Context, id: String, spec: DeviceSpec) -> Result<(), Error> {
+        let mut devs = self.devices.lock().unwrap();
+
+        if devs.contains_key(&id) {
+            return Err(Error::AlreadyExists(id));
+        }
+
+        let entry = Entry::new(spec, self.sim.clock.now());
+        info!("Adding {} {id}: {}", entry.kind, entry.name);
+        let kind = entry.kind.to_owned();
+        self.record_change(&id, "add_device", Change::Added { kind });
+        devs.insert(id, entry);
+
+        Ok(())
+    }
+
+    async fn remove_device(self, _: Context, id: String) -> Result<(), Error> {
+        let entry = self
+            .devices
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .ok_or_else(|| Error::NotFound(id.clone()))?;
+
+        info!("Removed {} {id}", entry.kind);
+        if let Some(limit) = &self.rate_limit {
+            limit.forget(&id);
+        }
+        self.record_change(&id, "remove_device", Change::Removed);
+
+        Ok(())
+    }
+
+    async fn set_device_lockout(self, _: Context, id: String, locked: bool) -> Result<(), Error> {
+        self.devices
+            .lock()
+            .unwrap()
+            .get_mut(&id)
+            .ok_or_else(|| Error::NotFound(id.clone()))?
+            .lockout = locked;
+
+        info!("Device {id} locked out: {locked}");
+        self.record(&id, "set_device_lockout");
+
+        Ok(())
+    }
+
+    async fn set_device_online(self, _: Context, id: String, online: bool) -> Result<(), Error> {
+        testing_only("set_device_online")?;
+
+        self.devices
+            .lock()
+            .unwrap()
+            .get_mut(&id)
+            .ok_or_else(|| Error::NotFound(id.clone()))?
+            .offline = !online;
+        info!("Device {id} online: {online}");
+
+        Ok(())
+    }
+
+    async fn get_device_lockout(self, _: Context, id: String) -> Result<bool, Error> {
+        self.devices
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|entry| entry.lockout)
+            .ok_or(Error::NotFound(id))
+    }
+
+    async fn get_maintenance_status(
+        self,
+        _: Context,
+        id: String,
+    ) -> Result<MaintenanceStatus, Error> {
+        self.apply(&id, |d| Ok(d.maintenance)).await
+    }
+
+    async fn find_all_devices(self, _: Context) -> Result<Vec<DeviceInfo>, Error> {
+        self.lag().await;
+        Ok(self.device_infos(|_| true))
+    }
+
+    async fn find_devices_in_room(
+        self,
+        _: Context,
+        room: String,
+    ) -> Result<Vec<DeviceInfo>, Error> {
+        self.lag().await;
+        Ok(self.device_infos(|entry| entry.room.as_ref() == Some(&room)))
+    }
+
+    async fn list_rooms(self, _: Context) -> Result<Vec<String>, Error> {
+        self.lag().await;
+        let rooms: BTreeSet<_> = self
+            .devices
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|entry| entry.room.clone())
+            .collect();
+
+        Ok(rooms.into_iter().collect())
+    }
+
+    async fn scan_health(self, _: Context) -> Result<Vec<DeviceHealth>, Error> {
+        let entries: Vec<_> = self
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| (id.clone(), entry.kind))
+            .collect();
+
+        // The devices are probed at once, a slow one does not delay the others
+        let backend = &self;
+        let probes = entries.into_iter().map(|(id, kind)| async move {
+            let (status, detail) = match backend.apply(&id, |d| Ok(degradation(&d.kind))).await {
+                Ok(None) => (HealthStatus::Ok, None),
+                Ok(Some(detail)) => (HealthStatus::Degraded, Some(detail)),
+                Err(e) => (HealthStatus::Offline, Some(e.to_string())),
+            };
+            DeviceHealth {
+                id,
+                kind: kind.to_owned(),
+                status,
+                detail,
+            }
+        });
+        let mut res = futures::future::join_all(probes).await;
+        res.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(res)
+    }
+
+    async fn get_device_kind(self, _: Context, id: String) -> Result<String, Error> {
+        self.apply(&id, |d| Ok(d.kind.display().to_owned())).await
+    }
+
+    async fn get_capabilities(self, _: Context, id: String) -> Result<Capabilities, Error> {
+        self.apply(&id, |d| Ok(self.capabilities(&d.kind))).await
+    }
+
+    async fn active_hazards(self, _: Context, id: String) -> Result<Vec<Hazard>, Error> {
+        self.apply(&id, |d| Ok(active_hazards(&d.kind))).await
+    }
+
+    async fn invoke(
+        self,
+        ctx: Context,
+        id: String,
+        op: String,
+        args: String,
+    ) -> Result<String, Error> {
+        let args: Vec<Value> =
+            serde_json::from_str(&args).map_err(|e| Error::InvalidArgument(e.to_string()))?;
+        // Not through `apply`, the faults are injected by the operation itself
+        let kind = self
+            .devices
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|entry| entry.kind)
+            .ok_or_else(|| Error::NotFound(id.clone()))?;
+
+        let r = match (kind, op.as_str()) {
+            ("Lamp", "turn_on") => json(self.turn_lamp_on(ctx, id).await?),
+            ("Lamp", "turn_off") => json(self.turn_lamp_off(ctx, id).await?),
+            ("Lamp", "get_on_off") => json(self.get_lamp_on_off(ctx, id).await?),
+            ("Lamp", "get_brightness") => json(self.get_lamp_brightness(ctx, id).await?),
+            ("Lamp", "set_brightness") => {
+                json(self.set_lamp_brightness(ctx, id, arg(&args, 0)?).await?)
+            }
+            ("Lamp", "get_brightness_hi") => json(self.get_lamp_brightness_hi(ctx, id).await?),
+            ("Lamp", "set_brightness_hi") => {
+                json(self.set_lamp_brightness_hi(ctx, id, arg(&args, 0)?).await?)
+            }
+            ("Lamp", "max_brightness") => json(self.get_lamp_max_brightness(ctx, id).await?),
+            ("Lamp", "color_temperature") => json(self.get_lamp_color_temp(ctx, id).await?),
+            ("Lamp", "set_color_temperature") => {
+                json(self.set_lamp_color_temp(ctx, id, arg(&args, 0)?).await?)
+            }
+            ("Lamp", "power") => json(self.get_lamp_power(ctx, id).await?),
+            ("Lamp", "adjust_brightness") => {
+                json(self.adjust_lamp_brightness(ctx, id, arg(&args, 0)?).await?)
+            }
+            ("Sink", "open_drain") => json(self.open_sink_drain(ctx, id).await?),
+            ("Sink", "close_drain") => json(self.close_sink_drain(ctx, id).await?),
+            ("Sink", "stop") => json(self.stop_sink(ctx, id).await?),
+            ("Sink", "get_drain") => json(self.get_sink_drain(ctx, id).await?),
+            ("Sink", "get_water_level") => json(self.get_sink_level(ctx, id).await?),
+            ("Sink", "set_flow") => json(self.set_sink_flow(ctx, id, arg(&args, 0)?).await?),
+            ("Sink", "get_flow") => json(self.get_sink_flow(ctx, id).await?),
+            ("Sink", "adjust_flow") => json(self.adjust_sink_flow(ctx, id, arg(&args, 0)?).await?),
+            ("Sink", "set_temperature") => json(self.set_sink_temp(ctx, id, arg(&args, 0)?).await?),
+            ("Sink", "set_temperature_forced") => {
+                json(self.set_sink_temp_forced(ctx, id, arg(&args, 0)?).await?)
+            }
+            ("Sink", "get_temperature") => json(self.get_sink_temp(ctx, id).await?),
+            ("Sink", "max_temperature") => json(self.get_sink_max_temp(ctx, id).await?),
+            ("Sink", "set_max_temperature") => {
+                json(self.set_sink_max_temp(ctx, id, arg(&args, 0)?).await?)
+            }
+            ("Door", "is_open") => json(self.get_door_open(ctx, id).await?),
+            ("Door", "lock_status") => json(self.get_door_lock_status(ctx, id).await?),
+            ("Door", "lock") => json(self.lock_door(ctx, id).await?),
+            ("Door", "unlock") => json(self.unlock_door(ctx, id).await?),
+            ("Door", "lock_with_retries") => {
+                json(self.lock_door_retry(ctx, id, arg(&args, 0)?).await?)
+            }
+            ("Fridge", "is_open") => json(self.get_fridge_open(ctx, id).await?),
+            ("Fridge", "open_duration") => json(self.get_fridge_open_duration(ctx, id).await?),
+            ("Fridge", "temperature") => json(self.get_fridge_temperature(ctx, id).await?),
+            ("Fridge", "target_temperature") => {
+                json(self.get_fridge_target_temperature(ctx, id).await?)
+            }
+            ("Fridge", "set_target_temperature") => json(
+                self.set_fridge_target_temperature(ctx, id, arg(&args, 0)?)
+                    .await?,
+            ),
+            ("Fridge", "adjust_target_temperature") => json(
+                self.adjust_fridge_target_temperature(ctx, id, arg(&args, 0)?)
+                    .await?,
+            ),
+            ("Curtain", "is_open") => json(self.get_curtain_open(ctx, id).await?),
+            ("Curtain", "open") => json(self.open_curtain(ctx, id).await?),
+            ("Curtain", "close") => json(self.close_curtain(ctx, id).await?),
+            (_, "hazards") => json(self.active_hazards(ctx, id).await?),
+            _ => {
+                return Err(Error::Mismatch {
+                    found: kind,
+                    req: op,
+                })
+            }
+        };
+
+        Ok(r)
+    }
+
+    async fn get_property(
+        self,
+        ctx: Context,
+        id: String,
+        property: String,
+    ) -> Result<String, Error> {
+        let property = self.property(&id, &property).await?;
+        self.invoke(ctx, id, property.get, "[]".to_owned()).await
+    }
+
+    async fn set_property(
+        self,
+        ctx: Context,
+        id: String,
+        property: String,
+        value: String,
+    ) -> Result<String, Error> {
+        let property = self.property(&id, &property).await?;
+        let set = property.set.ok_or_else(|| {
+            Error::InvalidArgument(format!("{} of {id} cannot be set", property.name))
+        })?;
+        let value: Value =
+            serde_json::from_str(&value).map_err(|e| Error::InvalidArgument(e.to_string()))?;
+        // The typed clients check the ranges before sending the request
+        if let (Some(range), Some(value)) = (property.value.range(), value.as_i64()) {
+            if !range.contains(value) {
+                return Err(Error::OutOfRange {
+                    value,
+                    min: range.min,
+                    max: range.max,
+                });
+            }
+        }
+        self.invoke(ctx, id, set, Value::Array(vec![value]).to_string())
+            .await
+    }
+
+    async fn get_property_range(
+        self,
+        _: Context,
+        id: String,
+        property: String,
+    ) -> Result<Range, Error> {
+        let property = self.property(&id, &property).await?;
+        property.value.range().ok_or_else(|| {
+            Error::InvalidArgument(format!("{} of {id} is not a whole number", property.name))
+        })
+    }
+
+    async fn describe_operation(self, _: Context, name: String) -> Result<OperationDoc, Error> {
+        OperationDoc::of(&name)
+            .ok_or_else(|| Error::InvalidArgument(format!("{name} is not a device operation")))
+    }
+
+    async fn handshake(self, _: Context, client_version: u32) -> Result<u32, Error> {
+        if client_version != PROTOCOL_VERSION {
+            tracing::warn!(
+                "Client speaking protocol version {client_version}, expected {PROTOCOL_VERSION}"
+            );
+        }
+
+        Ok(PROTOCOL_VERSION)
+    }
+
+    async fn server_info(self, _: Context) -> Result<ServerInfo, Error> {
+        let mut methods: Vec<_> = METHODS
+            .iter()
+            .filter(|method| !self.unsupported.contains(**method))
+            .filter(|method| cfg!(feature = "testing") || !TESTING_METHODS.contains(*method))
+            .filter(|method| cfg!(feature = "debug-api") || **method != "dump_state")
+            .map(|method| method.to_string())
+            .collect();
+        methods.sort();
+
+        Ok(ServerInfo {
+            protocol_version: PROTOCOL_VERSION,
+            methods,
+        })
+    }
+
+    async fn ping(self, _: Context) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn metrics_text(self, _: Context) -> Result<String, Error> {
+        let mut kinds = BTreeMap::new();
+        for entry in self.devices.lock().unwrap().values() {
+            *kinds.entry(entry.kind).or_insert(0) += 1;
+        }
+        let power = self.circuit_load(None).await;
+        // No call is counted while the metrics are disabled
+        let disabled = Metrics::default();
+        let metrics = self.metrics.as_deref().unwrap_or(&disabled);
+        let mut out = String::new();
+
+        writeln!(
+            out,
+            "# HELP sifis_calls_total Calls served, by method and outcome."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE sifis_calls_total counter").unwrap();
+        for ((method, outcome), count) in metrics.calls.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "sifis_calls_total{{method=\"{method}\",outcome=\"{outcome}\"}} {count}"
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "# HELP sifis_call_duration_seconds Time taken to serve the calls, by method."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE sifis_call_duration_seconds histogram").unwrap();
+        for (method, histogram) in metrics.latency.lock().unwrap().iter() {
+            let mut calls = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                calls += count;
+                writeln!(
+                    out,
+                    "sifis_call_duration_seconds_bucket{{method=\"{method}\",le=\"{le}\"}} {calls}"
+                )
+                .unwrap();
+            }
+            let (sum, count) = (histogram.sum, histogram.count);
+            writeln!(
+                out,
+                "sifis_call_duration_seconds_bucket{{method=\"{method}\",le=\"+Inf\"}} {count}"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "sifis_call_duration_seconds_sum{{method=\"{method}\"}} {sum}"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "sifis_call_duration_seconds_count{{method=\"{method}\"}} {count}"
+            )
+            .unwrap();
+        }
+        writeln!(out, "# HELP sifis_devices Devices in the home, by kind.").unwrap();
+        writeln!(out, "# TYPE sifis_devices gauge").unwrap();
+        for (kind, count) in kinds {
+            writeln!(out, "sifis_devices{{kind=\"{kind}\"}} {count}").unwrap();
+        }
+        writeln!(
+            out,
+            "# HELP sifis_power_watts Power drawn by all the devices."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE sifis_power_watts gauge").unwrap();
+        writeln!(out, "sifis_power_watts {power}").unwrap();
+
+        Ok(out)
+    }
+
+    async fn dump_state(self, _: Context) -> Result<String, Error> {
+        // Kept in the trait without the feature, the encoding of the other
+        // requests does not depend on it
+        if !cfg!(feature = "debug-api") {
+            return Err(Error::Unsupported {
+                method: "dump_state".to_owned(),
+            });
+        }
+
+        let entries: Vec<_> = self
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| {
+                let fields = serde_json::json!({
+                    "name": entry.name,
+                    "room": entry.room,
+                    "lockout": entry.lockout,
+                    "offline": entry.offline,
+                });
+                (id.clone(), fields, entry.device.clone())
+            })
+            .collect();
+
+        // Sorted by id
+        let mut dump = serde_json::Map::new();
+        for (id, mut fields, device) in entries {
+            let d = device.lock().await;
+            fields["state"] = serde_json::json!(d.kind);
+            fields["maintenance"] = serde_json::json!(d.maintenance);
+            fields["opened_at_secs"] = serde_json::json!(d.opened_at.map(|t| t.as_secs()));
+            dump.insert(id, fields);
+        }
+
+        Ok(Value::Object(dump).to_string())
+    }
+
+    async fn recent_events(self, _: Context, limit: usize) -> Result<Vec<DeviceEvent>, Error> {
+        let log = self.events.lock().unwrap();
+        let skip = log.events.len().saturating_sub(limit);
+
+        Ok(log.events.iter().skip(skip).cloned().collect())
+    }
+
+    async fn wait_events(
+        self,
+        _: Context,
+        after: Option<u64>,
+        timeout_ms: u32,
+    ) -> Result<EventBatch, Error> {
+        let Some(after) = after else {
+            let next = self.events.lock().unwrap().next;
+            return Ok(EventBatch {
+                next,
+                ..Default::default()
+            });
+        };
+
+        // The log is never locked across the wait, the writers never block
+        let mut notify = self.events.lock().unwrap().notify.subscribe();
+        let timeout = Duration::from_millis(u64::from(timeout_ms));
+        let _ = tokio::time::timeout(timeout, notify.wait_for(|&next| next > after)).await;
+
+        Ok(self.events.lock().unwrap().since(after))
+    }
+
+    async fn wait_device_events(
+        self,
+        ctx: Context,
+        id: String,
+        after: Option<u64>,
+        timeout_ms: u32,
+    ) -> Result<EventBatch, Error> {
+        let mut batch = self.wait_events(ctx, after, timeout_ms).await?;
+        batch.events.retain(|event| event.device_id == id);
+
+        Ok(batch)
+    }
+
+    async fn advance_time(self, _: Context, seconds: u64) -> Result<(), Error> {
+        testing_only("advance_time")?;
+
+        info!("Advancing the simulated time by {seconds}s");
+        self.sim.clock.advance(Duration::from_secs(seconds));
+        self.sim.catch_up(&self.devices, &self.events).await;
+
+        Ok(())
+    }
+
+    async fn prioritized(
+        self,
+        ctx: Context,
+        priority: Priority,
+        request: Box<SifisApiRequest>,
+    ) -> Result<Box<SifisApiResponse>, Error> {
+        if let Some(response) = refused(&self.unsupported, &request) {
+            return Ok(Box::new(response));
+        }
+        let backend = InMemoryBackend { priority, ..self };
+        Ok(Box::new(backend.serve().serve(ctx, *request).await))
+    }
+}
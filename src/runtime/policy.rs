@@ -0,0 +1,69 @@
+//! Hazard policy of the mock runtime
+//!
+//! [InMemoryBackend](super::InMemoryBackend) asks its policy before an
+//! operation exposes the home to a new hazard, e.g. lighting a lamp, or goes
+//! past one of its safety limits, e.g. the maximum temperature of a sink.
+//! An embedder can supply its own policy to defer to an external authority,
+//! like a usage control system.
+
+use std::fmt::Debug;
+
+use crate::Hazard;
+
+/// An operation about to expose the home to a hazard
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HazardousOperation {
+    /// Device the operation commands
+    pub device_id: String,
+    /// Hazard the device would present
+    pub hazard: Hazard,
+    /// Safety limit of the runtime the operation goes past, if any, e.g.
+    /// the maximum temperature of a sink
+    pub limit_exceeded: Option<String>,
+    /// Whether the user acknowledged the risk, with a `*_forced` operation
+    pub acknowledged: bool,
+}
+
+/// Decision of a [HazardPolicy]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Carry the operation out
+    Allow,
+    /// Refuse the operation
+    Deny,
+    /// Carry the operation out only once the user acknowledged the risk,
+    /// refuse it otherwise
+    RequireAck,
+}
+
+/// Decides whether the hazardous operations are carried out
+///
+/// A refused operation fails with
+/// [Error::Forbidden](crate::service::Error::Forbidden) and leaves the
+/// device unchanged, its `retriable_with_ack` is set if the policy requires
+/// an acknowledgement the operation can be retried with.
+pub trait HazardPolicy: Debug + Send + Sync {
+    /// Decide on `operation`, before it is carried out.
+    ///
+    /// It is called with the device held, it should answer quickly.
+    fn check(&self, operation: &HazardousOperation) -> Verdict;
+}
+
+/// The built-in safety limits
+///
+/// The operations within the limits are allowed. Going past the maximum
+/// temperature of a sink requires an acknowledgement, going past the others,
+/// like the breaker limit, is denied.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultHazardPolicy;
+
+impl HazardPolicy for DefaultHazardPolicy {
+    fn check(&self, operation: &HazardousOperation) -> Verdict {
+        match operation.limit_exceeded {
+            None => Verdict::Allow,
+            Some(_) if operation.hazard == Hazard::Scald => Verdict::RequireAck,
+            Some(_) => Verdict::Deny,
+        }
+    }
+}
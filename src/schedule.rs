@@ -0,0 +1,71 @@
+//! Scenes run at a given time, e.g. a morning routine
+//!
+//! A scene is a [HomeSnapshot] saved under a name with
+//! [Sifis::save_scene], the runtime sets the devices back to the states it
+//! records once its time comes.
+
+use std::fmt;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{HomeSnapshot, Result, Sifis};
+
+/// Identifier of a scheduled scene, given by the runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ScheduleId(pub u64);
+
+impl fmt::Display for ScheduleId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Scene waiting for its time, see [Sifis::schedule_scene]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schedule {
+    /// Identifier to cancel it with
+    pub id: ScheduleId,
+    /// Name of the scene, e.g. `morning`
+    pub name: String,
+    /// When the scene runs
+    pub at: SystemTime,
+}
+
+impl Sifis {
+    /// Save `scene` under the name `name`, replacing the scene of the same
+    /// name if any.
+    ///
+    /// The scenes are kept until the runtime stops, they are not persisted.
+    pub async fn save_scene(&self, name: &str, scene: HomeSnapshot) -> Result<()> {
+        self.client
+            .save_scene(self.context(), name.to_owned(), scene)
+            .await??;
+        Ok(())
+    }
+
+    /// Run the scene saved as `name` at the time `at`.
+    ///
+    /// A time already past runs it right away. The outcome of each device
+    /// is only logged by the runtime, a device that cannot be set does not
+    /// stop the others. The schedules are lost when the runtime stops.
+    pub async fn schedule_scene(&self, name: &str, at: SystemTime) -> Result<ScheduleId> {
+        let r = self
+            .client
+            .schedule_scene(self.context(), name.to_owned(), at)
+            .await??;
+        Ok(r)
+    }
+
+    /// Cancel the scene scheduled as `id`, if it has not run yet.
+    pub async fn cancel_schedule(&self, id: ScheduleId) -> Result<()> {
+        self.client.cancel_schedule(self.context(), id).await??;
+        Ok(())
+    }
+
+    /// Get the scenes waiting for their time, by id.
+    pub async fn list_schedules(&self) -> Result<Vec<Schedule>> {
+        let r = self.client.list_schedules(self.context()).await??;
+        Ok(r)
+    }
+}
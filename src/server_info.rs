@@ -0,0 +1,119 @@
+//! Methods a runtime serves, for the clients to degrade gracefully when it
+//! is older than them
+//!
+//! e.g. a client may fall back to polling [Sifis::recent_events] if the
+//! runtime does not serve [SifisApi::wait_events](crate::service::SifisApi::wait_events).
+
+use std::fmt::{self, Debug, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::service::{SifisApiRequest, SifisApiResponse, METHODS};
+use crate::{Result, Sifis};
+
+/// Description of a runtime, see [Sifis::server_info]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    /// Protocol version, see [PROTOCOL_VERSION](crate::service::PROTOCOL_VERSION)
+    pub protocol_version: u32,
+    /// Methods the runtime serves, e.g. `turn_lamp_on`, sorted
+    pub methods: Vec<String>,
+}
+
+impl ServerInfo {
+    /// Whether the runtime serves the method `method`, e.g. `turn_lamp_on`.
+    pub fn supports(&self, method: &str) -> bool {
+        self.methods
+            .binary_search_by(|m| m.as_str().cmp(method))
+            .is_ok()
+    }
+}
+
+impl Sifis {
+    /// Get the protocol version of the runtime and the methods it serves.
+    pub async fn server_info(&self) -> Result<ServerInfo> {
+        let r = self.client.server_info(self.context()).await??;
+        Ok(r)
+    }
+
+    /// Tell whether the runtime serves the method `method`, named after the
+    /// method of [SifisApi](crate::service::SifisApi), e.g. `wait_events`.
+    ///
+    /// It is false if the runtime cannot tell, e.g. it is unreachable.
+    pub async fn supports(&self, method: &str) -> bool {
+        self.server_info()
+            .await
+            .is_ok_and(|info| info.supports(method))
+    }
+}
+
+/// Name of the method `request` calls, e.g. `turn_lamp_on`, if it is one of
+/// [METHODS]
+pub(crate) fn request_method(request: &SifisApiRequest) -> Option<&'static str> {
+    let name = snake_case(variant(&Prefix::of(request)));
+    METHODS.iter().copied().find(|method| *method == name)
+}
+
+/// Whether `response` is an error
+pub(crate) fn is_error(response: &SifisApiResponse) -> bool {
+    // Every response is the `Result` of its method, e.g. `TurnLampOn(Err(..))`
+    let prefix = Prefix::of(response);
+    prefix
+        .split_once('(')
+        .is_some_and(|(_, result)| variant(result) == "Err")
+}
+
+/// `TurnLampOn` as `turn_lamp_on`
+fn snake_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 4);
+    for (i, c) in name.char_indices() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Leading variant name of a `Debug` output, e.g. `TurnLampOn`
+fn variant(debug: &str) -> &str {
+    let end = debug
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(debug.len());
+    &debug[..end]
+}
+
+/// Start of the `Debug` output of a value, enough for the names of a method
+/// and of its result, the rest of the value is not formatted
+struct Prefix(String);
+
+impl Prefix {
+    const LEN: usize = 64;
+
+    fn of(value: &impl Debug) -> String {
+        let mut prefix = Prefix(String::with_capacity(Self::LEN));
+        // Stopped by the writer once full
+        let _ = write!(prefix, "{value:?}");
+        prefix.0
+    }
+}
+
+impl Write for Prefix {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = Self::LEN - self.0.len();
+        if s.len() < room {
+            self.0.push_str(s);
+            return Ok(());
+        }
+        let end = (0..=room)
+            .rev()
+            .find(|&i| s.is_char_boundary(i))
+            .unwrap_or(0);
+        self.0.push_str(&s[..end]);
+        Err(fmt::Error)
+    }
+}
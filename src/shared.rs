@@ -0,0 +1,101 @@
+//! Connections shared by the clients of the same process
+//!
+//! Opening a connection costs a handshake with the runtime, a process making
+//! many short-lived clients can reuse a single one instead.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::cache::Cache;
+use crate::service::SifisApiClient;
+use crate::{max_frame_length_from_env, ConnectionState, Priority, Result, Sifis};
+
+/// Connection shared by [Sifis::shared_from_path]
+///
+/// It only holds the client weakly, the connection closes once every
+/// client using it is dropped.
+struct SharedConnection {
+    client: Weak<SifisApiClient>,
+    cache: Cache,
+    dispatch: Arc<Mutex<Option<JoinHandle<()>>>>,
+    state: watch::Receiver<ConnectionState>,
+}
+
+impl SharedConnection {
+    fn new(sifis: &Sifis) -> Self {
+        SharedConnection {
+            client: Arc::downgrade(&sifis.client),
+            cache: sifis.cache.clone(),
+            dispatch: sifis.dispatch.clone(),
+            state: sifis.state.clone(),
+        }
+    }
+
+    /// A client using the connection, if it is still open
+    fn client(&self) -> Option<Sifis> {
+        let client = self.client.upgrade()?;
+        let sifis = Sifis {
+            base: client.clone(),
+            client,
+            cache: self.cache.clone(),
+            dispatch: self.dispatch.clone(),
+            state: self.state.clone(),
+            timeout: None,
+            priority: Priority::Routine,
+            cancel: None,
+        };
+
+        sifis.is_connected().then_some(sifis)
+    }
+}
+
+/// Shared connections, by socket path
+fn connections() -> &'static Mutex<HashMap<PathBuf, SharedConnection>> {
+    static CONNECTIONS: OnceLock<Mutex<HashMap<PathBuf, SharedConnection>>> = OnceLock::new();
+
+    CONNECTIONS.get_or_init(Default::default)
+}
+
+impl Sifis {
+    /// Connect to the default unix socket as in [Sifis::new], sharing the
+    /// connection with the other shared clients of the process.
+    pub async fn shared() -> Result<Sifis> {
+        let sifis_server =
+            std::env::var("SIFIS_SERVER").unwrap_or("/var/run/sifis.sock".to_string());
+        Self::shared_from_path(&sifis_server).await
+    }
+
+    /// Connect to the runtime listening on the unix socket `path`, reusing
+    /// the connection of a previous shared client if it is still open.
+    ///
+    /// The connection closes once every client using it is dropped, the
+    /// next call opens a new one. A client shutting it down with
+    /// [Sifis::shutdown] closes it for all of them. [Sifis::from_path] and
+    /// [Sifis::new] always open a fresh connection, never shared.
+    pub async fn shared_from_path(path: impl AsRef<Path>) -> Result<Sifis> {
+        let path = path.as_ref();
+        if let Some(sifis) = connections()
+            .lock()
+            .unwrap()
+            .get(path)
+            .and_then(SharedConnection::client)
+        {
+            return Ok(sifis);
+        }
+
+        let sifis = Self::from_path_with_frame_limit(path, max_frame_length_from_env()).await?;
+
+        // Another client may have connected meanwhile, the first one wins
+        let mut connections = connections().lock().unwrap();
+        if let Some(shared) = connections.get(path).and_then(SharedConnection::client) {
+            return Ok(shared);
+        }
+        connections.insert(path.to_owned(), SharedConnection::new(&sifis));
+
+        Ok(sifis)
+    }
+}
@@ -0,0 +1,186 @@
+//! Human readable reports of the device states
+//!
+//! A reading failing for a single device, e.g. one removed while the report
+//! is being built, shows as [UNAVAILABLE] instead of failing the whole report.
+//! The device wrappers still return the error.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Write as _};
+
+use crate::{Result, Sifis};
+
+/// Placeholder of a reading that failed
+const UNAVAILABLE: &str = "<unavailable>";
+
+/// Format a reading of the device `id`, or the placeholder if it failed
+fn cell(id: &str, reading: Result<impl Display>) -> String {
+    match reading {
+        Ok(value) => value.to_string(),
+        Err(e) => {
+            tracing::warn!("Cannot read {id}: {e}");
+            UNAVAILABLE.to_owned()
+        }
+    }
+}
+
+impl Sifis {
+    /// Names of the devices, indexed by id
+    async fn names(&self) -> Result<HashMap<String, String>> {
+        let names = self
+            .devices()
+            .await?
+            .into_iter()
+            .map(|info| (info.id, info.name))
+            .collect();
+
+        Ok(names)
+    }
+
+    /// Tabular report of the available Lamps.
+    pub async fn lamps_summary(&self) -> Result<String> {
+        let mut out = String::new();
+
+        let names = self.names().await?;
+        writeln!(
+            out,
+            "{:<15} {:<20} {:<7} {:<5}",
+            "Lamp id", "Name", "Status", "Brightness"
+        )
+        .unwrap();
+        for lamp in self.lamps().await? {
+            let on_off = lamp
+                .get_on_off()
+                .await
+                .map(|on| if on { "On" } else { "Off" });
+            let on_off = cell(&lamp.id, on_off);
+            let brightness = cell(&lamp.id, lamp.get_brightness().await);
+            let name = names.get(lamp.id.as_str()).map_or("", String::as_str);
+            writeln!(
+                out,
+                "{:<15} {name:<20} {:<7} {:<5} ",
+                lamp.id, on_off, brightness
+            )
+            .unwrap();
+        }
+
+        Ok(out)
+    }
+
+    /// Tabular report of the available Sinks.
+    pub async fn sinks_summary(&self) -> Result<String> {
+        let mut out = String::new();
+
+        let names = self.names().await?;
+        writeln!(
+            out,
+            "{:<15} {:<20} {:<4} {:<11} {:<11} {:<6}",
+            "Sink id", "Name", "Flow", "Water level", "Temperature", "Drain"
+        )
+        .unwrap();
+        for sink in self.sinks().await? {
+            let flow = cell(&sink.id, sink.get_flow().await);
+            let water_level = cell(&sink.id, sink.get_water_level().await);
+            let temperature = cell(&sink.id, sink.get_temperature().await);
+            let drain = sink
+                .get_drain()
+                .await
+                .map(|open| if open { "Open" } else { "Closed" });
+            let drain = cell(&sink.id, drain);
+            let name = names.get(sink.id.as_str()).map_or("", String::as_str);
+            writeln!(
+                out,
+                "{:<15} {name:<20} {flow:<4} {water_level:<11} {temperature:<11} {drain:<6}",
+                sink.id
+            )
+            .unwrap();
+        }
+
+        Ok(out)
+    }
+
+    /// Tabular report of the available Doors.
+    pub async fn doors_summary(&self) -> Result<String> {
+        let mut out = String::new();
+
+        let names = self.names().await?;
+        writeln!(
+            out,
+            "{:<15} {:<20} {:<5} {:<11}",
+            "Door id", "Name", "Open?", "Lock status"
+        )
+        .unwrap();
+        for door in self.doors().await? {
+            let is_open = cell(&door.id, door.is_open().await);
+            let lock_status = cell(&door.id, door.lock_status().await);
+            let name = names.get(door.id.as_str()).map_or("", String::as_str);
+            writeln!(
+                out,
+                "{:<15} {name:<20} {is_open:<5} {lock_status:<11}",
+                door.id
+            )
+            .unwrap();
+        }
+
+        Ok(out)
+    }
+
+    /// Tabular report of the available Fridges.
+    pub async fn fridges_summary(&self) -> Result<String> {
+        let mut out = String::new();
+
+        let names = self.names().await?;
+        writeln!(
+            out,
+            "{:<15} {:<20} {:<5} {:<11} {:<12}",
+            "Fridge id", "Name", "Open?", "Temperature", "Target Temp."
+        )
+        .unwrap();
+        for fridge in self.fridges().await? {
+            let is_open = cell(&fridge.id, fridge.is_open().await);
+            let temperature = cell(&fridge.id, fridge.temperature().await);
+            let target_temperature = cell(&fridge.id, fridge.target_temperature().await);
+            let name = names.get(fridge.id.as_str()).map_or("", String::as_str);
+            writeln!(
+                out,
+                "{:<15} {name:<20} {is_open:<5} {temperature:<11} {target_temperature:<12}",
+                fridge.id
+            )
+            .unwrap();
+        }
+
+        Ok(out)
+    }
+
+    /// Tabular report of the available Curtains.
+    pub async fn curtains_summary(&self) -> Result<String> {
+        let mut out = String::new();
+
+        let names = self.names().await?;
+        writeln!(out, "{:<15} {:<20} {:<5}", "Curtain id", "Name", "Open?").unwrap();
+        for curtain in self.curtains().await? {
+            let is_open = cell(&curtain.id, curtain.is_open().await);
+            let name = names.get(curtain.id.as_str()).map_or("", String::as_str);
+            writeln!(out, "{:<15} {name:<20} {is_open:<5}", curtain.id).unwrap();
+        }
+
+        Ok(out)
+    }
+
+    /// Report the state of every device in the home, one section per kind.
+    pub async fn summary(&self) -> Result<String> {
+        let sections = [
+            ("Lamps", self.lamps_summary().await?),
+            ("Sinks", self.sinks_summary().await?),
+            ("Doors", self.doors_summary().await?),
+            ("Fridges", self.fridges_summary().await?),
+            ("Curtains", self.curtains_summary().await?),
+        ];
+
+        let mut out = String::new();
+        for (title, table) in sections {
+            writeln!(out, "== {title} ==\n{table}").unwrap();
+        }
+
+        Ok(out)
+    }
+}
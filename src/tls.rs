@@ -0,0 +1,63 @@
+//! TLS over TCP transport
+//!
+//! Meant for runtimes reachable beyond the local host, the unix socket
+//! remains the default transport.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use tarpc::tokio_util::codec::LengthDelimitedCodec;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::{Certificate, ClientConfig, PrivateKey, ServerConfig, ServerName};
+use tokio_rustls::TlsConnector;
+
+pub use tokio_rustls::rustls;
+
+/// Server configuration using the PEM certificate chain at `cert` and the
+/// PKCS#8 PEM key at `key`.
+pub fn load_server_config(
+    cert: impl AsRef<Path>,
+    key: impl AsRef<Path>,
+) -> io::Result<ServerConfig> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key)?))?
+        .into_iter()
+        .next()
+        .map(PrivateKey)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No PKCS#8 key found"))?;
+
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Frame a stream as the tarpc transports expect
+pub fn framed<S>(stream: S) -> tarpc::tokio_util::codec::Framed<S, LengthDelimitedCodec>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+{
+    LengthDelimitedCodec::builder().new_framed(stream)
+}
+
+/// Connect to `addr`, given as `host:port`, and perform the handshake
+///
+/// The certificate of the server is verified against `host`.
+pub(crate) async fn connect(
+    addr: &str,
+    config: Arc<ClientConfig>,
+) -> io::Result<TlsStream<TcpStream>> {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    let domain =
+        ServerName::try_from(host).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let stream = TcpStream::connect(addr).await?;
+    TlsConnector::from(config).connect(domain, stream).await
+}
@@ -0,0 +1,281 @@
+//! JSON-RPC over WebSocket bridge, for the clients that cannot reach the
+//! unix socket, e.g. a dashboard in a browser
+//!
+//! Every text message is a [JSON-RPC 2.0](https://www.jsonrpc.org/specification)
+//! request for a [SifisApi](crate::service::SifisApi) method, the
+//! parameters named as its arguments:
+//! `{"jsonrpc":"2.0","id":1,"method":"turn_lamp_on","params":{"id":"lamp1"}}`.
+//!
+//! The answer carries the value the method returns,
+//! `{"jsonrpc":"2.0","id":1,"result":{"state":true,"changed":true}}`, or the
+//! [service::Error] it fails with as the `data` of the error:
+//! `{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"Device lamp9 not found","data":{"NotFound":"lamp9"}}}`.
+//!
+//! The requests of a connection are served at once, up to
+//! [MAX_IN_FLIGHT] of them, each answer is sent as soon as it is ready. A
+//! request without `id` is served without answer.
+//!
+//! Browsers let any page open a WebSocket to any address, the bridge only
+//! accepts the pages of the allowed origins, e.g. `http://localhost:8080`,
+//! and refuses the others during the handshake. A client sending no
+//! `Origin`, i.e. not a browser, is accepted. The bridge has no
+//! authentication, the [REFUSED_METHODS] changing the setup of the home or
+//! skipping its checks are not served to any client.
+
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use crate::service::{self, SifisApiRequest};
+use crate::{Error, Sifis};
+
+/// The runtime answered with an error
+const SERVER_ERROR: i64 = -32000;
+/// The message is not JSON
+const PARSE_ERROR: i64 = -32700;
+/// The message is not a JSON-RPC request
+const INVALID_REQUEST: i64 = -32600;
+/// No such method
+const METHOD_NOT_FOUND: i64 = -32601;
+/// The parameters do not match the arguments of the method
+const INVALID_PARAMS: i64 = -32602;
+/// The runtime could not be reached
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Methods the bridge does not serve, they are left to the clients of the
+/// unix socket
+///
+/// They add, remove or lock out the devices, set them without the checks
+/// of the single commands, simulate the device events, dump the runtime
+/// state or carry another request, which would not be checked.
+pub const REFUSED_METHODS: &[&str] = &[
+    "restore_snapshot",
+    "save_scene",
+    "schedule_scene",
+    "cancel_schedule",
+    "add_device",
+    "remove_device",
+    "set_device_lockout",
+    "set_device_online",
+    "set_door_open",
+    "set_fridge_open",
+    "dump_state",
+    "advance_time",
+    "prioritized",
+];
+
+/// Requests of a connection served at once, the next ones are read once
+/// one of them is answered
+pub const MAX_IN_FLIGHT: usize = 32;
+
+/// Accept the WebSocket connections on `listener`, serving them through
+/// `sifis`.
+///
+/// The browser pages are only accepted from the origins `allowed_origins`,
+/// none if it is empty. It fails only if accepting a connection does.
+pub async fn serve(
+    listener: TcpListener,
+    sifis: Sifis,
+    allowed_origins: Vec<String>,
+) -> io::Result<()> {
+    let allowed_origins: Arc<[String]> = allowed_origins.into();
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        tracing::info!("New WebSocket client {peer}");
+        let sifis = sifis.clone();
+        let allowed_origins = allowed_origins.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, sifis, &allowed_origins).await {
+                tracing::warn!("WebSocket connection of {peer} broken: {e}");
+            }
+        });
+    }
+}
+
+/// Perform the WebSocket handshake on `stream`, refusing a browser page
+/// from an origin not among `allowed_origins`, then serve its requests
+/// through `sifis` until it is closed.
+pub async fn serve_connection<S>(
+    stream: S,
+    sifis: Sifis,
+    allowed_origins: &[String],
+) -> Result<(), tungstenite::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let check_origin = |request: &Request, response: Response| {
+        let Some(origin) = request.headers().get("origin") else {
+            return Ok(response);
+        };
+        if allowed_origins
+            .iter()
+            .any(|allowed| origin == allowed.as_str())
+        {
+            return Ok(response);
+        }
+        tracing::warn!("Refusing a WebSocket client from the origin {origin:?}");
+        let mut refusal = ErrorResponse::new(Some("Origin not allowed".to_owned()));
+        *refusal.status_mut() = StatusCode::FORBIDDEN;
+        Err(refusal)
+    };
+    let (mut to_client, mut from_client) =
+        tokio_tungstenite::accept_hdr_async(stream, check_origin)
+            .await?
+            .split();
+    let (answers, mut ready) = mpsc::channel(MAX_IN_FLIGHT);
+    // Room for the answer of the next request, reserved before reading it
+    let mut slot = None;
+
+    loop {
+        tokio::select! {
+            reserved = answers.clone().reserve_owned(), if slot.is_none() => {
+                slot = reserved.ok();
+            }
+            message = from_client.next(), if slot.is_some() => {
+                let text = match message.transpose()? {
+                    Some(Message::Text(text)) => text,
+                    Some(Message::Close(_)) | None => break,
+                    Some(_) => continue,
+                };
+                let sifis = sifis.clone();
+                let slot = slot.take();
+                tokio::spawn(async move {
+                    if let (Some(answer), Some(slot)) = (answer(&sifis, &text).await, slot) {
+                        slot.send(answer.to_string());
+                    }
+                });
+            }
+            Some(answer) = ready.recv() => to_client.send(Message::Text(answer)).await?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Serve the JSON-RPC request `text`, giving the answer unless it is a
+/// notification
+async fn answer(sifis: &Sifis, text: &str) -> Option<Value> {
+    let request: Value = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(e) => return Some(failure(Value::Null, PARSE_ERROR, e.to_string(), None)),
+    };
+    let id = request.get("id").cloned();
+    let outcome = call(sifis, &request).await;
+
+    let id = id?;
+    Some(match outcome {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message, data)) => failure(id, code, message, data),
+    })
+}
+
+/// Error answer to the request `id`
+fn failure(id: Value, code: i64, message: String, data: Option<Value>) -> Value {
+    let mut error = json!({ "code": code, "message": message });
+    if let Some(data) = data {
+        error["data"] = data;
+    }
+
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
+
+/// Serve `request` through `sifis`, giving the result or the code, message
+/// and data of the error
+async fn call(sifis: &Sifis, request: &Value) -> Result<Value, (i64, String, Option<Value>)> {
+    let invalid = |message: &str| (INVALID_REQUEST, message.to_owned(), None);
+    if request.get("jsonrpc") != Some(&json!("2.0")) {
+        return Err(invalid("Not a JSON-RPC 2.0 request"));
+    }
+    let method = request
+        .get("method")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("Missing method"))?;
+    if REFUSED_METHODS.contains(&method) {
+        return Err((
+            METHOD_NOT_FOUND,
+            format!("Method {method} not served by the bridge"),
+            None,
+        ));
+    }
+    let params = match request.get("params") {
+        None => json!({}),
+        Some(params @ Value::Object(_)) => params.clone(),
+        Some(_) => return Err(invalid("The params must be named")),
+    };
+    let deadline = deadline(&params);
+
+    // The methods are tagged by their name in camel case, as they serialize
+    let tagged = Value::Object([(camel_case(method), params)].into_iter().collect());
+    let request: SifisApiRequest = serde_json::from_value(tagged).map_err(|e| {
+        let message = e.to_string();
+        if message.starts_with("unknown variant") {
+            (METHOD_NOT_FOUND, format!("No method {method}"), None)
+        } else {
+            (INVALID_PARAMS, message, None)
+        }
+    })?;
+
+    let mut ctx = sifis.context();
+    ctx.deadline = ctx.deadline.max(deadline);
+    let response = sifis
+        .client
+        .prioritized(ctx, sifis.priority, Box::new(request))
+        .await
+        .map_err(Error::from)
+        .and_then(|r| Ok(r?))
+        .map_err(|e| (INTERNAL_ERROR, e.to_string(), None))?;
+
+    // Untag the Result the method answers with
+    let response = serde_json::to_value(*response).unwrap_or_default();
+    let outcome = response
+        .as_object()
+        .and_then(|tagged| tagged.values().next())
+        .and_then(Value::as_object)
+        .and_then(|outcome| outcome.iter().next());
+    match outcome {
+        Some((tag, result)) if tag == "Ok" => Ok(result.clone()),
+        Some((_, error)) => {
+            let message = match serde_json::from_value::<service::Error>(error.clone()) {
+                Ok(e) => e.to_string(),
+                Err(_) => "Runtime error".to_owned(),
+            };
+            Err((SERVER_ERROR, message, Some(error.clone())))
+        }
+        None => Err((INTERNAL_ERROR, "Malformed answer".to_owned(), None)),
+    }
+}
+
+/// Deadline of a request waiting for as long as its `timeout_ms` or
+/// `duration_ms` parameter, e.g. a ramp, with the same margin as the client
+fn deadline(params: &Value) -> SystemTime {
+    let wait = ["timeout_ms", "duration_ms"]
+        .iter()
+        .find_map(|name| params.get(name).and_then(Value::as_u64))
+        .unwrap_or_default();
+
+    SystemTime::now() + Duration::from_millis(wait) + Duration::from_secs(10)
+}
+
+/// `turn_lamp_on` as `TurnLampOn`
+fn camel_case(method: &str) -> String {
+    method
+        .split('_')
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect()
+}
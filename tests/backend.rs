@@ -0,0 +1,68 @@
+use anyhow::Result;
+use futures::{future, prelude::*};
+use sifis_api::runtime::InMemoryBackend;
+use sifis_api::service::{SifisApi, SifisApiRequest, SifisApiResponse, PROTOCOL_VERSION};
+use sifis_api::{Error, Sifis};
+use tarpc::server::{BaseChannel, Channel};
+use tarpc::tokio_serde::formats::Bincode;
+
+#[tokio::test]
+async fn embedded_backend() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let sock = dir.path().join("sifis.sock");
+
+    let listener = tarpc::serde_transport::unix::listen(&sock, Bincode::default).await?;
+    let backend = InMemoryBackend::default();
+    tokio::spawn(
+        listener
+            .filter_map(|r| future::ready(r.ok()))
+            .map(BaseChannel::with_defaults)
+            .for_each(move |channel| {
+                tokio::spawn(channel.execute(backend.clone().serve()));
+                future::ready(())
+            }),
+    );
+
+    let sifis = Sifis::from_path(&sock).await?;
+
+    let lamp = sifis.lamp("lamp1").await?;
+    assert!(lamp.turn_on().await?.state);
+    assert!(lamp.get_on_off().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn protocol_mismatch() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let sock = dir.path().join("sifis.sock");
+
+    // A runtime from the future, only answering the version exchange
+    let listener = tarpc::serde_transport::unix::listen(&sock, Bincode::default).await?;
+    tokio::spawn(
+        listener
+            .filter_map(|r| future::ready(r.ok()))
+            .map(BaseChannel::with_defaults)
+            .for_each(|channel| {
+                let serve = tarpc::server::serve(|_, req| async move {
+                    match req {
+                        SifisApiRequest::Handshake { .. } => {
+                            SifisApiResponse::Handshake(Ok(PROTOCOL_VERSION + 1))
+                        }
+                        _ => unreachable!("only the handshake is expected"),
+                    }
+                });
+                tokio::spawn(channel.execute(serve));
+                future::ready(())
+            }),
+    );
+
+    let err = Sifis::from_path(&sock).await.err().unwrap();
+    assert!(matches!(
+        err,
+        Error::ProtocolMismatch { client, server }
+            if client == PROTOCOL_VERSION && server == PROTOCOL_VERSION + 1
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use sifis_api::service::{self, ErrorCode};
+use sifis_api::{Error, Hazard};
+
+#[test]
+fn service_error_codes() {
+    let mismatch = service::Error::Mismatch {
+        found: "Sink".to_string(),
+        req: "Lamp".to_string(),
+    };
+    assert_eq!(ErrorCode::KindMismatch, mismatch.code());
+
+    let not_found = service::Error::NotFound("lamp42".to_string());
+    assert_eq!(ErrorCode::NotFound, not_found.code());
+
+    let forbidden = service::Error::Forbidden {
+        risk: Hazard::Scald,
+        comment: "Too hot".to_string(),
+        retriable_with_ack: true,
+    };
+    assert_eq!(ErrorCode::Forbidden, forbidden.code());
+
+    let invalid = service::Error::InvalidArgument("missing argument 0".to_string());
+    assert_eq!(ErrorCode::InvalidArgument, invalid.code());
+
+    let rate_limited = service::Error::RateLimited {
+        retry_after_ms: 100,
+    };
+    assert_eq!(ErrorCode::RateLimited, rate_limited.code());
+
+    let out_of_range = service::Error::OutOfRange {
+        value: 7000,
+        min: 2000,
+        max: 6500,
+    };
+    assert_eq!(ErrorCode::OutOfRange, out_of_range.code());
+
+    let locked_out = service::Error::LockedOut("lamp1".to_string());
+    assert_eq!(ErrorCode::LockedOut, locked_out.code());
+
+    assert_eq!(ErrorCode::Cancelled, service::Error::Cancelled.code());
+
+    let offline = service::Error::Offline("lamp1".to_string());
+    assert_eq!(ErrorCode::Offline, offline.code());
+
+    let unsupported = service::Error::Unsupported {
+        method: "wait_events".to_string(),
+    };
+    assert_eq!(ErrorCode::Unsupported, unsupported.code());
+}
+
+#[test]
+fn client_error_codes() {
+    let runtime = Error::from(service::Error::NotFound("lamp42".to_string()));
+    assert_eq!(Some(ErrorCode::NotFound), runtime.code());
+
+    assert_eq!(Some(ErrorCode::NotFound), Error::NotFound.code());
+
+    let io = Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused));
+    assert_eq!(None, io.code());
+    assert_eq!(None, io.retry_after());
+
+    let rate_limited = Error::from(service::Error::RateLimited {
+        retry_after_ms: 100,
+    });
+    assert_eq!(
+        Some(std::time::Duration::from_millis(100)),
+        rate_limited.retry_after()
+    );
+}
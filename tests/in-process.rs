@@ -0,0 +1,2246 @@
+#![cfg(feature = "testing")]
+
+use anyhow::Result;
+use futures::StreamExt;
+use serde_json::json;
+use sifis_api::runtime::{
+    DefaultHazardPolicy, HazardPolicy, HazardousOperation, InMemoryBackend, Verdict,
+};
+use sifis_api::service::{self, ErrorCode};
+use sifis_api::{
+    CancellationToken, Clamped, DeviceId, DeviceInfo, DeviceKind, DeviceSpec, DoorLockStatus,
+    DoorState, Error, FridgeState, Hazard, HealthStatus, HomeEvent, KnownKind, Lamp, LampState,
+    LockResult, MaintenanceStatus, OperationDoc, Priority, Range, Sifis, SifisMulti, SinkState,
+    ValueType,
+};
+use std::time::{Duration, SystemTime};
+
+#[tokio::test]
+async fn in_process() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+
+    let lamp = sifis.lamp("lamp1").await?;
+    assert!(!lamp.get_on_off().await?);
+    assert!(lamp.turn_on().await?.state);
+    assert!(lamp.get_on_off().await?);
+
+    // Every client gets its own devices
+    let other = Sifis::in_process(InMemoryBackend::default());
+    assert!(!other.lamp("lamp1").await?.get_on_off().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lamp_power() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+
+    let lamp1 = sifis.lamp("lamp1").await?;
+    let lamp2 = sifis.lamp("lamp2").await?;
+
+    lamp1.set_brightness(50).await?;
+    assert_eq!(0.0, lamp1.power().await?);
+    assert_eq!(0.0, sifis.total_power().await?);
+
+    lamp1.turn_on().await?;
+    assert_eq!(5.0, lamp1.power().await?);
+
+    lamp2.turn_on().await?;
+    lamp2.set_brightness(100).await?;
+    assert_eq!(10.0, lamp2.power().await?);
+    assert_eq!(15.0, sifis.total_power().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lamp_ramp() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let lamp = sifis.lamp("lamp1").await?;
+
+    assert_eq!(
+        20,
+        lamp.ramp_brightness(20, Duration::from_millis(200)).await?
+    );
+    assert_eq!(20, lamp.get_brightness().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lamp_wait_for_brightness() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let lamp = sifis.lamp("lamp1").await?;
+
+    let ramp = lamp.ramp_brightness(30, Duration::from_millis(300));
+    let wait = lamp.wait_for_brightness(30, Duration::from_secs(2));
+    let (ramped, reached) = tokio::join!(ramp, wait);
+    assert_eq!(30, ramped?);
+    assert!(reached?);
+
+    // Already there
+    assert!(
+        lamp.wait_for_brightness(30, Duration::from_millis(100))
+            .await?
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lamp_wait_for_brightness_timeout() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let lamp = sifis.lamp("lamp1").await?;
+    lamp.set_brightness(10).await?;
+
+    let start = std::time::Instant::now();
+    assert!(
+        !lamp
+            .wait_for_brightness(80, Duration::from_millis(200))
+            .await?
+    );
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(10, lamp.get_brightness().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lamp_ramp_cancelled() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let lamp = sifis.lamp("lamp1").await?;
+
+    let ramp = lamp.ramp_brightness(100, Duration::from_secs(1));
+    assert!(tokio::time::timeout(Duration::from_millis(300), ramp)
+        .await
+        .is_err());
+
+    // Let the cancellation reach the server
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let brightness = lamp.get_brightness().await?;
+    assert!(brightness > 0 && brightness < 100);
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(brightness, lamp.get_brightness().await?);
+
+    Ok(())
+}
+
+fn door(id: &str, lock: DoorLockStatus) -> (String, DeviceSpec) {
+    (
+        id.to_owned(),
+        DeviceSpec {
+            name: format!("Door {id}"),
+            room: None,
+            maintenance: Default::default(),
+            kind: DeviceKind::Door(DoorState {
+                is_open: false,
+                lock,
+                ..Default::default()
+            }),
+        },
+    )
+}
+
+#[tokio::test]
+async fn lock_all_doors() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::new([
+        door("front", DoorLockStatus::Unlocked),
+        door("back", DoorLockStatus::Jammed),
+        door("garage", DoorLockStatus::Locked),
+    ]));
+
+    let report = sifis.lock_all_doors().await?;
+
+    assert_eq!(
+        vec![
+            ("back".to_owned(), false),
+            ("front".to_owned(), true),
+            ("garage".to_owned(), true)
+        ],
+        report
+    );
+    assert_eq!(
+        DoorLockStatus::Locked,
+        sifis.door("front").await?.lock_status().await?
+    );
+    assert_eq!(
+        DoorLockStatus::Jammed,
+        sifis.door("back").await?.lock_status().await?
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_health() -> Result<()> {
+    let warm = FridgeState {
+        temperature: 12,
+        ..Default::default()
+    };
+    let backend = InMemoryBackend::new([
+        door("front", DoorLockStatus::Unlocked),
+        door("back", DoorLockStatus::Jammed),
+        (
+            "fridge".to_owned(),
+            DeviceSpec {
+                name: "Warm fridge".to_owned(),
+                room: None,
+                maintenance: Default::default(),
+                kind: DeviceKind::Fridge(warm),
+            },
+        ),
+    ]);
+    let sifis = Sifis::in_process(backend);
+
+    let report = sifis.scan_health().await?;
+    let statuses: Vec<_> = report
+        .iter()
+        .map(|device| (device.id.as_str(), device.status))
+        .collect();
+    assert_eq!(
+        vec![
+            ("back", HealthStatus::Degraded),
+            ("fridge", HealthStatus::Degraded),
+            ("front", HealthStatus::Ok),
+        ],
+        statuses
+    );
+    assert!(report[0].detail.as_ref().unwrap().contains("jammed"));
+    assert_eq!(None, report[2].detail);
+
+    let backend = InMemoryBackend::default().with_faults(1.0, Duration::ZERO, None);
+    let report = Sifis::in_process(backend).scan_health().await?;
+    assert_eq!(6, report.len());
+    assert!(report
+        .iter()
+        .all(|device| device.status == HealthStatus::Offline && device.detail.is_some()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn device_offline() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let lamp = sifis.lamp("lamp1").await?;
+
+    sifis.set_online("lamp1", false).await?;
+    let err = lamp.get_on_off().await.unwrap_err();
+    assert_eq!(Some(ErrorCode::Offline), err.code());
+    let err = lamp.turn_on().await.unwrap_err();
+    assert_eq!(Some(ErrorCode::Offline), err.code());
+
+    let report = sifis.scan_health().await?;
+    let offline: Vec<_> = report
+        .iter()
+        .filter(|device| device.status == HealthStatus::Offline)
+        .map(|device| device.id.as_str())
+        .collect();
+    assert_eq!(vec!["lamp1"], offline);
+    // The other devices keep working
+    assert!(sifis.lamp("lamp2").await?.turn_on().await?.changed);
+
+    sifis.set_online("lamp1", true).await?;
+    assert!(lamp.turn_on().await?.changed);
+
+    let err = sifis.set_online("nope", false).await.unwrap_err();
+    assert_eq!(Some(ErrorCode::NotFound), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn sorted_ids() -> Result<()> {
+    let ids = ["lamp9", "lamp10", "a", "lamp1", "hall", "z", "lamp02"];
+    let sifis = Sifis::in_process(InMemoryBackend::new(ids.map(|id| {
+        (
+            id.to_owned(),
+            DeviceSpec {
+                name: id.to_owned(),
+                room: None,
+                maintenance: Default::default(),
+                kind: DeviceKind::Lamp(LampState::default()),
+            },
+        )
+    })));
+    let mut expected = ids.to_vec();
+    expected.sort();
+
+    // The same order on every call, whatever the order of the configuration
+    for _ in 0..5 {
+        let lamps = sifis.lamps().await?;
+        let found: Vec<_> = lamps.iter().map(|lamp| lamp.id.as_str()).collect();
+        assert_eq!(expected, found);
+
+        let matching = sifis.lamps_matching("lamp*").await?;
+        let found: Vec<_> = matching.iter().map(|lamp| lamp.id.as_str()).collect();
+        assert_eq!(vec!["lamp02", "lamp1", "lamp10", "lamp9"], found);
+
+        let report = sifis.all_off().await?;
+        let found: Vec<_> = report.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(expected, found);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lamps_stream() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let all = sifis.lamps().await?;
+
+    let mut lamps = sifis.lamps_stream();
+    let first = lamps.next().await.unwrap()?;
+    assert_eq!(all[0].id, first.id);
+    drop(lamps);
+
+    let found = sifis
+        .lamps_stream()
+        .filter_map(|lamp| async move { lamp.ok() })
+        .filter(|lamp| futures::future::ready(lamp.id == "lamp2"))
+        .next()
+        .await
+        .unwrap();
+    assert!(found.turn_on().await?.state);
+
+    assert_eq!(all.len(), sifis.lamps_stream().count().await);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn device_id() -> Result<()> {
+    assert!("".parse::<DeviceId>().is_err());
+    assert!("lamp\n1".parse::<DeviceId>().is_err());
+    let id: DeviceId = "lamp1".parse()?;
+    assert_eq!("lamp1", id.to_string());
+
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    assert_eq!(id, sifis.lamp(&id).await?.id);
+    assert!(matches!(sifis.lamp("").await, Err(Error::InvalidId(_))));
+
+    let owned = String::from("lamp1");
+    assert_eq!(id, sifis.lamp(&owned).await?.id);
+    assert_eq!(id, sifis.lamp(owned.clone()).await?.id);
+    assert_eq!(id, sifis.lamp(id.clone()).await?.id);
+    assert_eq!("Lamp", sifis.device_kind(&owned).await?);
+    assert_eq!("Lamp", sifis.device_kind(&id).await?);
+
+    let names: std::collections::HashMap<DeviceId, &str> = [(id.clone(), "Desk")].into();
+    assert_eq!(Some(&"Desk"), names.get("lamp1"));
+    assert_eq!("lamp1", AsRef::<str>::as_ref(&id));
+    assert!(matches!(
+        sifis.sink("sink\t1").await,
+        Err(Error::InvalidId(_))
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn fridge_cooling() -> Result<()> {
+    let backend = InMemoryBackend::new([
+        (
+            "closed".to_owned(),
+            DeviceSpec {
+                name: "Closed fridge".to_owned(),
+                room: None,
+                maintenance: Default::default(),
+                kind: DeviceKind::Fridge(FridgeState::default()),
+            },
+        ),
+        (
+            "open".to_owned(),
+            DeviceSpec {
+                name: "Open fridge".to_owned(),
+                room: None,
+                maintenance: Default::default(),
+                kind: DeviceKind::Fridge(FridgeState {
+                    open: true,
+                    ..Default::default()
+                }),
+            },
+        ),
+    ]);
+    backend.simulate(Duration::from_millis(10));
+    let sifis = Sifis::in_process(backend);
+
+    let closed = sifis.fridge("closed").await?;
+    closed.set_target_temperature(-2).await?;
+    let open = sifis.fridge("open").await?;
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    assert!(closed.temperature().await? < 5);
+    assert!(closed.temperature().await? >= -2);
+    assert!(open.temperature().await? > 5);
+
+    Ok(())
+}
+
+fn two_fridges() -> InMemoryBackend {
+    let open = FridgeState {
+        open: true,
+        ..Default::default()
+    };
+
+    InMemoryBackend::new([("closed", FridgeState::default()), ("open", open)].map(
+        |(id, fridge)| {
+            (
+                id.to_owned(),
+                DeviceSpec {
+                    name: id.to_owned(),
+                    room: None,
+                    maintenance: Default::default(),
+                    kind: DeviceKind::Fridge(fridge),
+                },
+            )
+        },
+    ))
+}
+
+#[tokio::test]
+async fn advance_time() -> Result<()> {
+    let backend = two_fridges();
+    backend.simulate(Duration::from_secs(60));
+    let sifis = Sifis::in_process(backend);
+
+    let closed = sifis.fridge("closed").await?;
+    closed.set_target_temperature(-2).await?;
+    let open = sifis.fridge("open").await?;
+
+    sifis.advance_time(30).await?;
+    assert_eq!(5, closed.temperature().await?);
+
+    sifis.advance_time(570).await?;
+    assert_eq!(-2, closed.temperature().await?);
+    assert_eq!(15, open.temperature().await?);
+    assert_eq!(Some(Duration::from_secs(600)), open.open_duration().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn battery_low() -> Result<()> {
+    let backend = InMemoryBackend::new([
+        (
+            "door1".to_owned(),
+            DeviceSpec {
+                name: "Front door".to_owned(),
+                room: None,
+                maintenance: MaintenanceStatus {
+                    battery: Some(22),
+                    replacement_due: false,
+                },
+                kind: DeviceKind::Door(DoorState::default()),
+            },
+        ),
+        (
+            "lamp1".to_owned(),
+            DeviceSpec {
+                name: "Lamp".to_owned(),
+                room: None,
+                maintenance: Default::default(),
+                kind: DeviceKind::Lamp(LampState::default()),
+            },
+        ),
+    ]);
+    backend.simulate(Duration::from_secs(60));
+    let sifis = Sifis::in_process(backend);
+    let mut events = sifis.maintenance_events().await?;
+
+    sifis.advance_time(120).await?;
+    let status = sifis.maintenance_status("door1").await?;
+    assert_eq!(Some(20), status.battery);
+    assert!(!status.needs_attention());
+
+    sifis.advance_time(60).await?;
+    let low = MaintenanceStatus {
+        battery: Some(19),
+        replacement_due: false,
+    };
+    assert_eq!(low, sifis.maintenance_status("door1").await?);
+    assert!(low.low_battery());
+    assert_eq!(Some(("door1".to_owned(), low)), events.next().await);
+
+    // Mains powered, it never needs a charge
+    assert_eq!(
+        MaintenanceStatus::default(),
+        sifis.maintenance_status("lamp1").await?
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn fridge_set_open() -> Result<()> {
+    let backend = two_fridges();
+    backend.simulate(Duration::from_secs(60));
+    let sifis = Sifis::in_process(backend);
+
+    let fridge = sifis.fridge("closed").await?;
+    assert!(fridge.set_open(true).await?);
+    assert!(fridge.is_open().await?);
+    assert_eq!(vec![Hazard::EnergyConsumption], fridge.hazards().await?);
+
+    sifis.advance_time(90).await?;
+    assert_eq!(Some(Duration::from_secs(90)), fridge.open_duration().await?);
+    assert!(fridge.temperature().await? > 5);
+
+    assert!(!fridge.set_open(false).await?);
+    assert_eq!(None, fridge.open_duration().await?);
+    assert!(fridge.hazards().await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn fridge_target_range() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let fridge = sifis.fridge("fridge1").await?;
+
+    assert_eq!(-20, fridge.set_target_temperature(-20).await?);
+    let err = fridge.set_target_temperature(-128).await.unwrap_err();
+    assert_eq!(Some(ErrorCode::OutOfRange), err.code());
+    assert_eq!(-20, fridge.target_temperature().await?);
+
+    // A narrower range of the runtime
+    let backend = InMemoryBackend::default().with_fridge_targets(-5..=10);
+    let sifis = Sifis::in_process(backend);
+    let fridge = sifis.fridge("fridge1").await?;
+
+    assert_eq!(
+        Range { min: -5, max: 10 },
+        sifis
+            .property_range("fridge1", "target_temperature")
+            .await?
+    );
+    assert_eq!(10, fridge.set_target_temperature(10).await?);
+    let err = fridge.set_target_temperature(15).await.unwrap_err();
+    assert_eq!(Some(ErrorCode::OutOfRange), err.code());
+    let err = sifis
+        .set_property("fridge1", "target_temperature", json!(-10))
+        .await
+        .unwrap_err();
+    assert_eq!(Some(ErrorCode::OutOfRange), err.code());
+    assert_eq!(10, fridge.adjust_target_temperature(5).await?);
+    assert_eq!(-5, fridge.adjust_target_temperature(-100).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn door_set_open() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let door = sifis.door("door1").await?;
+
+    assert_eq!(LockResult::Locked, door.lock().await?);
+    let err = door.set_open(true).await.err().unwrap();
+    assert!(matches!(
+        err,
+        Error::Runtime(service::Error::Forbidden {
+            risk: Hazard::Intrusion,
+            retriable_with_ack: false,
+            ..
+        })
+    ));
+    assert!(!door.is_open().await?);
+
+    assert_eq!(LockResult::Unlocked, door.unlock().await?);
+    assert!(door.set_open(true).await?);
+    assert!(door.is_open().await?);
+    assert_eq!(LockResult::DoorOpen, door.lock().await?);
+    assert_eq!(DoorLockStatus::Unlocked, door.lock_status().await?);
+
+    assert!(!door.set_open(false).await?);
+    assert_eq!(LockResult::Locked, door.lock().await?);
+    assert_eq!(LockResult::AlreadyInState, door.lock().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn time_scale() -> Result<()> {
+    let backend = two_fridges().with_time_scale(1000.0);
+    backend.simulate(Duration::from_secs(1));
+    let sifis = Sifis::in_process(backend);
+
+    sifis
+        .fridge("closed")
+        .await?
+        .set_target_temperature(-2)
+        .await?;
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    assert_eq!(-2, sifis.fridge("closed").await?.temperature().await?);
+    assert_eq!(20, sifis.fridge("open").await?.temperature().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn sink_flood_hazard() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let sink = sifis.sink("sink1").await?;
+    assert!(sink.hazards().await?.is_empty());
+
+    sink.set_flow(50).await?;
+    assert!(sink.hazards().await?.is_empty());
+
+    sink.close_drain().await?;
+    assert_eq!(vec![Hazard::Flood], sink.hazards().await?);
+
+    // The water scalds above the limit of the sink only
+    sink.set_max_temperature(50).await?;
+    sink.set_temperature(50).await?;
+    assert_eq!(vec![Hazard::Flood], sink.hazards().await?);
+    sink.set_temperature_forced(70).await?;
+    assert_eq!(vec![Hazard::Flood, Hazard::Scald], sink.hazards().await?);
+
+    sink.set_flow(0).await?;
+    assert!(sink.hazards().await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn device_lockout() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let lamp = sifis.lamp("lamp1").await?;
+    lamp.set_brightness(30).await?;
+    assert!(!sifis.is_locked_out("lamp1").await?);
+
+    sifis.set_lockout("lamp1", true).await?;
+    assert!(sifis.is_locked_out("lamp1").await?);
+
+    let err = lamp.turn_on().await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Runtime(service::Error::LockedOut(ref id)) if id == "lamp1"
+    ));
+    let err = lamp.set_brightness(80).await.unwrap_err();
+    assert_eq!(Some(ErrorCode::LockedOut), err.code());
+    let err = sifis
+        .invoke("lamp1", "turn_on", Vec::new())
+        .await
+        .unwrap_err();
+    assert_eq!(Some(ErrorCode::LockedOut), err.code());
+
+    assert!(!lamp.get_on_off().await?);
+    assert_eq!(30, lamp.get_brightness().await?);
+
+    // Including the limits of the device
+    let sink = sifis.sink("sink1").await?;
+    let max_temp = sink.max_temperature().await?;
+    sifis.set_lockout("sink1", true).await?;
+    let err = sink.set_max_temperature(30).await.unwrap_err();
+    assert_eq!(Some(ErrorCode::LockedOut), err.code());
+    let err = sifis
+        .set_property("sink1", "max_temperature", json!(30))
+        .await
+        .unwrap_err();
+    assert_eq!(Some(ErrorCode::LockedOut), err.code());
+    assert_eq!(max_temp, sink.max_temperature().await?);
+    sifis.set_lockout("sink1", false).await?;
+
+    // The other devices keep accepting the commands
+    assert!(sifis.lamp("lamp2").await?.turn_on().await?.state);
+    let report = sifis.all_off().await?;
+    let (_, outcome) = report.iter().find(|(id, _)| id == "lamp1").unwrap();
+    assert_eq!(ErrorCode::LockedOut, outcome.as_ref().unwrap_err().code());
+    // The devices it does not set are left out, even if locked out
+    sifis.set_lockout("door1", true).await?;
+    let report = sifis.all_off().await?;
+    assert!(report.iter().all(|(id, _)| id != "door1"));
+    sifis.set_lockout("door1", false).await?;
+
+    sifis.set_lockout("lamp1", false).await?;
+    assert!(lamp.turn_on().await?.state);
+
+    assert_eq!(
+        Some(ErrorCode::NotFound),
+        sifis.set_lockout("nope", true).await.unwrap_err().code()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn sink_drain() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let sink = sifis.sink("sink1").await?;
+    assert!(sink.get_drain().await?);
+
+    assert!(!sink.close_drain().await?);
+    assert!(!sink.get_drain().await?);
+    assert!(sifis.sinks_summary().await?.contains("Closed"));
+
+    assert!(sink.open_drain().await?);
+    assert!(sink.get_drain().await?);
+    assert!(sifis.sinks_summary().await?.contains("Open"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn sink_stop() -> Result<()> {
+    for (flow, drain) in [(80, false), (0, false), (30, true), (0, true)] {
+        let sifis = Sifis::in_process(InMemoryBackend::default());
+        let sink = sifis.sink("sink1").await?;
+        sink.set_flow(flow).await?;
+        if !drain {
+            sink.close_drain().await?;
+        }
+
+        sink.stop().await?;
+        assert_eq!(0, sink.get_flow().await?);
+        assert!(sink.get_drain().await?);
+    }
+
+    // The safe state is never rate limited
+    let backend = InMemoryBackend::default().with_rate_limit(Duration::from_secs(10));
+    let sifis = Sifis::in_process(backend);
+    let sink = sifis.sink("sink1").await?;
+    sink.set_flow(50).await?;
+    sink.stop().await?;
+    assert_eq!(0, sink.get_flow().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn sink_max_temperature() -> Result<()> {
+    let backend = InMemoryBackend::new([("kitchen", 70), ("bathroom", 40)].map(|(id, max)| {
+        (
+            id.to_owned(),
+            DeviceSpec {
+                name: id.to_owned(),
+                room: None,
+                maintenance: Default::default(),
+                kind: DeviceKind::Sink(SinkState {
+                    max_safe_temp: max,
+                    ..Default::default()
+                }),
+            },
+        )
+    }));
+    let sifis = Sifis::in_process(backend);
+
+    let kitchen = sifis.sink("kitchen").await?;
+    assert_eq!(70, kitchen.max_temperature().await?);
+    assert_eq!(60, kitchen.set_temperature(60).await?);
+
+    let bathroom = sifis.sink("bathroom").await?;
+    assert_eq!(40, bathroom.max_temperature().await?);
+    let err = bathroom.set_temperature(60).await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Runtime(service::Error::Forbidden {
+            risk: Hazard::Scald,
+            retriable_with_ack: true,
+            ..
+        })
+    ));
+    assert_eq!(20, bathroom.get_temperature().await?);
+    assert_eq!(60, bathroom.set_temperature_forced(60).await?);
+    assert_eq!(60, bathroom.get_temperature().await?);
+    assert_eq!(40, bathroom.set_temperature(40).await?);
+
+    // Lowering the limit cools the water down
+    assert_eq!(50, kitchen.set_max_temperature(50).await?);
+    assert_eq!(50, kitchen.get_temperature().await?);
+    assert_eq!(40, bathroom.get_temperature().await?);
+
+    // Raising it is a scald hazard
+    let err = kitchen.set_max_temperature(u8::MAX).await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Runtime(service::Error::Forbidden {
+            risk: Hazard::Scald,
+            retriable_with_ack: false,
+            ..
+        })
+    ));
+    assert_eq!(50, kitchen.max_temperature().await?);
+    assert!(kitchen.set_temperature(80).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lamp_on_hazards() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let lamp = sifis.lamp("lamp1").await?;
+    assert!(lamp.hazards().await?.is_empty());
+
+    lamp.turn_on().await?;
+    assert_eq!(
+        vec![Hazard::Fire, Hazard::EnergyConsumption],
+        lamp.hazards().await?
+    );
+    assert!(sifis.door("door1").await?.hazards().await?.is_empty());
+
+    Ok(())
+}
+
+/// Refuses every operation that could start a fire
+#[derive(Debug)]
+struct NoFire;
+
+impl HazardPolicy for NoFire {
+    fn check(&self, operation: &HazardousOperation) -> Verdict {
+        match operation.hazard {
+            Hazard::Fire => Verdict::Deny,
+            _ => DefaultHazardPolicy.check(operation),
+        }
+    }
+}
+
+#[tokio::test]
+async fn hazard_policy() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default().with_hazard_policy(NoFire));
+    let lamp = sifis.lamp("lamp1").await?;
+
+    let err = lamp.turn_on().await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Runtime(service::Error::Forbidden {
+            risk: Hazard::Fire,
+            retriable_with_ack: false,
+            ..
+        })
+    ));
+    assert!(!lamp.get_on_off().await?);
+    assert!(lamp.hazards().await?.is_empty());
+
+    // The other hazards are left to the built-in limits
+    let sink = sifis.sink("sink1").await?;
+    assert_eq!(50, sink.set_max_temperature(50).await?);
+    assert_eq!(40, sink.set_temperature(40).await?);
+    let err = sink.set_temperature(90).await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Runtime(service::Error::Forbidden {
+            risk: Hazard::Scald,
+            retriable_with_ack: true,
+            ..
+        })
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn breaker_trips() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default().with_breaker(15.0));
+    let lamp1 = sifis.lamp("lamp1").await?;
+    let lamp2 = sifis.lamp("lamp2").await?;
+
+    lamp1.set_brightness(100).await?;
+    lamp1.turn_on().await?;
+    lamp2.set_brightness(100).await?;
+    assert_eq!(10.0, sifis.circuit_load().await?);
+
+    let err = lamp2.turn_on().await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Runtime(service::Error::Forbidden {
+            risk: Hazard::PowerOutage,
+            retriable_with_ack: false,
+            ..
+        })
+    ));
+    assert!(!lamp2.get_on_off().await?);
+    assert_eq!(10.0, sifis.circuit_load().await?);
+
+    lamp1.set_brightness(40).await?;
+    lamp2.set_brightness(50).await?;
+    lamp2.turn_on().await?;
+    assert_eq!(9.0, sifis.circuit_load().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn events_stream() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    sifis.lamp("lamp2").await?.turn_on().await?;
+
+    let mut events = sifis.events().await?;
+    sifis.lamp("lamp1").await?.turn_on().await?;
+    sifis.door("door1").await?.lock().await?;
+
+    let mut changes = Vec::new();
+    for _ in 0..2 {
+        let Some(HomeEvent::Changed(event)) = events.next().await else {
+            panic!("a change is expected");
+        };
+        changes.push((event.device_id, event.operation));
+    }
+    assert_eq!(
+        vec![
+            ("lamp1".to_owned(), "turn_lamp_on".to_owned()),
+            ("door1".to_owned(), "lock_door".to_owned()),
+        ],
+        changes
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn watch_property() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let lamp = sifis.lamp("lamp1").await?;
+    lamp.set_brightness(10).await?;
+
+    let mut brightness = sifis.watch_property("lamp1", "brightness").await?;
+    assert_eq!(json!(10), brightness.next().await.unwrap()?);
+
+    lamp.set_brightness(40).await?;
+    assert_eq!(json!(40), brightness.next().await.unwrap()?);
+
+    // Neither another device nor another property yields a value
+    sifis.lamp("lamp2").await?.set_brightness(20).await?;
+    lamp.turn_on().await?;
+    lamp.set_brightness(70).await?;
+    assert_eq!(json!(70), brightness.next().await.unwrap()?);
+
+    sifis.remove_device("lamp1").await?;
+    assert!(brightness.next().await.is_none());
+
+    assert_eq!(
+        Some(ErrorCode::InvalidArgument),
+        sifis
+            .watch_property("lamp2", "flow")
+            .await
+            .err()
+            .unwrap()
+            .code()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn watch_many() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let ids = ["lamp1", "lamp2", "nope"].map(str::to_owned).to_vec();
+    let mut events = sifis.watch_many(ids).await?;
+
+    let lamp1 = sifis.lamp("lamp1").await?;
+    let lamp2 = sifis.lamp("lamp2").await?;
+    lamp1.turn_on().await?;
+    sifis.door("door1").await?.lock().await?;
+    lamp2.turn_on().await?;
+    lamp1.set_brightness(40).await?;
+
+    let mut updates = Vec::new();
+    for _ in 0..3 {
+        let (id, event) = events.next().await.unwrap()?;
+        assert_eq!(id, event.device_id);
+        updates.push((id, event.operation));
+    }
+    assert_eq!(
+        vec![
+            ("lamp1".to_owned(), "turn_lamp_on".to_owned()),
+            ("lamp2".to_owned(), "turn_lamp_on".to_owned()),
+            ("lamp1".to_owned(), "set_lamp_brightness".to_owned()),
+        ],
+        updates
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn events_stream_lagged() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let mut events = sifis.events().await?;
+
+    let lamp = sifis.lamp("lamp1").await?;
+    for brightness in 0..300 {
+        lamp.set_brightness(brightness % 100).await?;
+    }
+
+    assert_eq!(Some(HomeEvent::Lagged(44)), events.next().await);
+    let Some(HomeEvent::Changed(event)) = events.next().await else {
+        panic!("a change is expected");
+    };
+    assert_eq!("set_lamp_brightness", event.operation);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn events_stream_topology() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let mut events = sifis.events().await?;
+
+    let spec = DeviceSpec {
+        name: "Hall lamp".to_owned(),
+        room: None,
+        maintenance: Default::default(),
+        kind: DeviceKind::Lamp(LampState::default()),
+    };
+    sifis.add_device("lamp3", spec).await?;
+    sifis.lamp("lamp3").await?.turn_on().await?;
+    sifis.remove_device("lamp3").await?;
+
+    assert_eq!(
+        Some(HomeEvent::Added {
+            id: "lamp3".to_owned(),
+            kind: "Lamp".to_owned(),
+        }),
+        events.next().await
+    );
+    let Some(HomeEvent::Changed(event)) = events.next().await else {
+        panic!("a change is expected");
+    };
+    assert_eq!("turn_lamp_on", event.operation);
+    assert_eq!(
+        Some(HomeEvent::Removed {
+            id: "lamp3".to_owned()
+        }),
+        events.next().await
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn adjust_saturates() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+
+    let lamp = sifis.lamp("lamp1").await?;
+    assert_eq!(30, lamp.adjust_brightness(30).await?);
+    assert_eq!(20, lamp.adjust_brightness(-10).await?);
+    assert_eq!(0, lamp.adjust_brightness(-50).await?);
+    assert_eq!(100, lamp.adjust_brightness(500).await?);
+    assert_eq!(100, lamp.adjust_brightness(i16::MAX).await?);
+    assert_eq!(100, lamp.get_brightness().await?);
+
+    let sink = sifis.sink("sink1").await?;
+    assert_eq!(0, sink.adjust_flow(-10).await?);
+    assert_eq!(100, sink.adjust_flow(150).await?);
+
+    let fridge = sifis.fridge("fridge1").await?;
+    assert_eq!(-20, fridge.adjust_target_temperature(-100).await?);
+    assert_eq!(20, fridge.adjust_target_temperature(i16::MAX).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn observer() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let observer = sifis.observer();
+
+    sifis.lamp("lamp1").await?.set_brightness(40).await?;
+
+    let lamp = observer.lamp("lamp1").await?;
+    assert_eq!("lamp1", *lamp.id());
+    assert_eq!(40, lamp.get_brightness().await?);
+    assert!(!lamp.get_on_off().await?);
+    assert_eq!(2, observer.lamps().await?.len());
+    assert_eq!(
+        DoorLockStatus::Unlocked,
+        observer.door("door1").await?.lock_status().await?
+    );
+    assert!(observer.summary().await?.contains("lamp1"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn unchecked_handles() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+
+    let lamp = sifis.lamp_unchecked("lamp1");
+    assert!(lamp.turn_on().await?.state);
+    assert!(sifis.lamp("lamp1").await?.get_on_off().await?);
+    assert!(!sifis.door_unchecked("door1").is_open().await?);
+
+    let missing = sifis.lamp_unchecked("nope");
+    let err = missing.get_on_off().await.unwrap_err();
+    assert_eq!(Some(ErrorCode::NotFound), err.code());
+
+    let err = sifis.sink_unchecked("lamp1").get_flow().await.unwrap_err();
+    assert_eq!(Some(ErrorCode::KindMismatch), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lookup_timeout() -> Result<()> {
+    let backend = InMemoryBackend::default().with_faults(0.0, Duration::from_secs(2), None);
+    let sifis = Sifis::in_process(backend);
+
+    let start = std::time::Instant::now();
+    let err = sifis
+        .lamp_with_timeout("lamp1", Duration::from_millis(100))
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::Rpc(_)));
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    let sifis = sifis.with_timeout(Duration::from_millis(100));
+    let start = std::time::Instant::now();
+    assert!(sifis.door("door1").await.is_err());
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn recent_events() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    assert!(sifis.recent_events(10).await?.is_empty());
+
+    let lamp = sifis.lamp("lamp1").await?;
+    lamp.turn_on().await?;
+    lamp.get_brightness().await?;
+    lamp.set_brightness(50).await?;
+    sifis.door("door1").await?.lock().await?;
+
+    let events = sifis.recent_events(10).await?;
+    let operations: Vec<_> = events
+        .iter()
+        .map(|e| (e.device_id.as_str(), e.operation.as_str()))
+        .collect();
+    assert_eq!(
+        vec![
+            ("lamp1", "turn_lamp_on"),
+            ("lamp1", "set_lamp_brightness"),
+            ("door1", "lock_door")
+        ],
+        operations
+    );
+    assert!(events.iter().all(|e| e.peer_pid.is_none()));
+
+    let last = sifis.recent_events(1).await?;
+    assert_eq!(1, last.len());
+    assert_eq!("lock_door", last[0].operation);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn invoke() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+
+    assert_eq!(
+        json!({"state": true, "changed": true}),
+        sifis.invoke("lamp1", "turn_on", vec![]).await?
+    );
+    assert_eq!(
+        json!({ "requested": 40, "applied": 40 }),
+        sifis
+            .invoke("lamp1", "set_brightness", vec![json!(40)])
+            .await?
+    );
+    assert!(sifis.lamp("lamp1").await?.get_on_off().await?);
+    assert_eq!(40, sifis.lamp("lamp1").await?.get_brightness().await?);
+    assert_eq!(
+        json!("unlocked"),
+        sifis.invoke("door1", "lock_status", vec![]).await?
+    );
+
+    let err = sifis.invoke("door1", "turn_on", vec![]).await.unwrap_err();
+    assert_eq!(Some(ErrorCode::KindMismatch), err.code());
+    let err = sifis
+        .invoke("lamp1", "set_brightness", vec![json!("bright")])
+        .await
+        .unwrap_err();
+    assert_eq!(Some(ErrorCode::InvalidArgument), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn properties() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+
+    // A settable property of each kind, or a read-only one with its value
+    for (id, property, value, settable, unknown) in [
+        ("lamp1", "brightness", json!(40), true, "flow"),
+        ("sink1", "flow", json!(30), true, "brightness"),
+        (
+            "door1",
+            "lock_status",
+            json!("unlocked"),
+            false,
+            "target_temperature",
+        ),
+        (
+            "fridge1",
+            "target_temperature",
+            json!(6),
+            true,
+            "lock_status",
+        ),
+        ("curtain1", "open", json!(false), false, "on"),
+    ] {
+        if settable {
+            assert_eq!(
+                value,
+                sifis.set_property(id, property, value.clone()).await?
+            );
+        } else {
+            let err = sifis
+                .set_property(id, property, value.clone())
+                .await
+                .unwrap_err();
+            assert_eq!(Some(ErrorCode::InvalidArgument), err.code());
+        }
+        assert_eq!(value, sifis.get_property(id, property).await?);
+
+        let err = sifis.get_property(id, unknown).await.unwrap_err();
+        assert_eq!(Some(ErrorCode::InvalidArgument), err.code());
+        let err = sifis.set_property(id, unknown, json!(1)).await.unwrap_err();
+        assert_eq!(Some(ErrorCode::InvalidArgument), err.code());
+    }
+
+    // Set through the typed handlers, within the ranges of the capabilities
+    assert_eq!(json!(false), sifis.get_property("lamp1", "on").await?);
+    let err = sifis
+        .set_property("lamp1", "brightness", json!("bright"))
+        .await
+        .unwrap_err();
+    assert_eq!(Some(ErrorCode::InvalidArgument), err.code());
+    let err = sifis
+        .set_property("fridge1", "target_temperature", json!(40))
+        .await
+        .unwrap_err();
+    assert_eq!(Some(ErrorCode::OutOfRange), err.code());
+    assert_eq!(40, sifis.lamp("lamp1").await?.get_brightness().await?);
+
+    let err = sifis.get_property("nope", "on").await.unwrap_err();
+    assert_eq!(Some(ErrorCode::NotFound), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn property_ranges() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+
+    let brightness = sifis.property_range("lamp1", "brightness").await?;
+    assert_eq!(Range { min: 0, max: 100 }, brightness);
+    assert!(brightness.contains(100));
+    assert!(!brightness.contains(101));
+
+    let target = sifis
+        .property_range("fridge1", "target_temperature")
+        .await?;
+    assert_eq!(Range { min: -20, max: 20 }, target);
+    assert!(!target.contains(40));
+
+    // Only the whole numbers have a range
+    let err = sifis.property_range("lamp1", "on").await.unwrap_err();
+    assert_eq!(Some(ErrorCode::InvalidArgument), err.code());
+    let err = sifis.property_range("lamp1", "flow").await.unwrap_err();
+    assert_eq!(Some(ErrorCode::InvalidArgument), err.code());
+    let err = sifis.property_range("nope", "flow").await.unwrap_err();
+    assert_eq!(Some(ErrorCode::NotFound), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn describe_operation() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+
+    let doc = sifis.describe("turn_lamp_on").await?;
+    assert_eq!("turn_lamp_on", doc.name);
+    assert!(doc.hazards.contains(&Hazard::Fire));
+    assert!(doc.hazards.contains(&Hazard::EnergyConsumption));
+    assert!(doc.hazards.contains(&Hazard::LogEnergyConsumption));
+    assert!(doc.params.is_empty());
+
+    let doc = sifis.describe("set_sink_temp").await?;
+    assert_eq!(vec![Hazard::Scald], doc.hazards);
+    assert_eq!(1, doc.params.len());
+    assert_eq!(
+        ("temp", "u8"),
+        (&*doc.params[0].name, &*doc.params[0].type_name)
+    );
+
+    // The runtime answers from the same table
+    assert_eq!(Some(doc), OperationDoc::of("set_sink_temp"));
+
+    let err = sifis.describe("handshake").await.unwrap_err();
+    assert_eq!(Some(ErrorCode::InvalidArgument), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn read_cache() -> Result<()> {
+    let backend = InMemoryBackend::default();
+    let cached = Sifis::in_process(backend.clone()).with_cache(Duration::from_millis(300));
+    let other = Sifis::in_process(backend);
+
+    let lamp = cached.lamp("lamp1").await?;
+    assert!(!lamp.get_on_off().await?);
+
+    // Within the ttl the change made by another client is not seen
+    other.lamp("lamp1").await?.turn_on().await?;
+    assert!(!lamp.get_on_off().await?);
+
+    cached.clear_cache();
+    assert!(lamp.get_on_off().await?);
+
+    // A change made through the cached client is seen right away
+    lamp.set_brightness(30).await?;
+    assert_eq!(30, lamp.get_brightness().await?);
+    other.lamp("lamp1").await?.set_brightness(60).await?;
+    assert_eq!(30, lamp.get_brightness().await?);
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(60, lamp.get_brightness().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn curtain() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+
+    let curtains = sifis.curtains().await?;
+    assert_eq!(1, curtains.len());
+    let curtain = sifis.curtain("curtain1").await?;
+    assert!(!curtain.is_open().await?);
+
+    assert!(curtain.open().await?);
+    assert!(curtain.is_open().await?);
+    // Opening an open curtain is a no-op
+    assert!(curtain.open().await?);
+    assert!(curtain.is_open().await?);
+
+    assert!(!curtain.close().await?);
+    assert!(!curtain.is_open().await?);
+
+    let err = sifis.lamp("curtain1").await.err().unwrap();
+    assert_eq!(Some(ErrorCode::NotFound), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn snapshot() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    sifis.lamp("lamp2").await?.set_brightness(70).await?;
+    sifis.sink("sink1").await?.set_flow(20).await?;
+    sifis.door("door1").await?.lock().await?;
+
+    let snapshot = sifis.snapshot().await?;
+
+    let lamps = sifis.lamps().await?;
+    assert_eq!(lamps.len(), snapshot.lamps.len());
+    for (lamp, (id, state)) in lamps.iter().zip(&snapshot.lamps) {
+        assert_eq!(lamp.id, id.as_str());
+        assert_eq!(lamp.get_on_off().await?, state.on);
+        assert_eq!(lamp.get_brightness_hi().await?, state.brightness);
+    }
+
+    let (id, sink) = &snapshot.sinks[0];
+    let reads = sifis.sink(id).await?;
+    assert_eq!(reads.get_flow().await?, sink.flow);
+    assert_eq!(reads.get_temperature().await?, sink.temp);
+    assert_eq!(reads.get_water_level().await?, sink.level);
+
+    let (id, door) = &snapshot.doors[0];
+    let reads = sifis.door(id).await?;
+    assert_eq!(reads.is_open().await?, door.is_open);
+    assert_eq!(reads.lock_status().await?, door.lock);
+
+    let (id, fridge) = &snapshot.fridges[0];
+    let reads = sifis.fridge(id).await?;
+    assert_eq!(reads.is_open().await?, fridge.open);
+    assert_eq!(reads.temperature().await?, fridge.temperature);
+    assert_eq!(reads.target_temperature().await?, fridge.target_temperature);
+
+    let (id, curtain) = &snapshot.curtains[0];
+    assert_eq!(sifis.curtain(id).await?.is_open().await?, curtain.open);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn scheduled_scene() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let lamp = sifis.lamp("lamp1").await?;
+    lamp.set_brightness(30).await?;
+    lamp.turn_on().await?;
+    sifis.save_scene("morning", sifis.snapshot().await?).await?;
+    lamp.turn_off().await?;
+
+    let soon = SystemTime::now() + Duration::from_millis(200);
+    let id = sifis.schedule_scene("morning", soon).await?;
+    let tonight = SystemTime::now() + Duration::from_secs(3600);
+    let later = sifis.schedule_scene("morning", tonight).await?;
+    let pending: Vec<_> = sifis
+        .list_schedules()
+        .await?
+        .into_iter()
+        .map(|schedule| schedule.id)
+        .collect();
+    assert_eq!(vec![id, later], pending);
+    assert!(!lamp.get_on_off().await?);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(lamp.get_on_off().await?);
+    assert_eq!(30, lamp.get_brightness().await?);
+
+    sifis.cancel_schedule(later).await?;
+    assert!(sifis.list_schedules().await?.is_empty());
+    let err = sifis.cancel_schedule(later).await.unwrap_err();
+    assert_eq!(Some(ErrorCode::InvalidArgument), err.code());
+    let err = sifis.schedule_scene("evening", soon).await.unwrap_err();
+    assert_eq!(Some(ErrorCode::InvalidArgument), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn restore_snapshot() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    sifis.lamp("lamp2").await?.set_brightness(70).await?;
+    sifis.door("door1").await?.lock().await?;
+    let saved = sifis.snapshot().await?;
+
+    sifis.lamp("lamp1").await?.turn_on().await?;
+    sifis.lamp("lamp2").await?.set_brightness(10).await?;
+    sifis.door("door1").await?.unlock().await?;
+    sifis.sink("sink1").await?.set_flow(40).await?;
+    assert_ne!(saved, sifis.snapshot().await?);
+
+    let res = sifis.restore_snapshot(saved.clone()).await?;
+    assert_eq!(6, res.len());
+    assert!(res.iter().all(|(_, r)| r.is_ok()));
+    assert_eq!(saved, sifis.snapshot().await?);
+
+    // The devices that cannot be restored do not stop the others
+    sifis.lamp("lamp1").await?.turn_on().await?;
+    sifis.set_lockout("sink1", true).await?;
+    sifis.remove_device("curtain1").await?;
+    let mut mismatched = saved.clone();
+    mismatched.lamps[1].0 = "door1".to_owned();
+
+    let res = sifis.restore_snapshot(mismatched).await?;
+    let code = |id: &str| {
+        let (_, r) = res.iter().find(|(i, _)| i == id).unwrap();
+        r.as_ref().err().map(service::Error::code)
+    };
+    assert_eq!(None, code("lamp1"));
+    assert_eq!(Some(ErrorCode::KindMismatch), code("door1"));
+    assert_eq!(Some(ErrorCode::LockedOut), code("sink1"));
+    assert_eq!(Some(ErrorCode::NotFound), code("curtain1"));
+    assert!(!sifis.lamp("lamp1").await?.get_on_off().await?);
+    assert_eq!(
+        DoorLockStatus::Locked,
+        sifis.door("door1").await?.lock_status().await?
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lock_door_retry() -> Result<()> {
+    let sifis = Sifis::in_process(
+        InMemoryBackend::new([
+            door("front", DoorLockStatus::Jammed),
+            door("back", DoorLockStatus::Jammed),
+        ])
+        .with_seed(42),
+    );
+
+    let front = sifis.door("front").await?;
+    assert_eq!(LockResult::Jammed, front.lock().await?);
+    assert!(front.lock_with_retries(20).await?);
+    assert_eq!(DoorLockStatus::Locked, front.lock_status().await?);
+
+    let back = sifis.door("back").await?;
+    let mut locked = false;
+    for _ in 0..20 {
+        if back.lock_with_retries(1).await? {
+            locked = true;
+            break;
+        }
+        assert_eq!(DoorLockStatus::Jammed, back.lock_status().await?);
+    }
+    assert!(locked);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn door_jam_probability() -> Result<()> {
+    let (id, mut spec) = door("front", DoorLockStatus::Unlocked);
+    if let DeviceKind::Door(ref mut state) = spec.kind {
+        state.jam_probability = 1.0;
+    }
+    let sifis = Sifis::in_process(
+        InMemoryBackend::new([(id, spec), door("back", DoorLockStatus::Unlocked)]).with_seed(7),
+    );
+
+    let front = sifis.door("front").await?;
+    assert_eq!(LockResult::Jammed, front.lock().await?);
+    assert_eq!(DoorLockStatus::Jammed, front.lock_status().await?);
+    assert!(!front.lock_with_retries(5).await?);
+
+    let back = sifis.door("back").await?;
+    for _ in 0..10 {
+        assert_eq!(LockResult::Locked, back.lock().await?);
+        assert_eq!(LockResult::Unlocked, back.unlock().await?);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "debug-api")]
+#[tokio::test]
+async fn dump_state() -> Result<()> {
+    let (id, mut spec) = door("front", DoorLockStatus::Unlocked);
+    if let DeviceKind::Door(ref mut state) = spec.kind {
+        state.jam_probability = 0.5;
+    }
+    let sifis = Sifis::in_process(InMemoryBackend::new([(id, spec)]));
+
+    // Neither has a getter
+    let state = sifis.dump_state().await?;
+    assert_eq!(
+        json!(0.5),
+        state["front"]["state"]["Door"]["jam_probability"]
+    );
+    assert_eq!(json!(false), state["front"]["offline"]);
+
+    sifis.set_online("front", false).await?;
+    let state = sifis.dump_state().await?;
+    assert_eq!(json!(true), state["front"]["offline"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn device_kind() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+
+    for (id, kind) in [
+        ("lamp1", "Lamp"),
+        ("sink1", "Sink"),
+        ("door1", "Door"),
+        ("fridge1", "Fridge"),
+        ("curtain1", "Curtain"),
+    ] {
+        assert_eq!(kind, sifis.device_kind(id).await?);
+    }
+
+    let err = sifis.device_kind("oven1").await.unwrap_err();
+    assert_eq!(Some(ErrorCode::NotFound), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lamp_max_brightness() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::new([(
+        "capped".to_owned(),
+        DeviceSpec {
+            name: "Capped lamp".to_owned(),
+            room: None,
+            maintenance: Default::default(),
+            kind: DeviceKind::Lamp(LampState {
+                max_brightness: Some(60),
+                ..Default::default()
+            }),
+        },
+    )]));
+    let lamp = sifis.lamp("capped").await?;
+
+    assert_eq!(Some(60), lamp.max_brightness().await?);
+    assert_eq!(50, lamp.set_brightness(50).await?.applied);
+    assert_eq!(60, lamp.set_brightness(60).await?.applied);
+    let clamped = lamp.set_brightness(100).await?;
+    assert_eq!(
+        Clamped {
+            requested: 100,
+            applied: 60
+        },
+        clamped
+    );
+    assert!(clamped.was_clamped());
+    assert_eq!(60, lamp.get_brightness().await?);
+    assert_eq!(
+        60,
+        lamp.ramp_brightness(100, Duration::from_millis(10)).await?
+    );
+
+    let uncapped = Sifis::in_process(InMemoryBackend::default())
+        .lamp("lamp1")
+        .await?;
+    assert_eq!(None, uncapped.max_brightness().await?);
+    let set = uncapped.set_brightness(100).await?;
+    assert_eq!(100, set.applied);
+    assert!(!set.was_clamped());
+    // Above 100 percent even without a cap
+    let set = uncapped.set_brightness(150).await?;
+    assert_eq!(100, set.applied);
+    assert!(set.was_clamped());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn sink_flow_clamped() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let sink = sifis.sink("sink1").await?;
+
+    let set = sink.set_flow(40).await?;
+    assert_eq!(
+        Clamped {
+            requested: 40,
+            applied: 40
+        },
+        set
+    );
+    assert!(!set.was_clamped());
+
+    let set = sink.set_flow(200).await?;
+    assert_eq!(100, set.applied);
+    assert!(set.was_clamped());
+    assert_eq!(100, sink.get_flow().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lamp_color_temperature() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::new([(
+        "tunable".to_owned(),
+        DeviceSpec {
+            name: "Tunable white lamp".to_owned(),
+            room: None,
+            maintenance: Default::default(),
+            kind: DeviceKind::Lamp(LampState {
+                color_temp_kelvin: Some(2700),
+                ..Default::default()
+            }),
+        },
+    )]));
+    let lamp = sifis.lamp("tunable").await?;
+
+    assert_eq!(Some(2700), lamp.color_temperature().await?);
+    assert_eq!(2000, lamp.set_color_temperature(2000).await?);
+    assert_eq!(6500, lamp.set_color_temperature(6500).await?);
+    assert_eq!(Some(6500), lamp.color_temperature().await?);
+
+    for kelvin in [1999, 6501] {
+        let err = lamp.set_color_temperature(kelvin).await.unwrap_err();
+        assert_eq!(Some(ErrorCode::OutOfRange), err.code());
+    }
+    assert_eq!(Some(6500), lamp.color_temperature().await?);
+
+    let plain = Sifis::in_process(InMemoryBackend::default())
+        .lamp("lamp1")
+        .await?;
+    assert_eq!(None, plain.color_temperature().await?);
+    let err = plain.set_color_temperature(4000).await.unwrap_err();
+    assert_eq!(Some(ErrorCode::KindMismatch), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lamp_brightness_hi() -> Result<()> {
+    // Every percentage survives the round trip, the ends map to the ends
+    for percent in Lamp::BRIGHTNESS {
+        let hi = Lamp::brightness_to_hi(percent);
+        assert_eq!(percent, Lamp::brightness_from_hi(hi));
+    }
+    assert_eq!(0, Lamp::brightness_to_hi(0));
+    assert_eq!(u16::MAX, Lamp::brightness_to_hi(100));
+    assert_eq!(u16::MAX, Lamp::brightness_to_hi(200));
+
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let lamp = sifis.lamp("lamp1").await?;
+
+    // The 8-bit view reads the 16-bit value rounded, and sets it exactly
+    assert_eq!(1000, lamp.set_brightness_hi(1000).await?.applied);
+    assert_eq!(1000, lamp.get_brightness_hi().await?);
+    assert_eq!(2, lamp.get_brightness().await?);
+    assert_eq!(40, lamp.set_brightness(40).await?.applied);
+    assert_eq!(Lamp::brightness_to_hi(40), lamp.get_brightness_hi().await?);
+    assert!(!lamp.set_brightness_hi(u16::MAX).await?.was_clamped());
+    assert_eq!(100, lamp.get_brightness().await?);
+
+    // The cap applies to both views
+    let capped = Sifis::in_process(InMemoryBackend::new([(
+        "capped".to_owned(),
+        DeviceSpec {
+            name: "Capped lamp".to_owned(),
+            room: None,
+            maintenance: Default::default(),
+            kind: DeviceKind::Lamp(LampState {
+                max_brightness: Some(60),
+                ..Default::default()
+            }),
+        },
+    )]))
+    .lamp("capped")
+    .await?;
+    let cap = Lamp::brightness_to_hi(60);
+    assert_eq!(
+        Clamped {
+            requested: u16::MAX,
+            applied: cap
+        },
+        capped.set_brightness_hi(u16::MAX).await?
+    );
+    assert_eq!(60, capped.get_brightness().await?);
+
+    // The snapshots keep the high resolution value
+    lamp.set_brightness_hi(12345).await?;
+    let (_, state) = &sifis.snapshot().await?.lamps[0];
+    assert_eq!(12345, state.brightness);
+
+    // Serialized in percent, along with the value when not exact
+    let value = serde_json::to_value(state)?;
+    assert_eq!(json!(19), value["brightness"]);
+    assert_eq!(json!(12345), value["brightness_hi"]);
+    assert_eq!(*state, serde_json::from_value(value)?);
+    let state: LampState = serde_json::from_value(json!({"brightness": 80, "on": true}))?;
+    assert_eq!(Lamp::brightness_to_hi(80), state.brightness);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lamp_set_state() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::new([(
+        "tunable".to_owned(),
+        DeviceSpec {
+            name: "Tunable white lamp".to_owned(),
+            room: None,
+            maintenance: Default::default(),
+            kind: DeviceKind::Lamp(LampState {
+                max_brightness: Some(80),
+                color_temp_kelvin: Some(2700),
+                ..Default::default()
+            }),
+        },
+    )]));
+    let lamp = sifis.lamp("tunable").await?;
+
+    let state = LampState {
+        brightness: Lamp::brightness_to_hi(90),
+        on: true,
+        max_brightness: None,
+        color_temp_kelvin: Some(4000),
+    };
+    let expected = LampState {
+        brightness: Lamp::brightness_to_hi(80),
+        on: true,
+        max_brightness: Some(80),
+        color_temp_kelvin: Some(4000),
+    };
+    assert_eq!(expected, lamp.set_state(state).await?);
+    assert!(lamp.get_on_off().await?);
+    assert_eq!(80, lamp.get_brightness().await?);
+    assert_eq!(Some(4000), lamp.color_temperature().await?);
+
+    // A refused field leaves the whole state untouched
+    let err = lamp
+        .set_state(LampState {
+            brightness: Lamp::brightness_to_hi(10),
+            on: false,
+            max_brightness: None,
+            color_temp_kelvin: Some(9000),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(Some(ErrorCode::OutOfRange), err.code());
+    assert!(lamp.get_on_off().await?);
+    assert_eq!(80, lamp.get_brightness().await?);
+
+    let plain = sifis.lamp("tunable").await?;
+    let off = plain.set_state(LampState::default()).await?;
+    assert!(!off.on);
+    assert_eq!(Some(4000), off.color_temp_kelvin);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn capabilities() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+
+    let lamp = sifis.capabilities("lamp1").await?;
+    assert_eq!("Lamp", lamp.kind);
+    let brightness = lamp
+        .properties
+        .iter()
+        .find(|p| p.name == "brightness")
+        .unwrap();
+    assert_eq!(ValueType::Integer { min: 0, max: 100 }, brightness.value);
+    assert_eq!(Some("set_brightness"), brightness.set.as_deref());
+    assert!(lamp.operations.iter().any(|op| op.name == "turn_on"));
+
+    // Every property can be read through invoke
+    for device in sifis.devices().await? {
+        let capabilities = sifis.capabilities(&device.id).await?;
+        assert_eq!(device.kind, capabilities.kind);
+        for property in capabilities.properties {
+            sifis.invoke(&device.id, &property.get, vec![]).await?;
+        }
+    }
+
+    assert_eq!(
+        Some(ErrorCode::NotFound),
+        sifis.capabilities("nope").await.unwrap_err().code()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rate_limit() -> Result<()> {
+    let backend = InMemoryBackend::default().with_rate_limit(Duration::from_millis(200));
+    let sifis = Sifis::in_process(backend);
+
+    let lamp = sifis.lamp("lamp1").await?;
+    assert!(lamp.turn_on().await?.changed);
+    let err = lamp.turn_on().await.unwrap_err();
+    assert_eq!(Some(ErrorCode::RateLimited), err.code());
+    let wait = err.retry_after().unwrap();
+    assert!(wait > Duration::ZERO && wait <= Duration::from_millis(200));
+
+    // The other devices are limited on their own
+    sifis.lamp("lamp2").await?.turn_on().await?;
+
+    tokio::time::sleep(wait).await;
+    // Only the commands applied count against the limit
+    let err = lamp.set_color_temperature(3000).await.unwrap_err();
+    assert_eq!(Some(ErrorCode::KindMismatch), err.code());
+    assert!(!lamp.turn_on().await?.changed);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn priority() -> Result<()> {
+    // Each call holds the lamp for 100ms
+    let backend = InMemoryBackend::default().with_faults(0.0, Duration::from_millis(100), None);
+    let sifis = Sifis::in_process(backend);
+
+    let queued: Vec<_> = (1..=5)
+        .map(|i| {
+            let sifis = sifis.clone();
+            tokio::spawn(async move { sifis.lamp_unchecked("lamp1").set_brightness(i * 10).await })
+        })
+        .collect();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    // Served as soon as the lamp is free, ahead of the queued calls
+    sifis.with_priority(Priority::Urgent).all_off().await?;
+    for call in queued {
+        call.await??;
+    }
+
+    let operations: Vec<_> = sifis
+        .recent_events(20)
+        .await?
+        .into_iter()
+        .filter(|e| e.device_id == "lamp1")
+        .map(|e| e.operation)
+        .collect();
+    let off = operations.iter().position(|op| op == "all_off").unwrap();
+    assert!(off <= 2, "{operations:?}");
+    assert_eq!(6, operations.len());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn priority_rate_limit() -> Result<()> {
+    let backend = InMemoryBackend::default().with_rate_limit(Duration::from_secs(1));
+    let sifis = Sifis::in_process(backend);
+    let urgent = sifis.with_priority(Priority::Urgent);
+
+    sifis.lamp("lamp1").await?.turn_on().await?;
+    let err = sifis.lamp("lamp1").await?.turn_off().await.unwrap_err();
+    assert_eq!(Some(ErrorCode::RateLimited), err.code());
+    assert!(urgent.lamp("lamp1").await?.turn_off().await?.changed);
+
+    // The routine client is unchanged
+    assert!(sifis
+        .with_priority(Priority::Routine)
+        .lamp("lamp1")
+        .await?
+        .turn_on()
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancellation() -> Result<()> {
+    let token = CancellationToken::new();
+    let sifis = Sifis::in_process(InMemoryBackend::default()).with_cancellation(token.clone());
+
+    // The lamp never gets there, the runtime answers once the time runs out
+    let lamp = sifis.lamp("lamp1").await?;
+    let pending =
+        tokio::spawn(async move { lamp.wait_for_brightness(42, Duration::from_secs(5)).await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let start = std::time::Instant::now();
+    token.cancel();
+    let err = pending.await?.unwrap_err();
+    assert_eq!(Some(ErrorCode::Cancelled), err.code());
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // The requests made afterwards fail at once as well
+    let err = sifis.ping().await.unwrap_err();
+    assert_eq!(Some(ErrorCode::Cancelled), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn metrics() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let counter = |text: &str, labels: &str| -> Option<u64> {
+        let prefix = format!("sifis_calls_total{{{labels}}} ");
+        text.lines()
+            .find_map(|l| l.strip_prefix(&prefix))
+            .map(|count| count.parse().unwrap())
+    };
+
+    sifis.lamp("lamp1").await?.turn_on().await?;
+    let text = sifis.metrics_text().await?;
+    assert_eq!(
+        Some(1),
+        counter(&text, r#"method="turn_lamp_on",outcome="ok""#)
+    );
+    assert!(text.contains("sifis_devices{kind=\"Lamp\"} 2"));
+    assert!(text.contains("sifis_power_watts 0"));
+
+    sifis.lamp("lamp1").await?.turn_on().await?;
+    assert!(sifis.device_kind("nope").await.is_err());
+    let text = sifis.metrics_text().await?;
+    assert_eq!(
+        Some(2),
+        counter(&text, r#"method="turn_lamp_on",outcome="ok""#)
+    );
+    assert_eq!(
+        Some(1),
+        counter(&text, r#"method="get_device_kind",outcome="error""#)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lamp_ensure() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+    let writes = |text: &str| -> Vec<Option<u64>> {
+        ["turn_lamp_on", "turn_lamp_off", "set_lamp_brightness"]
+            .iter()
+            .map(|method| {
+                let prefix = format!(r#"sifis_calls_total{{method="{method}",outcome="ok"}} "#);
+                text.lines()
+                    .find_map(|l| l.strip_prefix(&prefix))
+                    .map(|count| count.parse().unwrap())
+            })
+            .collect()
+    };
+    let lamp = sifis.lamp("lamp1").await?;
+
+    let state = lamp.ensure(true, Some(70)).await?;
+    assert!(state.on);
+    assert_eq!(70, Lamp::brightness_from_hi(state.brightness));
+    let before = writes(&sifis.metrics_text().await?);
+    assert_eq!(vec![Some(1), None, Some(1)], before);
+
+    // Already there, nothing to do
+    let state = lamp.ensure(true, Some(70)).await?;
+    assert!(state.on);
+    assert_eq!(70, Lamp::brightness_from_hi(state.brightness));
+    let state = lamp.ensure(true, None).await?;
+    assert!(state.on);
+    assert_eq!(before, writes(&sifis.metrics_text().await?));
+
+    // Only the brightness changes
+    lamp.ensure(true, Some(30)).await?;
+    assert_eq!(
+        vec![Some(1), None, Some(2)],
+        writes(&sifis.metrics_text().await?)
+    );
+
+    // Only the on status changes
+    let state = lamp.ensure(false, Some(30)).await?;
+    assert!(!state.on);
+    assert_eq!(
+        vec![Some(1), Some(1), Some(2)],
+        writes(&sifis.metrics_text().await?)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn metrics_latency() -> Result<()> {
+    // Every device call takes at least 5ms
+    let backend = InMemoryBackend::default().with_faults(0.0, Duration::from_millis(5), None);
+    let sifis = Sifis::in_process(backend);
+    let metric = |text: &str, name: &str| -> Option<f64> {
+        let prefix = format!("sifis_call_duration_seconds_{name} ");
+        text.lines()
+            .find_map(|l| l.strip_prefix(&prefix))
+            .map(|value| value.parse().unwrap())
+    };
+
+    let lamp = sifis.lamp_unchecked("lamp1");
+    for brightness in [10, 20, 30, 40] {
+        lamp.set_brightness(brightness).await?;
+    }
+    let text = sifis.metrics_text().await?;
+
+    let method = r#"{method="set_lamp_brightness"}"#;
+    assert_eq!(Some(4.0), metric(&text, &format!("count{method}")));
+    assert!(metric(&text, &format!("sum{method}")).unwrap() >= 0.02);
+    let bucket = |le: &str| {
+        metric(
+            &text,
+            &format!(r#"bucket{{method="set_lamp_brightness",le="{le}"}}"#),
+        )
+    };
+    assert_eq!(Some(0.0), bucket("0.001"));
+    assert_eq!(Some(4.0), bucket("+Inf"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn metrics_disabled() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default().with_metrics(false));
+
+    sifis.lamp("lamp1").await?.turn_on().await?;
+    let text = sifis.metrics_text().await?;
+    assert!(!text.contains("sifis_calls_total{"));
+    assert!(!text.contains("sifis_call_duration_seconds_count{"));
+    assert!(text.contains("sifis_devices{kind=\"Lamp\"} 2"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn devices() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default());
+
+    let devices = sifis.devices().await?;
+    let ids: Vec<_> = devices.iter().map(|d| d.id.as_str()).collect();
+    assert_eq!(
+        vec!["curtain1", "door1", "fridge1", "lamp1", "lamp2", "sink1"],
+        ids
+    );
+
+    let lamp = devices.iter().find(|d| d.id == "lamp1").unwrap();
+    assert_eq!("Safe lamp", lamp.name);
+    assert_eq!("Lamp", lamp.kind);
+
+    assert!(sifis.lamps_summary().await?.contains("Safe lamp"));
+
+    for device in devices {
+        let kind = device.known_kind().unwrap();
+        assert_eq!(device.kind, kind.display());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unknown_kind() -> Result<()> {
+    // As listed by a newer runtime
+    let payload = json!([
+        {"id": "lamp1", "name": "Safe lamp", "room": null, "kind": "Lamp"},
+        {"id": "thermostat1", "name": "Hall Thermostat", "room": "Hall", "kind": "Thermostat"},
+    ]);
+    let devices: Vec<DeviceInfo> = serde_json::from_value(payload)?;
+
+    assert_eq!(Some(KnownKind::Lamp), devices[0].known_kind());
+    assert_eq!("Hall Thermostat", devices[1].name);
+    assert_eq!("Thermostat", devices[1].kind);
+    assert_eq!(None, devices[1].known_kind());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn multiple_runtimes() -> Result<()> {
+    let kitchen = Sifis::in_process(InMemoryBackend::default());
+    let garage = Sifis::in_process(InMemoryBackend::new(
+        InMemoryBackend::default_devices()
+            .into_iter()
+            .filter(|(id, _)| id.starts_with("lamp") || id.starts_with("door")),
+    ));
+    let multi = SifisMulti::new()
+        .with_zone("kitchen", kitchen.clone())
+        .with_zone("garage", garage.clone());
+
+    assert_eq!(vec!["garage", "kitchen"], multi.zones().collect::<Vec<_>>());
+
+    multi.lamp("kitchen/lamp1").await?.turn_on().await?;
+    assert!(kitchen.lamp("lamp1").await?.get_on_off().await?);
+    assert!(!garage.lamp("lamp1").await?.get_on_off().await?);
+    assert!(!multi.lamp("garage/lamp1").await?.get_on_off().await?);
+
+    multi.door("garage/door1").await?.lock().await?;
+    assert_eq!(
+        DoorLockStatus::Locked,
+        garage.door("door1").await?.lock_status().await?
+    );
+    assert_eq!(
+        DoorLockStatus::Unlocked,
+        kitchen.door("door1").await?.lock_status().await?
+    );
+
+    // The sink is only in the kitchen
+    multi.sink("kitchen/sink1").await?;
+    for id in ["garage/sink1", "attic/lamp1", "lamp1"] {
+        let err = multi.lamp(id).await.err().unwrap();
+        assert_eq!(Some(ErrorCode::NotFound), err.code(), "{id}");
+    }
+
+    let ids: Vec<_> = multi.devices().await?.into_iter().map(|d| d.id).collect();
+    assert_eq!(
+        vec![
+            "garage/door1",
+            "garage/lamp1",
+            "garage/lamp2",
+            "kitchen/curtain1",
+            "kitchen/door1",
+            "kitchen/fridge1",
+            "kitchen/lamp1",
+            "kitchen/lamp2",
+            "kitchen/sink1"
+        ],
+        ids
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rooms() -> Result<()> {
+    let devices = InMemoryBackend::default_devices()
+        .into_iter()
+        .map(|(id, mut spec)| {
+            spec.room = match id.as_str() {
+                "lamp1" | "sink1" | "fridge1" => Some("Kitchen".to_owned()),
+                "lamp2" | "curtain1" => Some("Bedroom".to_owned()),
+                _ => None,
+            };
+            (id, spec)
+        });
+    let sifis = Sifis::in_process(InMemoryBackend::new(devices));
+
+    assert_eq!(vec!["Bedroom", "Kitchen"], sifis.rooms().await?);
+
+    let kitchen = sifis.devices_in_room("Kitchen").await?;
+    let ids: Vec<_> = kitchen.iter().map(|d| d.id.as_str()).collect();
+    assert_eq!(vec!["fridge1", "lamp1", "sink1"], ids);
+    assert!(kitchen.iter().all(|d| d.room.as_deref() == Some("Kitchen")));
+
+    let bedroom = sifis.devices_in_room("Bedroom").await?;
+    let ids: Vec<_> = bedroom.iter().map(|d| d.id.as_str()).collect();
+    assert_eq!(vec!["curtain1", "lamp2"], ids);
+
+    assert!(sifis.devices_in_room("Attic").await?.is_empty());
+    let door = sifis.devices().await?.into_iter().find(|d| d.id == "door1");
+    assert_eq!(None, door.unwrap().room);
+
+    // A room exists as long as a device is in it
+    sifis.remove_device("lamp2").await?;
+    sifis.remove_device("curtain1").await?;
+    assert_eq!(vec!["Kitchen"], sifis.rooms().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn summary_unavailable() -> Result<()> {
+    let backend = InMemoryBackend::default().with_faults(1.0, Duration::ZERO, None);
+    let sifis = Sifis::in_process(backend);
+
+    let summary = sifis.summary().await?;
+    assert!(summary.contains("lamp1"));
+    assert!(summary.contains("<unavailable>"));
+
+    assert!(sifis.lamp("lamp1").await?.get_on_off().await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn concurrent_devices() -> Result<()> {
+    let delay = Duration::from_millis(500);
+    let backend = InMemoryBackend::default().with_faults(0.0, delay, None);
+    let sifis = Sifis::in_process(backend);
+    let lamp1 = sifis.lamp("lamp1").await?;
+    let lamp2 = sifis.lamp("lamp2").await?;
+
+    // Two calls on lamp1 take their turn, the read on lamp2 goes through
+    let start = std::time::Instant::now();
+    let (on, brightness, read) = tokio::join!(lamp1.turn_on(), lamp1.set_brightness(50), async {
+        let brightness = lamp2.get_brightness().await;
+        (brightness, start.elapsed())
+    });
+    on?;
+    brightness?;
+    read.0?;
+    assert!(read.1 < 2 * delay);
+    assert!(start.elapsed() >= 2 * delay);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn unsupported_method() -> Result<()> {
+    let sifis = Sifis::in_process(InMemoryBackend::default().without_methods(["wait_events"]));
+
+    assert!(sifis.supports("turn_lamp_on").await);
+    assert!(!sifis.supports("wait_events").await);
+    assert!(!sifis.supports("make_coffee").await);
+    let info = sifis.server_info().await?;
+    assert_eq!(service::PROTOCOL_VERSION, info.protocol_version);
+    assert!(info.methods.windows(2).all(|w| w[0] < w[1]));
+    assert!(info.supports("advance_time"));
+    assert!(info
+        .methods
+        .iter()
+        .all(|method| service::METHODS.contains(&method.as_str())));
+
+    let e = sifis.events().await.err().unwrap();
+    assert_eq!(Some(ErrorCode::Unsupported), e.code());
+    assert!(matches!(
+        e,
+        Error::Runtime(service::Error::Unsupported { method }) if method == "wait_events"
+    ));
+
+    // A client falls back to polling
+    sifis.lamp("lamp1").await?.turn_on().await?;
+    assert_eq!(1, sifis.recent_events(10).await?.len());
+
+    let urgent = sifis.with_priority(Priority::Urgent);
+    let e = urgent.events().await.err().unwrap();
+    assert_eq!(Some(ErrorCode::Unsupported), e.code());
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use anyhow::Result;
+use futures::{future, prelude::*};
+use sifis_api::service::{SifisApiRequest, SifisApiResponse, PROTOCOL_VERSION};
+use sifis_api::{ConnectionState, Error, Sifis};
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+use tarpc::server::{BaseChannel, Channel};
+use tarpc::tokio_serde::formats::Bincode;
+use tempfile::tempdir;
+
+#[tokio::test]
+async fn hung_runtime() -> Result<()> {
+    let dir = tempdir()?;
+    let sock = dir.path().join("sifis.sock");
+
+    // Accept the connections and never answer past the version exchange
+    let listener = tarpc::serde_transport::unix::listen(&sock, Bincode::default).await?;
+    tokio::spawn(
+        listener
+            .filter_map(|r| future::ready(r.ok()))
+            .map(BaseChannel::with_defaults)
+            .for_each(|channel| {
+                let serve = tarpc::server::serve(|_, req| async move {
+                    match req {
+                        SifisApiRequest::Handshake { .. } => {
+                            SifisApiResponse::Handshake(Ok(PROTOCOL_VERSION))
+                        }
+                        _ => future::pending().await,
+                    }
+                });
+                tokio::spawn(channel.execute(serve));
+                future::ready(())
+            }),
+    );
+
+    let sifis = Sifis::from_path(&sock)
+        .await?
+        .with_keepalive(Duration::from_millis(100));
+    assert!(sifis.is_connected());
+    let mut events = sifis.connection_events();
+    assert_eq!(Some(ConnectionState::Connected), events.next().await);
+
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    assert!(!sifis.is_connected());
+    let disconnected = tokio::time::timeout(Duration::from_millis(100), events.next()).await?;
+    assert_eq!(Some(ConnectionState::Disconnected), disconnected);
+    assert_eq!(None, events.next().await);
+
+    let start = Instant::now();
+    let err = sifis.lamps().await.unwrap_err();
+    assert!(start.elapsed() < Duration::from_millis(100));
+    assert!(matches!(err, Error::Io(e) if e.kind() == ErrorKind::NotConnected));
+
+    Ok(())
+}
@@ -1,7 +1,16 @@
 use anyhow::Result;
 use assert_cmd::prelude::*;
-use sifis_api::{DoorLockStatus, Sifis};
-use std::{path::PathBuf, process::Command, sync::OnceLock, time::Duration};
+use futures::StreamExt;
+use sifis_api::{
+    service::ErrorCode, CancellationToken, ConnectionState, DeviceKind, DeviceSpec, DoorLockStatus,
+    HomeEvent, Lamp, LampState, LockResult, Priority, Sifis,
+};
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
 use tempfile::{tempdir, TempDir};
 
 #[derive(Debug)]
@@ -12,17 +21,25 @@ struct Mock {
 
 static SERVER: OnceLock<Result<Mock>> = OnceLock::new();
 
+fn start_server(dir: &Path, sock: &Path, envs: &[(&str, &str)]) -> Result<Child> {
+    let server = Command::cargo_bin("sifis-runtime-mock")?
+        .env("SIFIS_SERVER", sock)
+        .envs(envs.iter().copied())
+        .current_dir(dir)
+        .spawn()?;
+
+    // Wait for the server to get up
+    std::thread::sleep(Duration::from_secs(1));
+
+    Ok(server)
+}
+
 impl Mock {
     fn new() -> Result<Mock> {
         let dir: TempDir = tempdir()?;
         let sock: PathBuf = dir.path().join("sifis.sock");
 
-        let _server = Command::cargo_bin("sifis-runtime-mock")?
-            .env("SIFIS_SERVER", &sock)
-            .spawn()?;
-
-        // Wait for the server to get up
-        std::thread::sleep(Duration::from_secs(1));
+        let _server = start_server(dir.path(), &sock, &[])?;
 
         Ok(Mock { sock, _dir: dir })
     }
@@ -41,6 +58,379 @@ impl Mock {
     }
 }
 
+/// A mock runtime owned by a single test, free to change the device states
+/// without interfering with the other tests.
+struct IsolatedMock {
+    server: Child,
+    sock: PathBuf,
+    _dir: TempDir,
+}
+
+impl IsolatedMock {
+    fn new() -> Result<IsolatedMock> {
+        Self::start(tempdir()?, &[])
+    }
+
+    /// Start the mock with the devices described by `conf`
+    fn with_config(conf: &str) -> Result<IsolatedMock> {
+        Self::with_config_env(conf, &[])
+    }
+
+    /// Start the mock with the devices described by `conf` and additional
+    /// environment variables
+    fn with_config_env(conf: &str, envs: &[(&str, &str)]) -> Result<IsolatedMock> {
+        let dir: TempDir = tempdir()?;
+        std::fs::write(dir.path().join("sifis-runtime.toml"), conf)?;
+
+        Self::start(dir, envs)
+    }
+
+    /// Start the mock with additional environment variables
+    fn with_env(envs: &[(&str, &str)]) -> Result<IsolatedMock> {
+        Self::start(tempdir()?, envs)
+    }
+
+    fn start(dir: TempDir, envs: &[(&str, &str)]) -> Result<IsolatedMock> {
+        let sock: PathBuf = dir.path().join("sifis.sock");
+
+        let server = start_server(dir.path(), &sock, envs)?;
+
+        Ok(IsolatedMock {
+            server,
+            sock,
+            _dir: dir,
+        })
+    }
+
+    async fn connect(&self) -> Result<Sifis> {
+        let sifis = Sifis::from_path(&self.sock).await?;
+
+        Ok(sifis)
+    }
+}
+
+impl Drop for IsolatedMock {
+    fn drop(&mut self) {
+        let _ = self.server.kill();
+        let _ = self.server.wait();
+    }
+}
+
+#[tokio::test]
+async fn command_line() -> Result<()> {
+    let dir = tempdir()?;
+    let conf = dir.path().join("a.toml");
+    std::fs::write(
+        &conf,
+        r#"
+        [devices.lamp_a]
+        name = "Lamp A"
+        [devices.lamp_a.kind.Lamp]
+        brightness = 0
+        on = false
+        "#,
+    )?;
+    let sock = dir.path().join("a.sock");
+
+    let server = Command::cargo_bin("sifis-runtime-mock")?
+        .env("SIFIS_SERVER", dir.path().join("env.sock"))
+        .arg("--config")
+        .arg(&conf)
+        .arg("--socket")
+        .arg(&sock)
+        .spawn()?;
+    std::thread::sleep(Duration::from_secs(1));
+    let mock = IsolatedMock {
+        server,
+        sock,
+        _dir: dir,
+    };
+
+    let sifis = mock.connect().await?;
+    let lamps = sifis.lamps().await?;
+    assert_eq!(1, lamps.len());
+    assert_eq!("lamp_a", lamps[0].id);
+
+    Ok(())
+}
+
+#[test]
+fn client_one_shot() -> Result<()> {
+    let mock = IsolatedMock::new()?;
+    let client = || -> Result<Command> {
+        let mut client = Command::cargo_bin("sifis-client")?;
+        client.env("SIFIS_SERVER", &mock.sock);
+        Ok(client)
+    };
+
+    let out = client()?.arg("turn_light_on").arg("lamp1").output()?;
+    assert!(out.status.success());
+
+    let out = client()?.arg("list_lamps").output()?;
+    assert!(out.status.success());
+    let table = String::from_utf8(out.stdout)?;
+    assert!(table
+        .lines()
+        .any(|l| l.starts_with("lamp1") && l.contains(" On ")));
+
+    let out = client()?
+        .arg("turn_light_on")
+        .arg("no_such_lamp")
+        .output()?;
+    assert!(!out.status.success());
+    assert!(!out.stderr.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn client_export_import() -> Result<()> {
+    let mock = IsolatedMock::new()?;
+    let client = || -> Result<Command> {
+        let mut client = Command::cargo_bin("sifis-client")?;
+        client.env("SIFIS_SERVER", &mock.sock);
+        Ok(client)
+    };
+    let file = mock.sock.with_file_name("home.json");
+    let sifis = mock.connect().await?;
+
+    sifis.lamp("lamp1").await?.set_brightness(60).await?;
+    let saved = sifis.snapshot().await?;
+    let out = client()?.arg("export").arg(&file).output()?;
+    assert!(out.status.success());
+
+    sifis.lamp("lamp1").await?.set_brightness(20).await?;
+    sifis.sink("sink1").await?.set_flow(50).await?;
+    let out = client()?.arg("import").arg(&file).output()?;
+    assert!(out.status.success());
+    let table = String::from_utf8(out.stdout)?;
+    assert!(table
+        .lines()
+        .any(|l| l.starts_with("lamp1") && l.ends_with("Ok")));
+    assert_eq!(saved, sifis.snapshot().await?);
+
+    // A device missing from the runtime is reported, the others restored
+    sifis.remove_device("curtain1").await?;
+    let out = client()?.arg("import").arg(&file).output()?;
+    assert!(out.status.success());
+    let table = String::from_utf8(out.stdout)?;
+    assert!(table
+        .lines()
+        .any(|l| l.starts_with("curtain1") && !l.ends_with("Ok")));
+
+    let out = client()?
+        .arg("import")
+        .arg(mock.sock.with_file_name("missing.json"))
+        .output()?;
+    assert!(!out.status.success());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn channels_per_client() -> Result<()> {
+    let mock = IsolatedMock::with_env(&[("SIFIS_MAX_CHANNELS_PER_CLIENT", "2")])?;
+
+    let first = mock.connect().await?;
+    let second = mock.connect().await?;
+    let over = tokio::time::timeout(Duration::from_secs(5), mock.connect()).await?;
+    assert!(over.is_err());
+
+    // The connections within the limit keep working
+    assert!(first.lamp("lamp1").await?.turn_on().await?.state);
+    assert!(second.lamp("lamp1").await?.get_on_off().await?);
+
+    // Closing one makes room for another
+    second.shutdown().await;
+    let start = Instant::now();
+    let third = loop {
+        match mock.connect().await {
+            Ok(sifis) => break sifis,
+            Err(_) if start.elapsed() < Duration::from_secs(5) => {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    assert!(third.lamp("lamp1").await?.get_on_off().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn socket_mode() -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mock = IsolatedMock::with_env(&[("SIFIS_SOCKET_MODE", "0600")])?;
+
+    let mode = std::fs::metadata(&mock.sock)?.permissions().mode();
+    assert_eq!(0o600, mode & 0o777);
+    // The owner can still connect
+    assert!(mock.connect().await?.ping().await.is_ok());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn shared_connection() -> Result<()> {
+    let mock = IsolatedMock::with_env(&[("SIFIS_MAX_CHANNELS_PER_CLIENT", "1")])?;
+
+    let first = Sifis::shared_from_path(&mock.sock).await?;
+    let second = Sifis::shared_from_path(&mock.sock).await?;
+    assert!(first.lamp("lamp1").await?.turn_on().await?.state);
+    assert!(second.lamp("lamp1").await?.get_on_off().await?);
+
+    // The only connection allowed is the shared one
+    let fresh = tokio::time::timeout(Duration::from_secs(5), mock.connect()).await?;
+    assert!(fresh.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn shared_connection_proxied() -> Result<()> {
+    let mock = IsolatedMock::with_env(&[("SIFIS_MAX_CHANNELS_PER_CLIENT", "1")])?;
+
+    let urgent = Sifis::shared_from_path(&mock.sock)
+        .await?
+        .with_priority(Priority::Urgent);
+    assert!(urgent.lamp("lamp1").await?.turn_on().await?.state);
+
+    let token = CancellationToken::new();
+    let cancellable = Sifis::shared_from_path(&mock.sock)
+        .await?
+        .with_cancellation(token.clone());
+    assert!(cancellable.lamp("lamp1").await?.get_on_off().await?);
+
+    // Cancelling one client leaves the others sharing the connection alone
+    token.cancel();
+    let e = cancellable.ping().await.unwrap_err();
+    assert_eq!(Some(ErrorCode::Cancelled), e.code());
+    assert!(urgent.ping().await.is_ok());
+    assert!(Sifis::shared_from_path(&mock.sock)
+        .await?
+        .ping()
+        .await
+        .is_ok());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn frame_limit() -> Result<()> {
+    let mock = IsolatedMock::with_env(&[("SIFIS_MAX_FRAME_LENGTH", "4096")])?;
+
+    // Over the runtime limit
+    let sifis = mock.connect().await?;
+    let spec = DeviceSpec {
+        name: "x".repeat(8192),
+        room: None,
+        maintenance: Default::default(),
+        kind: DeviceKind::Lamp(LampState::default()),
+    };
+    let err = tokio::time::timeout(Duration::from_secs(5), sifis.add_device("big", spec))
+        .await?
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        sifis_api::Error::Io(_) | sifis_api::Error::Rpc(_)
+    ));
+
+    // Over the client limit
+    let sifis = mock.connect().await?;
+    for i in 0..100 {
+        let spec = DeviceSpec {
+            name: String::new(),
+            room: None,
+            maintenance: Default::default(),
+            kind: DeviceKind::Lamp(LampState::default()),
+        };
+        sifis.add_device(&format!("lamp_{i:03}"), spec).await?;
+    }
+    let sifis = Sifis::from_path_with_frame_limit(&mock.sock, 1024).await?;
+    let err = tokio::time::timeout(Duration::from_secs(5), sifis.snapshot())
+        .await?
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        sifis_api::Error::Io(_) | sifis_api::Error::Rpc(_)
+    ));
+
+    // The runtime keeps serving the connections within the limits
+    let sifis = mock.connect().await?;
+    assert!(sifis.lamp("big").await.is_err());
+    assert!(!sifis.snapshot().await?.lamps.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn record_replay() -> Result<()> {
+    let dir = tempdir()?;
+    let recording = dir.path().join("session.jsonl");
+
+    let mock = IsolatedMock::new()?;
+    let sifis = Sifis::from_path_with_recording(&mock.sock, &recording).await?;
+    let lamp = sifis.lamp("lamp1").await?;
+    assert!(!lamp.get_on_off().await?);
+    assert!(lamp.turn_on().await?.changed);
+    assert!(lamp.get_on_off().await?);
+    sifis.shutdown().await;
+    drop(mock);
+
+    let sifis = Sifis::replay(&recording)?;
+    let lamp = sifis.lamp("lamp1").await?;
+    assert!(!lamp.get_on_off().await?);
+    assert!(lamp.turn_on().await?.changed);
+    assert!(lamp.get_on_off().await?);
+    assert!(lamp.get_on_off().await?);
+
+    let err = lamp.set_brightness(50).await.unwrap_err();
+    assert_eq!(Some(ErrorCode::InvalidArgument), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn events_stream() -> Result<()> {
+    let mock = IsolatedMock::new()?;
+    let sifis = mock.connect().await?;
+    let mut events = sifis.events().await?;
+
+    sifis.lamp("lamp1").await?.turn_on().await?;
+
+    let Some(HomeEvent::Changed(event)) = events.next().await else {
+        panic!("a change is expected");
+    };
+    assert_eq!("lamp1", event.device_id);
+    assert_eq!(Some(std::process::id() as i32), event.peer_pid);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn initial_state_overrides() -> Result<()> {
+    let mock = IsolatedMock::with_env(&[(
+        "SIFIS_INIT",
+        "lamp1.on=true, lamp1.brightness=80,fridge1.target_temperature=-5,\
+         lamp2.brightness=high,lamp2.color=red,nope.on=true,lamp2,lamp2.on",
+    )])?;
+    let sifis = mock.connect().await?;
+
+    let lamp1 = sifis.lamp("lamp1").await?;
+    assert!(lamp1.get_on_off().await?);
+    assert_eq!(80, lamp1.get_brightness().await?);
+
+    let lamp2 = sifis.lamp("lamp2").await?;
+    assert!(!lamp2.get_on_off().await?);
+    assert_eq!(0, lamp2.get_brightness().await?);
+
+    let fridge = sifis.fridge("fridge1").await?;
+    assert_eq!(-5, fridge.target_temperature().await?);
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn lamp() -> Result<()> {
     let sifis = Mock::spawn().await?;
@@ -58,15 +448,75 @@ async fn lamp() -> Result<()> {
         assert!(!on);
         assert_eq!(0, brightness);
 
-        assert!(!lamp.turn_off().await?);
-        assert!(lamp.turn_on().await?);
-        assert_eq!(50, lamp.set_brightness(50).await?);
-        assert_eq!(100, lamp.set_brightness(100).await?);
+        let off = lamp.turn_off().await?;
+        assert!(!off.state);
+        assert!(!off.changed);
+        let on = lamp.turn_on().await?;
+        assert!(on.state);
+        assert!(on.changed);
+        assert_eq!(50, lamp.set_brightness(50).await?.applied);
+        assert_eq!(100, lamp.set_brightness(100).await?.applied);
     }
 
     Ok(())
 }
 
+#[tokio::test]
+async fn lamps_matching() -> Result<()> {
+    let sifis = Mock::spawn().await?;
+
+    let all: Vec<_> = sifis.lamps().await?.into_iter().map(|l| l.id).collect();
+    let matching: Vec<_> = sifis
+        .lamps_matching("lamp*")
+        .await?
+        .into_iter()
+        .map(|l| l.id)
+        .collect();
+    assert_eq!(vec!["lamp1", "lamp2"], all);
+    assert_eq!(all, matching);
+
+    let exact = sifis.lamps_matching("lamp1").await?;
+    assert_eq!(1, exact.len());
+    assert_eq!("lamp1", exact[0].id);
+
+    assert_eq!(all.len(), sifis.lamps_matching("lamp?").await?.len());
+    assert!(sifis.lamps_matching("sink*").await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn shared_client() -> Result<()> {
+    let sifis = Mock::spawn().await?;
+    let expected = sifis.lamps().await?.len();
+
+    let tasks: Vec<_> = (0..4)
+        .map(|_| {
+            let sifis = sifis.clone();
+            tokio::spawn(async move { sifis.lamps().await.map(|lamps| lamps.len()) })
+        })
+        .collect();
+
+    for task in tasks {
+        assert_eq!(expected, task.await??);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn owned_handles() -> Result<()> {
+    let sifis = Mock::spawn().await?;
+
+    let lamp = sifis.lamp("lamp1").await?;
+    drop(sifis);
+
+    let brightness = tokio::spawn(async move { lamp.get_brightness().await }).await??;
+    assert!(brightness <= 100);
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn sink() -> Result<()> {
     let sifis = Mock::spawn().await?;
@@ -85,10 +535,10 @@ async fn sink() -> Result<()> {
         assert_eq!(0, level);
         assert_eq!(20, temp);
 
-        assert_eq!(0, sink.set_flow(0).await?);
+        assert_eq!(0, sink.set_flow(0).await?.applied);
         assert!(sink.open_drain().await?);
         assert!(!sink.close_drain().await?);
-        assert_eq!(50, sink.set_flow(50).await?);
+        assert_eq!(50, sink.set_flow(50).await?.applied);
         assert_eq!(100, sink.set_temperature(100).await?);
     }
 
@@ -111,8 +561,8 @@ async fn door() -> Result<()> {
         assert!(!open);
         assert_eq!(DoorLockStatus::Unlocked, lock);
 
-        assert!(door.unlock().await?);
-        assert!(door.lock().await?);
+        assert_eq!(LockResult::AlreadyInState, door.unlock().await?);
+        assert_eq!(LockResult::Locked, door.lock().await?);
     }
 
     Ok(())
@@ -141,3 +591,449 @@ async fn fridge() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn summary() -> Result<()> {
+    let sifis = Mock::spawn().await?;
+
+    let summary = sifis.summary().await?;
+
+    for section in [
+        "== Lamps ==",
+        "== Sinks ==",
+        "== Doors ==",
+        "== Fridges ==",
+        "== Curtains ==",
+    ] {
+        assert!(summary.contains(section));
+    }
+    for lamp in sifis.lamps().await? {
+        assert!(summary.contains(lamp.id.as_str()));
+    }
+    for fridge in sifis.fridges().await? {
+        assert!(summary.contains(fridge.id.as_str()));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn all_off() -> Result<()> {
+    let mock = IsolatedMock::new()?;
+    let sifis = mock.connect().await?;
+
+    let lamps = sifis.lamps().await?;
+    let sinks = sifis.sinks().await?;
+
+    for lamp in &lamps {
+        lamp.turn_on().await?;
+    }
+    for sink in &sinks {
+        sink.set_flow(50).await?;
+        sink.close_drain().await?;
+    }
+
+    let report = sifis.all_off().await?;
+    assert_eq!(lamps.len() + sinks.len(), report.len());
+    assert!(report.iter().all(|(_, r)| r.is_ok()));
+
+    for lamp in &lamps {
+        assert!(!lamp.get_on_off().await?);
+    }
+    for sink in &sinks {
+        assert_eq!(0, sink.get_flow().await?);
+    }
+
+    // Already safe devices are reported as such
+    let report = sifis.all_off().await?;
+    assert_eq!(lamps.len() + sinks.len(), report.len());
+    assert!(report.iter().all(|(_, r)| r.is_ok()));
+
+    Ok(())
+}
+
+/// A sink flooding the home with scalding water and a lamp left on
+const DANGEROUS_CONF: &str = r#"
+    [devices.sink1]
+    name = "Kitchen sink"
+    [devices.sink1.kind.Sink]
+    flow = 80
+    temp = 70
+    level = 50
+    drain = false
+
+    [devices.lamp1]
+    name = "Desk lamp"
+    [devices.lamp1.kind.Lamp]
+    brightness = 100
+    on = true
+    "#;
+
+#[tokio::test]
+async fn safe_start() -> Result<()> {
+    let mock = IsolatedMock::with_config_env(DANGEROUS_CONF, &[("SIFIS_SAFE_START", "1")])?;
+    let sifis = mock.connect().await?;
+
+    let sink = sifis.sink("sink1").await?;
+    assert_eq!(0, sink.get_flow().await?);
+    assert!(sink.get_drain().await?);
+    // Only the flooding and scalding hazards are refused
+    assert!(sifis.lamp("lamp1").await?.get_on_off().await?);
+
+    Ok(())
+}
+
+#[test]
+fn safe_start_refused() -> Result<()> {
+    let dir = tempdir()?;
+    std::fs::write(dir.path().join("sifis-runtime.toml"), DANGEROUS_CONF)?;
+    let mut server = start_server(
+        dir.path(),
+        &dir.path().join("sifis.sock"),
+        &[("SIFIS_SAFE_START", "refuse")],
+    )?;
+
+    let status = server.try_wait()?;
+    if status.is_none() {
+        let _ = server.kill();
+    }
+    assert!(status.is_some_and(|status| !status.success()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn fridge_open_duration() -> Result<()> {
+    let mock = IsolatedMock::with_config(
+        r#"
+        [devices.open_fridge]
+        name = "Open fridge"
+        [devices.open_fridge.kind.Fridge]
+        open = true
+        temperature = 5
+        target_temperature = 4
+
+        [devices.closed_fridge]
+        name = "Closed fridge"
+        [devices.closed_fridge.kind.Fridge]
+        open = false
+        temperature = 5
+        target_temperature = 4
+        "#,
+    )?;
+    let sifis = mock.connect().await?;
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let open = sifis.fridge("open_fridge").await?;
+    assert!(open.open_duration().await?.unwrap() >= Duration::from_secs(1));
+
+    let closed = sifis.fridge("closed_fridge").await?;
+    assert_eq!(None, closed.open_duration().await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn fault_injection() -> Result<()> {
+    let mock = IsolatedMock::with_env(&[
+        ("SIFIS_FAULT_RATE", "1.0"),
+        ("SIFIS_FAULT_DELAY_MS", "200"),
+        ("SIFIS_SEED", "42"),
+    ])?;
+    let sifis = mock.connect().await?;
+
+    let lamp = sifis.lamp("lamp1").await?;
+
+    let start = Instant::now();
+    let err = lamp.get_on_off().await.unwrap_err();
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(Some(ErrorCode::NotFound), err.code());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn add_remove_device() -> Result<()> {
+    let mock = IsolatedMock::new()?;
+    let sifis = mock.connect().await?;
+
+    let spec = DeviceSpec {
+        name: "Desk lamp".to_string(),
+        room: None,
+        maintenance: Default::default(),
+        kind: DeviceKind::Lamp(LampState {
+            brightness: Lamp::brightness_to_hi(30),
+            on: true,
+            ..Default::default()
+        }),
+    };
+
+    sifis.add_device("lamp3", spec.clone()).await?;
+    let lamp = sifis.lamp("lamp3").await?;
+    assert!(lamp.get_on_off().await?);
+    assert_eq!(30, lamp.get_brightness().await?);
+
+    let err = sifis.add_device("lamp3", spec).await.unwrap_err();
+    assert_eq!(Some(ErrorCode::AlreadyExists), err.code());
+
+    sifis.remove_device("lamp3").await?;
+    assert!(sifis.lamp("lamp3").await.is_err());
+
+    let err = sifis.remove_device("lamp3").await.unwrap_err();
+    assert_eq!(Some(ErrorCode::NotFound), err.code());
+
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn tls() -> Result<()> {
+    use sifis_api::tls::rustls;
+    use std::sync::Arc;
+
+    let certs = tempdir()?;
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+    let cert_path = certs.path().join("cert.pem");
+    let key_path = certs.path().join("key.pem");
+    std::fs::write(&cert_path, cert.serialize_pem()?)?;
+    std::fs::write(&key_path, cert.serialize_private_key_pem())?;
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    let addr = format!("127.0.0.1:{port}");
+    let _mock = IsolatedMock::with_env(&[
+        ("SIFIS_TLS_ADDR", &addr),
+        ("SIFIS_TLS_CERT", cert_path.to_str().unwrap()),
+        ("SIFIS_TLS_KEY", key_path.to_str().unwrap()),
+    ])?;
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(&rustls::Certificate(cert.serialize_der()?))?;
+    let config = Arc::new(
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    );
+
+    let sifis = Sifis::from_tls(&format!("localhost:{port}"), config.clone()).await?;
+    let lamp = sifis.lamp("lamp1").await?;
+    assert!(lamp.turn_on().await?.state);
+
+    // The certificate is not valid for this name
+    let err = Sifis::from_tls(&format!("127.0.0.1:{port}"), config)
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, sifis_api::Error::Io(_)));
+
+    Ok(())
+}
+
+#[cfg(feature = "ws")]
+#[tokio::test]
+async fn ws_bridge() -> Result<()> {
+    use futures::SinkExt;
+    use serde_json::{json, Value};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+    use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+    type Ws = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn send(ws: &mut Ws, id: u64, method: &str, params: Value) -> Result<()> {
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        ws.send(Message::Text(request.to_string())).await?;
+        Ok(())
+    }
+    async fn answer(ws: &mut Ws) -> Result<Value> {
+        let message = ws.next().await.unwrap()?;
+        Ok(serde_json::from_str(message.to_text()?)?)
+    }
+
+    let mock = IsolatedMock::new()?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let allowed_origins = vec!["http://localhost:8080".to_owned()];
+    tokio::spawn(sifis_api::ws::serve(
+        listener,
+        mock.connect().await?,
+        allowed_origins,
+    ));
+
+    // The pages of the other origins are refused
+    let mut request = format!("ws://{addr}").into_client_request()?;
+    let origin = HeaderValue::from_static("http://evil.example");
+    request.headers_mut().insert("origin", origin);
+    match tokio_tungstenite::connect_async(request).await {
+        Err(WsError::Http(response)) => assert_eq!(StatusCode::FORBIDDEN, response.status()),
+        other => panic!("Expected a refusal, got {other:?}"),
+    }
+    let mut request = format!("ws://{addr}").into_client_request()?;
+    let origin = HeaderValue::from_static("http://localhost:8080");
+    request.headers_mut().insert("origin", origin);
+    assert!(tokio_tungstenite::connect_async(request).await.is_ok());
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{addr}")).await?;
+
+    send(&mut ws, 1, "turn_lamp_on", json!({ "id": "lamp1" })).await?;
+    assert_eq!(
+        json!({ "jsonrpc": "2.0", "id": 1, "result": { "state": true, "changed": true } }),
+        answer(&mut ws).await?
+    );
+    let sifis = mock.connect().await?;
+    assert!(sifis.lamp("lamp1").await?.get_on_off().await?);
+
+    // The errors of the runtime come along with the service::Error
+    send(&mut ws, 2, "get_lamp_on_off", json!({ "id": "nope" })).await?;
+    let error = &answer(&mut ws).await?["error"];
+    assert_eq!(json!(-32000), error["code"]);
+    assert_eq!(json!({ "NotFound": "nope" }), error["data"]);
+
+    send(&mut ws, 3, "make_coffee", json!({})).await?;
+    assert_eq!(json!(-32601), answer(&mut ws).await?["error"]["code"]);
+    // The setup of the home is left to the clients of the socket
+    send(&mut ws, 3, "remove_device", json!({ "id": "lamp1" })).await?;
+    assert_eq!(json!(-32601), answer(&mut ws).await?["error"]["code"]);
+    assert!(sifis.lamp("lamp1").await.is_ok());
+    send(&mut ws, 4, "set_lamp_brightness", json!({ "id": "lamp1" })).await?;
+    assert_eq!(json!(-32602), answer(&mut ws).await?["error"]["code"]);
+    ws.send(Message::Text("{".to_owned())).await?;
+    assert_eq!(json!(-32700), answer(&mut ws).await?["error"]["code"]);
+
+    // A long wait does not hold the requests sent after it
+    let wait = json!({ "id": "lamp1", "target": 40, "timeout_ms": 5000 });
+    send(&mut ws, 5, "wait_for_lamp_brightness", wait).await?;
+    let set = json!({ "id": "lamp1", "brightness": 40 });
+    send(&mut ws, 6, "set_lamp_brightness", set).await?;
+    let mut answers = vec![answer(&mut ws).await?, answer(&mut ws).await?];
+    answers.sort_by_key(|answer| answer["id"].as_u64());
+    assert_eq!(
+        vec![
+            json!({ "jsonrpc": "2.0", "id": 5, "result": true }),
+            json!({ "jsonrpc": "2.0", "id": 6, "result": { "requested": 40, "applied": 40 } }),
+        ],
+        answers
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn many_clients() -> Result<()> {
+    let sock = Mock::run();
+
+    // More than the usual limit of open file descriptors
+    for i in 0..2000 {
+        let sifis = Sifis::from_path(&sock).await?;
+        assert!(!sifis.lamps().await?.is_empty());
+
+        if i % 2 == 0 {
+            sifis.shutdown().await;
+        } else {
+            drop(sifis);
+            // Let the dispatch task notice it is not needed anymore
+            tokio::task::yield_now().await;
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn shutdown() -> Result<()> {
+    let sifis = Mock::spawn().await?;
+    let lamp = sifis.lamp("lamp1").await?;
+    let events = sifis.connection_events();
+
+    sifis.clone().shutdown().await;
+
+    let err = lamp.get_on_off().await.unwrap_err();
+    assert!(matches!(
+        err,
+        sifis_api::Error::Io(e) if e.kind() == std::io::ErrorKind::NotConnected
+    ));
+    assert_eq!(
+        vec![ConnectionState::Connected, ConnectionState::Disconnected],
+        events.collect::<Vec<_>>().await
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn idle_timeout() -> Result<()> {
+    let mock = IsolatedMock::with_env(&[("SIFIS_IDLE_TIMEOUT", "1")])?;
+    let idle = mock.connect().await?;
+    let mut events = idle.connection_events();
+    let busy = mock
+        .connect()
+        .await?
+        .with_keepalive(Duration::from_millis(200));
+    idle.ping().await?;
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(!idle.is_connected());
+    assert!(idle.lamps().await.is_err());
+    assert_eq!(Some(ConnectionState::Connected), events.next().await);
+    assert_eq!(Some(ConnectionState::Disconnected), events.next().await);
+
+    // The pings keep the connection alive
+    assert!(busy.is_connected());
+    busy.ping().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn dropped_connection() -> Result<()> {
+    let mock = IsolatedMock::with_env(&[("SIFIS_FAULT_DROP_RATE", "1")])?;
+    let sifis = mock.connect().await?;
+    let mut events = sifis.connection_events();
+    // Not a device call
+    sifis.ping().await?;
+
+    let lamp = sifis.lamp_unchecked("lamp1");
+    let err = tokio::time::timeout(Duration::from_secs(5), lamp.turn_on()).await?;
+    assert!(matches!(err, Err(sifis_api::Error::Io(_))));
+    assert_eq!(Some(ConnectionState::Connected), events.next().await);
+    assert_eq!(Some(ConnectionState::Disconnected), events.next().await);
+
+    // The runtime keeps serving the other connections
+    let other = mock.connect().await?;
+    other.ping().await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn dropped_connection_prioritized() -> Result<()> {
+    let mock = IsolatedMock::with_env(&[("SIFIS_FAULT_DROP_RATE", "1")])?;
+    let sifis = mock.connect().await?.with_priority(Priority::Urgent);
+
+    // Reported well before the deadline of the request
+    let lamp = sifis.lamp_unchecked("lamp1");
+    let err = tokio::time::timeout(Duration::from_secs(5), lamp.turn_on()).await?;
+    assert!(err.is_err());
+    assert!(sifis.ping().await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn keepalive() -> Result<()> {
+    let sifis = Mock::spawn()
+        .await?
+        .with_keepalive(Duration::from_millis(50));
+    sifis.ping().await?;
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(sifis.is_connected());
+    assert!(!sifis.lamps().await?.is_empty());
+
+    Ok(())
+}